@@ -5,8 +5,12 @@ mod prometheus;
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
-use ntp_daemon::{Config, ConfigUpdate, ObservableState};
+use ntp_daemon::{
+    config::ConfigFormat, AddPeer, Config, ConfigRequest, ConfigResponse, ConfigUpdate,
+    ObservableState, RemovePeer, RemoveServer, ServerArgs,
+};
 use prometheus::DisplayPrometheus;
+use tokio::io::AsyncWriteExt;
 
 #[derive(Parser)]
 #[command(version = "0.1.0", about = "Query and configure the ntpd-rs daemon")]
@@ -34,12 +38,32 @@ enum Command {
     Peers,
     #[command(about = "Information about the state of the daemon itself")]
     System,
+    #[command(about = "Packet counters and the most recent clients of every server")]
+    ServerStats,
     #[command(
         about = "Information about the state of the daemon and peers in the prometheus export format"
     )]
     Prometheus,
-    #[command(about = "Adjust configuration (e.g. loglevel) of the daemon")]
+    #[command(about = "The configuration the daemon currently runs with")]
+    ShowConfig {
+        /// Output format
+        #[arg(long, value_enum, default_value = "toml")]
+        format: ConfigFormat,
+    },
+    #[command(about = "Adjust configuration (e.g. loglevel or thresholds) of the daemon")]
     Config(ConfigUpdate),
+    #[command(about = "Start synchronizing with an additional peer")]
+    AddPeer(AddPeer),
+    #[command(about = "Stop synchronizing with a peer")]
+    RemovePeer(RemovePeer),
+    #[command(about = "List the peers as currently configured in the daemon")]
+    ListPeers,
+    #[command(about = "Start serving time on an additional address")]
+    AddServer(ServerArgs),
+    #[command(about = "Stop serving time on an address")]
+    RemoveServer(RemoveServer),
+    #[command(about = "Replace the allow and deny lists of a running server")]
+    UpdateServer(ServerArgs),
 }
 
 #[tokio::main]
@@ -71,8 +95,18 @@ async fn main() -> std::io::Result<()> {
     };
 
     let socket_path = match cli.command {
-        Command::Peers | Command::System | Command::Prometheus => &observation,
-        Command::Config(_) => &configuration,
+        Command::Peers
+        | Command::System
+        | Command::ServerStats
+        | Command::Prometheus
+        | Command::ShowConfig { .. } => &observation,
+        Command::Config(_)
+        | Command::AddPeer(_)
+        | Command::RemovePeer(_)
+        | Command::ListPeers
+        | Command::AddServer(_)
+        | Command::RemoveServer(_)
+        | Command::UpdateServer(_) => &configuration,
     };
 
     let mut stream = match tokio::net::UnixStream::connect(socket_path).await {
@@ -86,7 +120,9 @@ async fn main() -> std::io::Result<()> {
     let exit_code = match cli.command {
         Command::Peers => {
             let mut msg = Vec::with_capacity(16 * 1024);
-            match ntp_daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
+            match ntp_daemon::sockets::read_json_to_end::<ObservableState>(&mut stream, &mut msg)
+                .await
+            {
                 Ok(output) => {
                     // Unwrap here is fine as our serializer is infallible.
                     println!("{}", serde_json::to_string_pretty(&output.peers).unwrap());
@@ -102,7 +138,9 @@ async fn main() -> std::io::Result<()> {
         }
         Command::System => {
             let mut msg = Vec::with_capacity(16 * 1024);
-            match ntp_daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
+            match ntp_daemon::sockets::read_json_to_end::<ObservableState>(&mut stream, &mut msg)
+                .await
+            {
                 Ok(output) => {
                     // Unwrap here is fine as our serializer is infallible.
                     println!("{}", serde_json::to_string_pretty(&output.system).unwrap());
@@ -116,12 +154,30 @@ async fn main() -> std::io::Result<()> {
                 }
             }
         }
+        Command::ServerStats => {
+            let mut msg = Vec::with_capacity(16 * 1024);
+            match ntp_daemon::sockets::read_json_to_end::<ObservableState>(&mut stream, &mut msg)
+                .await
+            {
+                Ok(output) => {
+                    // Unwrap here is fine as our serializer is infallible.
+                    println!("{}", serde_json::to_string_pretty(&output.servers).unwrap());
+
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Failed to read state from observation socket: {}", e);
+
+                    1
+                }
+            }
+        }
         Command::Prometheus => {
             let mut stream = tokio::net::UnixStream::connect(observation).await?;
 
             let mut msg = Vec::with_capacity(16 * 1024);
             let output: ObservableState =
-                ntp_daemon::sockets::read_json(&mut stream, &mut msg).await?;
+                ntp_daemon::sockets::read_json_to_end(&mut stream, &mut msg).await?;
 
             println!("{}", prometheus::PEER_TYPE_HEADERS);
 
@@ -133,19 +189,115 @@ async fn main() -> std::io::Result<()> {
                 .system
                 .write_prometheus(&mut std::io::stdout(), &[])?;
 
+            if !output.servers.is_empty() {
+                println!("{}", prometheus::SERVER_TYPE_HEADERS);
+            }
+
+            for server in output.servers.iter() {
+                server.write_prometheus(&mut std::io::stdout(), &[])?;
+            }
+
             0
         }
-        Command::Config(config_update) => {
-            match ntp_daemon::sockets::write_json(&mut stream, &config_update).await {
-                Ok(_) => 0,
+        Command::ShowConfig { format } => {
+            let mut msg = Vec::with_capacity(16 * 1024);
+            match ntp_daemon::sockets::read_json_to_end::<ObservableState>(&mut stream, &mut msg)
+                .await
+            {
+                Ok(ObservableState {
+                    config: Some(config),
+                    ..
+                }) => match config.render(format) {
+                    Ok(rendered) => {
+                        println!("{}", rendered.trim_end());
+
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to write the configuration: {}", e);
+
+                        1
+                    }
+                },
+                Ok(_) => {
+                    eprintln!("The daemon does not report its configuration");
+
+                    1
+                }
                 Err(e) => {
-                    eprintln!("Failed to update configuration: {}", e);
+                    eprintln!("Failed to read state from observation socket: {}", e);
 
                     1
                 }
             }
         }
+        Command::Config(config_update) => {
+            configure(&mut stream, ConfigRequest::Update(config_update)).await
+        }
+        Command::AddPeer(add_peer) => match add_peer.to_config() {
+            Ok(peer) => configure(&mut stream, ConfigRequest::AddPeer(peer)).await,
+            Err(e) => {
+                eprintln!("Invalid peer: {}", e);
+
+                1
+            }
+        },
+        Command::RemovePeer(remove_peer) => {
+            configure(&mut stream, ConfigRequest::RemovePeer(remove_peer)).await
+        }
+        Command::ListPeers => configure(&mut stream, ConfigRequest::ListPeers).await,
+        Command::AddServer(server) => {
+            configure(&mut stream, ConfigRequest::AddServer(server)).await
+        }
+        Command::RemoveServer(remove_server) => {
+            configure(&mut stream, ConfigRequest::RemoveServer(remove_server)).await
+        }
+        Command::UpdateServer(server) => {
+            configure(&mut stream, ConfigRequest::UpdateServer(server)).await
+        }
     };
 
     std::process::exit(exit_code);
 }
+
+/// Send a request over the configuration socket, and report the reply. Returns the exit code.
+async fn configure(stream: &mut tokio::net::UnixStream, request: ConfigRequest) -> i32 {
+    // closing our side of the stream marks the end of the request
+    let sent = match ntp_daemon::sockets::write_json(stream, &request).await {
+        Ok(()) => stream.shutdown().await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = sent {
+        eprintln!("Failed to update configuration: {}", e);
+
+        return 1;
+    }
+
+    let mut msg = Vec::with_capacity(16 * 1024);
+    match ntp_daemon::sockets::read_json_to_end::<ConfigResponse>(stream, &mut msg).await {
+        Ok(ConfigResponse::Ok) => 0,
+        Ok(ConfigResponse::Updated(changes)) => {
+            // Unwrap here is fine as our serializer is infallible.
+            println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+
+            0
+        }
+        Ok(ConfigResponse::Peers(peers)) => {
+            // Unwrap here is fine as our serializer is infallible.
+            println!("{}", serde_json::to_string_pretty(&peers).unwrap());
+
+            0
+        }
+        Ok(ConfigResponse::Error(e)) => {
+            eprintln!("Failed to update configuration: {}", e);
+
+            1
+        }
+        Err(e) => {
+            eprintln!("Failed to read reply from configuration socket: {}", e);
+
+            1
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use ntp_daemon::ObservablePeerState;
-use ntp_proto::{PeerStatistics, SystemSnapshot};
+use ntp_daemon::{ObservablePeerState, ObservableServerState, ServerStatistics};
+use ntp_proto::{IgnoreStatistics, PeerStatistics, SystemSnapshot};
 
 pub(crate) trait DisplayPrometheus {
     fn write_prometheus(
@@ -84,6 +84,27 @@ impl DisplayPrometheus for SystemSnapshot {
             )?;
         }
 
+        writeln!(f, "# TYPE ntp_system_root_dispersion gauge")?;
+        self.format(
+            f,
+            "ntp_system",
+            "root_dispersion",
+            labels,
+            self.root_dispersion.to_seconds(),
+        )?;
+
+        writeln!(f, "# TYPE ntp_system_sync_state gauge")?;
+        let mut state_labels = labels.to_owned();
+        let sync_state = format!("{:?}", self.sync_state);
+        state_labels.push(("type", &sync_state));
+        self.format(
+            f,
+            "ntp_system",
+            "sync_state",
+            &state_labels,
+            self.sync_state as u8,
+        )?;
+
         writeln!(f, "# TYPE ntp_system_leap_indicator gauge")?;
         let mut labels = labels.to_owned();
         let leap_indicator = format!("{:?}", self.leap_indicator);
@@ -110,14 +131,19 @@ impl DisplayPrometheus for ObservablePeerState {
             ObservablePeerState::Nothing => (),
             ObservablePeerState::Observable {
                 statistics,
+                raw_offset,
                 reachability,
                 uptime,
                 poll_interval,
                 peer_id: _,
                 address,
+                ignored,
             } => {
                 let labels = &[("address", address.as_str())] as &[_];
                 statistics.write_prometheus(f, labels)?;
+                ignored.write_prometheus(f, labels)?;
+
+                self.format(f, "ntp_peer", "raw_offset", labels, raw_offset.to_seconds())?;
 
                 self.format(
                     f,
@@ -187,8 +213,104 @@ impl DisplayPrometheus for PeerStatistics {
     }
 }
 
+impl DisplayPrometheus for IgnoreStatistics {
+    fn write_prometheus(
+        &self,
+        f: &mut impl std::io::Write,
+        labels: &[(&str, &str)],
+    ) -> std::io::Result<()> {
+        let IgnoreStatistics {
+            invalid_mode,
+            invalid_version,
+            invalid_stratum,
+            invalid_packet_time,
+            kiss_ignore,
+            kiss_demobilize,
+            too_old,
+            excessive_delay,
+            excessive_delay_ratio,
+            stratum_limit,
+        } = self;
+
+        for (reason, count) in [
+            ("invalid_mode", invalid_mode),
+            ("invalid_version", invalid_version),
+            ("invalid_stratum", invalid_stratum),
+            ("invalid_packet_time", invalid_packet_time),
+            ("kiss_ignore", kiss_ignore),
+            ("kiss_demobilize", kiss_demobilize),
+            ("too_old", too_old),
+            ("excessive_delay", excessive_delay),
+            ("excessive_delay_ratio", excessive_delay_ratio),
+            ("stratum_limit", stratum_limit),
+        ] {
+            let labels: Vec<_> = labels.iter().copied().chain([("reason", reason)]).collect();
+            self.format(f, "ntp_peer", "ignored_packets_total", &labels, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DisplayPrometheus for ObservableServerState {
+    fn write_prometheus(
+        &self,
+        f: &mut impl std::io::Write,
+        _labels: &[(&str, &str)],
+    ) -> std::io::Result<()> {
+        let address = self.address.to_string();
+        let mut labels = vec![("address", address.as_str())];
+        if let Some(interface) = &self.interface {
+            labels.push(("interface", interface.as_str()));
+        }
+        let labels = labels.as_slice();
+        self.statistics.write_prometheus(f, labels)?;
+
+        self.format(f, "ntp_server", "clients", labels, self.clients.len())?;
+
+        writeln!(f)?;
+
+        Ok(())
+    }
+}
+
+impl DisplayPrometheus for ServerStatistics {
+    fn write_prometheus(
+        &self,
+        f: &mut impl std::io::Write,
+        labels: &[(&str, &str)],
+    ) -> std::io::Result<()> {
+        let ServerStatistics {
+            received,
+            served,
+            denied,
+            ignored,
+            rate_limited,
+            malformed,
+            version_mismatch,
+        } = self;
+
+        self.format(f, "ntp_server", "received_packets_total", labels, received)?;
+
+        for (result, count) in [
+            ("served", served),
+            ("denied", denied),
+            ("ignored", ignored),
+            ("rate_limited", rate_limited),
+            ("malformed", malformed),
+            ("version_mismatch", version_mismatch),
+        ] {
+            let labels: Vec<_> = labels.iter().copied().chain([("result", result)]).collect();
+            self.format(f, "ntp_server", "packets_total", &labels, count)?;
+        }
+
+        Ok(())
+    }
+}
+
 pub(crate) const PEER_TYPE_HEADERS: &str = r#" 
 # TYPE ntp_peer_offset gauge
+# TYPE ntp_peer_raw_offset gauge
 # TYPE ntp_peer_delay gauge
 # TYPE ntp_peer_dispersion gauge
 # TYPE ntp_peer_jitter gauge
@@ -196,4 +318,11 @@ pub(crate) const PEER_TYPE_HEADERS: &str = r#"
 # TYPE ntp_peer_reachability_unanswered_polls gauge
 # TYPE ntp_peer_uptime gauge
 # TYPE ntp_peer_poll_interval gauge
+# TYPE ntp_peer_ignored_packets_total counter
+"#;
+
+pub(crate) const SERVER_TYPE_HEADERS: &str = r#"
+# TYPE ntp_server_received_packets_total counter
+# TYPE ntp_server_packets_total counter
+# TYPE ntp_server_clients gauge
 "#;
@@ -1,17 +1,29 @@
+use crate::list_files::ListFiles;
+use crate::peer_manager::Peers;
 use crate::sockets::create_unix_socket;
 use crate::tracing::ReloadHandle;
-use ntp_proto::{NtpDuration, StepThreshold, SystemConfig};
+use ntp_proto::{
+    config_seconds, FrequencyTolerance, NtpClock, NtpDuration, PollInterval, StepThreshold,
+    SystemConfig, MIN_THRESHOLD_SECONDS,
+};
+use std::net::SocketAddr;
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UnixStream;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
-use tracing::error;
+use tracing::{error, warn};
 use tracing_subscriber::EnvFilter;
 
 use clap::Args;
 use serde::{Deserialize, Serialize};
 
-use super::ConfigureConfig;
+use super::{
+    format::LogFormat, subnet::IpSubnet, ConfigureConfig, FilterAction, LogSettings,
+    NormalizedAddress, PeerConfig, PeerHostMode, ServerConfig,
+};
+use crate::ipfilter::IpFilter;
 
 fn parse_env_filter(input: &str) -> Result<String, tracing_subscriber::filter::ParseError> {
     // run the parser to error on any invalid input
@@ -21,7 +33,7 @@ fn parse_env_filter(input: &str) -> Result<String, tracing_subscriber::filter::P
     Ok(input.to_string())
 }
 
-#[derive(Debug, Args, Serialize, Deserialize)]
+#[derive(Debug, Default, Args, Serialize, Deserialize)]
 pub struct ConfigUpdate {
     /// Change the log filter
     #[arg(long, value_parser = parse_env_filter)]
@@ -36,26 +48,558 @@ pub struct ConfigUpdate {
     /// during startup, use startup_panic_threshold
     #[arg(long)]
     pub panic_threshold: Option<f64>,
+
+    /// The panic threshold in seconds for jumps forward in time only (0 means no limit)
+    #[arg(long)]
+    pub panic_threshold_forward: Option<f64>,
+
+    /// The panic threshold in seconds for jumps backward in time only (0 means no limit)
+    #[arg(long)]
+    pub panic_threshold_backward: Option<f64>,
+
+    /// The maximum duration in seconds the system clock is allowed to change during startup
+    /// (0 means no limit)
+    #[arg(long)]
+    pub startup_panic_threshold: Option<f64>,
+
+    /// The startup panic threshold in seconds for jumps forward in time only
+    #[arg(long)]
+    pub startup_panic_threshold_forward: Option<f64>,
+
+    /// The startup panic threshold in seconds for jumps backward in time only
+    #[arg(long)]
+    pub startup_panic_threshold_backward: Option<f64>,
+
+    /// The maximum total duration in seconds of all steps after startup (0 means no limit)
+    #[arg(long)]
+    pub accumulated_threshold: Option<f64>,
+
+    /// Minimum number of peers that need to agree on the time before the clock is adjusted
+    #[arg(long)]
+    pub min_intersection_survivors: Option<usize>,
+
+    /// Number of survivors that the cluster algorithm tries to keep
+    #[arg(long)]
+    pub min_cluster_survivors: Option<usize>,
+
+    /// How much the time is allowed to drift (worst-case) per second, in parts per million
+    #[arg(long)]
+    pub frequency_tolerance: Option<u32>,
+
+    /// Root distance in seconds above which a peer is not used for synchronization
+    #[arg(long)]
+    pub distance_threshold: Option<f64>,
+
+    /// Duration in seconds before a spike is considered a real change in time
+    #[arg(long)]
+    pub spike_threshold: Option<f64>,
+
+    /// Duration in seconds used to measure the frequency error of the system clock on startup
+    #[arg(long)]
+    pub frequency_measurement_period: Option<f64>,
+
+    /// Lower limit of the system poll interval, as a power of two in seconds
+    #[arg(long, allow_negative_numbers = true)]
+    pub min_poll: Option<i8>,
+
+    /// Upper limit of the system poll interval, as a power of two in seconds
+    #[arg(long, allow_negative_numbers = true)]
+    pub max_poll: Option<i8>,
+
+    /// Highest stratum of a peer that we are willing to synchronize to
+    #[arg(long)]
+    pub max_stratum: Option<u8>,
+
+    /// Duration in seconds after the last clock update after which we stop claiming to be synchronized
+    #[arg(long)]
+    pub max_holdover: Option<f64>,
+}
+
+/// A setting that was changed by a `ConfigUpdate`
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub field: String,
+    /// `None` when the previous value is not known
+    pub old: Option<String>,
+    pub new: String,
+}
+
+impl ConfigChange {
+    fn new(field: &str, old: String, new: String) -> Self {
+        ConfigChange {
+            field: field.to_string(),
+            old: Some(old),
+            new,
+        }
+    }
+}
+
+/// A threshold of zero means there is no limit
+fn parse_threshold(field: &str, seconds: f64) -> Result<Option<NtpDuration>, String> {
+    if seconds == 0.0 {
+        Ok(None)
+    } else if seconds.is_finite() && seconds >= MIN_THRESHOLD_SECONDS {
+        Ok(Some(NtpDuration::from_seconds(seconds)))
+    } else {
+        Err(format!(
+            "{field} must be 0 (no limit) or at least {MIN_THRESHOLD_SECONDS} seconds"
+        ))
+    }
+}
+
+fn parse_duration(field: &str, seconds: f64) -> Result<NtpDuration, String> {
+    if seconds.is_finite() && seconds >= 0.0 {
+        Ok(NtpDuration::from_seconds(seconds))
+    } else {
+        Err(format!("{field} must be a non-negative number of seconds"))
+    }
+}
+
+fn parse_poll(field: &str, log: i8) -> Result<PollInterval, String> {
+    PollInterval::from_log(log).ok_or_else(|| {
+        format!(
+            "{field} must be between {} and {}",
+            PollInterval::SHORTEST.as_log(),
+            PollInterval::LONGEST.as_log()
+        )
+    })
+}
+
+/// Seconds, rounded to microseconds to hide the imprecision of the conversion to f64
+fn format_seconds(duration: NtpDuration) -> String {
+    config_seconds(duration).to_string()
+}
+
+fn format_threshold(threshold: Option<NtpDuration>) -> String {
+    match threshold {
+        Some(duration) => format_seconds(duration),
+        None => "none".to_string(),
+    }
+}
+
+impl ConfigUpdate {
+    /// Compute the system config with this update applied. Either every value is valid and the new
+    /// config is returned, or the update is rejected as a whole.
+    fn apply(&self, config: &SystemConfig) -> Result<SystemConfig, String> {
+        let mut new = *config;
+
+        if let Some(seconds) = self.panic_threshold {
+            let threshold = parse_threshold("panic-threshold", seconds)?;
+            new.panic_threshold = StepThreshold {
+                forward: threshold,
+                backward: threshold,
+            };
+        }
+        if let Some(seconds) = self.panic_threshold_forward {
+            new.panic_threshold.forward = parse_threshold("panic-threshold-forward", seconds)?;
+        }
+        if let Some(seconds) = self.panic_threshold_backward {
+            new.panic_threshold.backward = parse_threshold("panic-threshold-backward", seconds)?;
+        }
+
+        if let Some(seconds) = self.startup_panic_threshold {
+            let threshold = parse_threshold("startup-panic-threshold", seconds)?;
+            new.startup_panic_threshold = StepThreshold {
+                forward: threshold,
+                backward: threshold,
+            };
+        }
+        if let Some(seconds) = self.startup_panic_threshold_forward {
+            new.startup_panic_threshold.forward =
+                parse_threshold("startup-panic-threshold-forward", seconds)?;
+        }
+        if let Some(seconds) = self.startup_panic_threshold_backward {
+            new.startup_panic_threshold.backward =
+                parse_threshold("startup-panic-threshold-backward", seconds)?;
+        }
+
+        if let Some(seconds) = self.accumulated_threshold {
+            new.accumulated_threshold = parse_threshold("accumulated-threshold", seconds)?;
+        }
+
+        if let Some(survivors) = self.min_intersection_survivors {
+            if survivors == 0 {
+                return Err("min-intersection-survivors must be at least 1".into());
+            }
+            new.min_intersection_survivors = survivors;
+        }
+
+        if let Some(survivors) = self.min_cluster_survivors {
+            if survivors == 0 {
+                return Err("min-cluster-survivors must be at least 1".into());
+            }
+            new.min_cluster_survivors = survivors;
+        }
+
+        if let Some(ppm) = self.frequency_tolerance {
+            new.frequency_tolerance = FrequencyTolerance::ppm(ppm);
+        }
+
+        if let Some(seconds) = self.distance_threshold {
+            new.distance_threshold = parse_duration("distance-threshold", seconds)?;
+            if new.distance_threshold == NtpDuration::ZERO {
+                return Err("distance-threshold must be positive".into());
+            }
+        }
+
+        if let Some(seconds) = self.spike_threshold {
+            new.spike_threshold = parse_duration("spike-threshold", seconds)?;
+        }
+
+        if let Some(seconds) = self.frequency_measurement_period {
+            new.frequency_measurement_period =
+                parse_duration("frequency-measurement-period", seconds)?;
+        }
+
+        if let Some(log) = self.min_poll {
+            new.poll_limits.min = parse_poll("min-poll", log)?;
+        }
+        if let Some(log) = self.max_poll {
+            new.poll_limits.max = parse_poll("max-poll", log)?;
+        }
+        if new.poll_limits.min > new.poll_limits.max {
+            return Err("minimum poll interval is larger than the maximum".into());
+        }
+
+        if let Some(stratum) = self.max_stratum {
+            if !(1..=15).contains(&stratum) {
+                return Err("max-stratum must be between 1 and 15".into());
+            }
+            new.max_stratum = stratum;
+        }
+
+        if let Some(seconds) = self.max_holdover {
+            new.max_holdover = parse_duration("max-holdover", seconds)?;
+        }
+
+        Ok(new)
+    }
+}
+
+/// All fields that differ between two system configs
+pub(crate) fn system_config_changes(old: &SystemConfig, new: &SystemConfig) -> Vec<ConfigChange> {
+    let fields = [
+        (
+            "panic-threshold.forward",
+            format_threshold(old.panic_threshold.forward),
+            format_threshold(new.panic_threshold.forward),
+        ),
+        (
+            "panic-threshold.backward",
+            format_threshold(old.panic_threshold.backward),
+            format_threshold(new.panic_threshold.backward),
+        ),
+        (
+            "startup-panic-threshold.forward",
+            format_threshold(old.startup_panic_threshold.forward),
+            format_threshold(new.startup_panic_threshold.forward),
+        ),
+        (
+            "startup-panic-threshold.backward",
+            format_threshold(old.startup_panic_threshold.backward),
+            format_threshold(new.startup_panic_threshold.backward),
+        ),
+        (
+            "accumulated-threshold",
+            format_threshold(old.accumulated_threshold),
+            format_threshold(new.accumulated_threshold),
+        ),
+        (
+            "min-intersection-survivors",
+            old.min_intersection_survivors.to_string(),
+            new.min_intersection_survivors.to_string(),
+        ),
+        (
+            "min-cluster-survivors",
+            old.min_cluster_survivors.to_string(),
+            new.min_cluster_survivors.to_string(),
+        ),
+        (
+            "frequency-tolerance",
+            old.frequency_tolerance.to_ppm().to_string(),
+            new.frequency_tolerance.to_ppm().to_string(),
+        ),
+        (
+            "distance-threshold",
+            format_seconds(old.distance_threshold),
+            format_seconds(new.distance_threshold),
+        ),
+        (
+            "spike-threshold",
+            format_seconds(old.spike_threshold),
+            format_seconds(new.spike_threshold),
+        ),
+        (
+            "frequency-measurement-period",
+            format_seconds(old.frequency_measurement_period),
+            format_seconds(new.frequency_measurement_period),
+        ),
+        (
+            "poll-limits.min",
+            old.poll_limits.min.as_log().to_string(),
+            new.poll_limits.min.as_log().to_string(),
+        ),
+        (
+            "poll-limits.max",
+            old.poll_limits.max.as_log().to_string(),
+            new.poll_limits.max.as_log().to_string(),
+        ),
+        (
+            "max-stratum",
+            old.max_stratum.to_string(),
+            new.max_stratum.to_string(),
+        ),
+        (
+            "max-holdover",
+            format_seconds(old.max_holdover),
+            format_seconds(new.max_holdover),
+        ),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| ConfigChange::new(field, old, new))
+        .collect()
+}
+
+#[derive(Debug, Default, Args)]
+pub struct AddPeer {
+    /// Address of the peer, the port defaults to 123
+    pub addr: String,
+
+    /// Treat the address as a pool of servers
+    #[arg(long)]
+    pub pool: bool,
+
+    /// Maximum number of peers to use from the pool
+    #[arg(long, requires = "pool")]
+    pub max_peers: Option<usize>,
+
+    /// Never poll the peer more often than once every 2^MIN_POLL seconds
+    #[arg(long, allow_hyphen_values = true)]
+    pub min_poll: Option<i8>,
+
+    /// Never poll the peer less often than once every 2^MAX_POLL seconds
+    #[arg(long, allow_hyphen_values = true)]
+    pub max_poll: Option<i8>,
+
+    /// Send a quick burst of requests to speed up initial synchronization
+    #[arg(long)]
+    pub iburst: bool,
+
+    /// Send several requests on every poll, and only use the one with the lowest delay
+    #[arg(long)]
+    pub burst: bool,
+
+    /// Use this peer directly when it survives clock selection
+    #[arg(long)]
+    pub prefer: bool,
+
+    /// Only measure this peer, never use it to synchronize the clock
+    #[arg(long)]
+    pub noselect: bool,
+
+    /// Never discard this peer as a falseticker
+    #[arg(long)]
+    pub trust: bool,
+
+    /// Discard samples with a larger round-trip delay (e.g. 0.05 or "50ms")
+    #[arg(long)]
+    pub max_delay: Option<String>,
+
+    /// Discard samples with a delay above this multiple of the smallest recent delay
+    #[arg(long)]
+    pub max_delay_ratio: Option<f64>,
+
+    /// Fixed correction, added to every offset measured for this peer (e.g. "-1.5ms")
+    #[arg(long, allow_hyphen_values = true)]
+    pub offset: Option<String>,
+
+    /// Fraction of the round-trip delay spent on the way to the peer
+    #[arg(long)]
+    pub asymmetry: Option<f64>,
+}
+
+impl AddPeer {
+    /// The peer as it would be configured in the configuration file, so that both accept the
+    /// same options and check them in the same way
+    pub fn to_config(&self) -> Result<PeerConfig, String> {
+        let mut table = serde_json::Map::new();
+        let mut insert = |key: &str, value: serde_json::Value| {
+            table.insert(key.to_string(), value);
+        };
+
+        insert("addr", self.addr.clone().into());
+        if self.pool {
+            insert("mode", "pool".into());
+        }
+        if let Some(max_peers) = self.max_peers {
+            insert("max_peers", max_peers.into());
+        }
+        if let Some(min_poll) = self.min_poll {
+            insert("min-poll", min_poll.into());
+        }
+        if let Some(max_poll) = self.max_poll {
+            insert("max-poll", max_poll.into());
+        }
+        insert("iburst", self.iburst.into());
+        insert("burst", self.burst.into());
+        insert("prefer", self.prefer.into());
+        insert("noselect", self.noselect.into());
+        insert("trust", self.trust.into());
+        if let Some(max_delay) = &self.max_delay {
+            insert("max-delay", max_delay.clone().into());
+        }
+        if let Some(max_delay_ratio) = self.max_delay_ratio {
+            insert("max-delay-ratio", max_delay_ratio.into());
+        }
+        if let Some(offset) = &self.offset {
+            insert("offset", offset.clone().into());
+        }
+        if let Some(asymmetry) = self.asymmetry {
+            insert("asymmetry", asymmetry.into());
+        }
+
+        PeerConfig::deserialize(&serde_json::Value::Object(table)).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct RemovePeer {
+    /// Address of the peer as it was configured, the port defaults to 123
+    pub addr: String,
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct ServerArgs {
+    /// Address to listen on
+    pub addr: SocketAddr,
+
+    /// Only serve clients in these subnets (e.g. 192.168.0.0/16), may be given multiple times
+    #[arg(long, requires = "allowlist_action")]
+    pub allowlist: Option<Vec<IpSubnet>>,
+
+    /// What to do with clients outside the allowlist
+    #[arg(long, value_enum, requires = "allowlist")]
+    pub allowlist_action: Option<FilterAction>,
+
+    /// Do not serve clients in these subnets, may be given multiple times
+    #[arg(long, requires = "denylist_action")]
+    pub denylist: Option<Vec<IpSubnet>>,
+
+    /// What to do with clients in the denylist
+    #[arg(long, value_enum, requires = "denylist")]
+    pub denylist_action: Option<FilterAction>,
+}
+
+impl From<ServerArgs> for ServerConfig {
+    fn from(args: ServerArgs) -> Self {
+        // same defaults as for a server in the configuration file
+        let (allowlist, allowlist_action) = match args.allowlist {
+            Some(list) => (
+                IpFilter::new(&list),
+                args.allowlist_action.unwrap_or(FilterAction::Ignore),
+            ),
+            None => (IpFilter::all(), FilterAction::Ignore),
+        };
+        let (denylist, denylist_action) = match args.denylist {
+            Some(list) => (
+                IpFilter::new(&list),
+                args.denylist_action.unwrap_or(FilterAction::Ignore),
+            ),
+            None => (IpFilter::none(), FilterAction::Ignore),
+        };
+
+        ServerConfig {
+            addr: args.addr,
+            denylist,
+            denylist_action,
+            allowlist,
+            allowlist_action,
+            ..ServerConfig::from_addr(args.addr)
+        }
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct RemoveServer {
+    /// Address the server listens on
+    pub addr: SocketAddr,
+}
+
+/// A single operation sent over the configuration socket
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ConfigRequest {
+    Update(ConfigUpdate),
+    AddPeer(PeerConfig),
+    RemovePeer(RemovePeer),
+    ListPeers,
+    AddServer(ServerArgs),
+    RemoveServer(RemoveServer),
+    UpdateServer(ServerArgs),
+}
+
+/// A peer as currently configured in the daemon
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfiguredPeer {
+    pub address: String,
+    pub resolved: SocketAddr,
+    pub mode: PeerHostMode,
+}
+
+/// The reply of the daemon to a `ConfigRequest`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ConfigResponse {
+    Ok,
+    Updated(Vec<ConfigChange>),
+    Peers(Vec<ConfiguredPeer>),
+    Error(String),
 }
 
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest request that is accepted, which leaves room for long allow and deny lists
+const MAX_REQUEST_SIZE: u64 = 16 * 1024 * 1024;
+
 // Deal with reloading not being possible during testing.
 pub trait LogReloader {
     fn update_log(&self, f: EnvFilter);
+    fn update_format(&self, format: LogFormat);
 }
 
 impl LogReloader for ReloadHandle {
     fn update_log(&self, f: EnvFilter) {
         self.modify(|l| *l.filter_mut() = f).unwrap();
     }
+
+    fn update_format(&self, format: LogFormat) {
+        self.modify(|l| {
+            *l.inner_mut() = tracing_subscriber::fmt::layer()
+                .fmt_fields(format.get_format_fields())
+                .event_format(format);
+        })
+        .unwrap();
+    }
 }
 
-pub async fn spawn<H: LogReloader + Send + 'static>(
+pub async fn spawn<H: LogReloader + Send + Sync + 'static, C: NtpClock + Sync>(
     config: ConfigureConfig,
     system_config: Arc<RwLock<SystemConfig>>,
+    log_settings: Arc<RwLock<LogSettings>>,
+    peers: Arc<RwLock<Peers<C>>>,
     log_reload_handle: H,
 ) -> JoinHandle<std::io::Result<()>> {
     tokio::spawn(async move {
-        let result = dynamic_configuration(config, system_config, log_reload_handle).await;
+        let result = dynamic_configuration(
+            config,
+            system_config,
+            log_settings,
+            peers,
+            log_reload_handle,
+        )
+        .await;
         if let Err(ref e) = result {
             error!("Abnormal termination of dynamic configurator: {}", e);
         }
@@ -63,9 +607,11 @@ pub async fn spawn<H: LogReloader + Send + 'static>(
     })
 }
 
-async fn dynamic_configuration<H: LogReloader>(
+async fn dynamic_configuration<H: LogReloader + Send + Sync + 'static, C: NtpClock + Sync>(
     config: ConfigureConfig,
     system_config: Arc<RwLock<SystemConfig>>,
+    log_settings: Arc<RwLock<LogSettings>>,
+    peers: Arc<RwLock<Peers<C>>>,
     log_reload_handle: H,
 ) -> std::io::Result<()> {
     let path = match config.path {
@@ -81,27 +627,201 @@ async fn dynamic_configuration<H: LogReloader>(
     let permissions: std::fs::Permissions = PermissionsExt::from_mode(config.mode);
     std::fs::set_permissions(&path, permissions)?;
 
-    let mut msg = Vec::with_capacity(16 * 1024);
+    let log_reload_handle = Arc::new(log_reload_handle);
 
     loop {
-        let (mut stream, _addr) = peers_listener.accept().await?;
+        let (stream, _addr) = peers_listener.accept().await?;
 
-        let operation: ConfigUpdate = crate::sockets::read_json(&mut stream, &mut msg).await?;
-
-        tracing::info!(?operation, "dynamic config update");
+        // every client gets its own task, so a slow client or DNS lookup does not hold up others
+        let system_config = system_config.clone();
+        let log_settings = log_settings.clone();
+        let peers = peers.clone();
+        let log_reload_handle = log_reload_handle.clone();
+        tokio::spawn(async move {
+            handle_connection(
+                stream,
+                &system_config,
+                &log_settings,
+                &peers,
+                &*log_reload_handle,
+            )
+            .await
+        });
+    }
+}
 
-        if let Some(filter) = operation.log_filter {
-            log_reload_handle.update_log(EnvFilter::new(filter));
+async fn handle_connection<H: LogReloader, C: NtpClock + Sync>(
+    mut stream: UnixStream,
+    system_config: &RwLock<SystemConfig>,
+    log_settings: &RwLock<LogSettings>,
+    peers: &RwLock<Peers<C>>,
+    log_reload_handle: &H,
+) {
+    // the client closes its side of the stream after the request, so requests of any size
+    // can be read completely
+    let mut msg = Vec::with_capacity(16 * 1024);
+    let read = crate::sockets::read_json_to_end_limited(&mut stream, &mut msg, MAX_REQUEST_SIZE);
+    let request: ConfigRequest = match tokio::time::timeout(REQUEST_TIMEOUT, read).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(e)) => {
+            warn!(error = ?e, "could not read configuration request");
+            if e.kind() == std::io::ErrorKind::InvalidData {
+                let response = ConfigResponse::Error(format!("invalid request: {e}"));
+                crate::sockets::write_json(&mut stream, &response)
+                    .await
+                    .ok();
+            }
+            return;
+        }
+        Err(_) => {
+            warn!("configuration client did not finish its request in time");
+            let response = ConfigResponse::Error("request timed out".to_string());
+            crate::sockets::write_json(&mut stream, &response)
+                .await
+                .ok();
+            return;
         }
+    };
+
+    tracing::info!(?request, "dynamic config request");
+
+    let response = match request {
+        ConfigRequest::Update(operation) => {
+            let mut config = system_config.write().await;
+
+            match operation.apply(&config) {
+                Ok(new_config) => {
+                    let mut changes = system_config_changes(&config, &new_config);
+                    *config = new_config;
 
-        let mut config = system_config.write().await;
+                    if let Some(filter) = operation.log_filter {
+                        log_reload_handle.update_log(EnvFilter::new(&filter));
+                        let old = log_settings.write().await.filter.replace(filter.clone());
+                        changes.push(ConfigChange {
+                            field: "log-filter".to_string(),
+                            // without a filter in the configuration, the default is used
+                            old: Some(old.unwrap_or_else(|| "info".to_string())),
+                            new: filter,
+                        });
+                    }
 
-        if let Some(panic_threshold) = operation.panic_threshold {
-            config.panic_threshold = StepThreshold {
-                forward: Some(NtpDuration::from_seconds(panic_threshold)),
-                backward: Some(NtpDuration::from_seconds(panic_threshold)),
+                    ConfigResponse::Updated(changes)
+                }
+                Err(e) => ConfigResponse::Error(e),
+            }
+        }
+        ConfigRequest::AddPeer(add_peer) => add_peer_request(peers, add_peer).await,
+        ConfigRequest::RemovePeer(remove_peer) => {
+            match NormalizedAddress::from_string(remove_peer.addr) {
+                Ok(addr) => match peers.write().await.remove_peer(&addr) {
+                    0 => ConfigResponse::Error(format!("no peer with address {}", addr.as_str())),
+                    _ => ConfigResponse::Ok,
+                },
+                Err(e) => ConfigResponse::Error(format!("invalid peer address: {e}")),
+            }
+        }
+        ConfigRequest::ListPeers => {
+            let peers = peers.read().await;
+            let list = peers
+                .peer_configs()
+                .map(|(config, resolved)| ConfiguredPeer {
+                    address: config.addr().as_str().to_string(),
+                    resolved,
+                    mode: match config {
+                        PeerConfig::Standard(_) => PeerHostMode::Server,
+                        PeerConfig::Pool(_) => PeerHostMode::Pool,
+                    },
+                })
+                .collect();
+
+            ConfigResponse::Peers(list)
+        }
+        ConfigRequest::AddServer(args) => {
+            let addr = args.addr;
+            let config = ServerConfig::from(args);
+            // read the list files before taking the lock, they can be long
+            let files = ListFiles::read(&config).await;
+            match peers.write().await.add_server(config, files) {
+                true => ConfigResponse::Ok,
+                false => ConfigResponse::Error(format!("already serving on {addr}")),
+            }
+        }
+        ConfigRequest::RemoveServer(RemoveServer { addr }) => {
+            match peers.write().await.remove_server(addr, None) {
+                true => ConfigResponse::Ok,
+                false => ConfigResponse::Error(format!("no server on {addr}")),
+            }
+        }
+        ConfigRequest::UpdateServer(args) => {
+            let addr = args.addr;
+            let (has_allowlist, has_denylist) = (args.allowlist.is_some(), args.denylist.is_some());
+            let mut peers = peers.write().await;
+            // only the allow and deny lists can be changed here, keep the other settings
+            let update = ServerConfig::from(args);
+            // servers on an interface are only managed through the configuration file
+            let running = peers
+                .server_configs()
+                .find(|config| config.addr == addr && config.interface.is_none());
+            let config = match running {
+                Some(running) => {
+                    let mut config = ServerConfig {
+                        allowlist: update.allowlist,
+                        allowlist_action: update.allowlist_action,
+                        denylist: update.denylist,
+                        denylist_action: update.denylist_action,
+                        ..running.clone()
+                    };
+                    // the list files stay, without a list they alone decide
+                    if running.allowlist_file.is_some() && !has_allowlist {
+                        config.allowlist = IpFilter::none();
+                        config.allowlist_action = running.allowlist_action;
+                    }
+                    if running.denylist_file.is_some() && !has_denylist {
+                        config.denylist_action = running.denylist_action;
+                    }
+                    config
+                }
+                None => update,
             };
+            match peers.update_server(config) {
+                true => ConfigResponse::Ok,
+                false => ConfigResponse::Error(format!("no server on {addr}")),
+            }
         }
+    };
+
+    if let Err(e) = crate::sockets::write_json(&mut stream, &response).await {
+        warn!(error = ?e, "could not send reply to configuration client");
+    }
+}
+
+async fn add_peer_request<C: NtpClock + Sync>(
+    peers: &RwLock<Peers<C>>,
+    config: PeerConfig,
+) -> ConfigResponse {
+    // resolve before taking the lock, the system should not wait on DNS
+    let resolved = match resolve_peer(&config).await {
+        Ok(resolved) => resolved,
+        Err(e) => return ConfigResponse::Error(e),
+    };
+
+    peers.write().await.add_resolved_peer(config, resolved);
+
+    ConfigResponse::Ok
+}
+
+/// Look up the address of a peer once, without the retries done at startup
+pub(crate) async fn resolve_peer(config: &PeerConfig) -> Result<SocketAddr, String> {
+    match config.addr().lookup_host().await.map(|mut i| i.next()) {
+        Ok(Some(resolved)) => Ok(resolved),
+        Ok(None) => Err(format!(
+            "could not resolve peer address {}",
+            config.addr().as_str()
+        )),
+        Err(e) => Err(format!(
+            "could not resolve peer address {}: {e}",
+            config.addr().as_str()
+        )),
     }
 }
 
@@ -109,13 +829,67 @@ async fn dynamic_configuration<H: LogReloader>(
 mod tests {
     use std::time::Duration;
 
-    use crate::sockets::write_json;
+    use ntp_proto::{NtpLeapIndicator, NtpTimestamp, PollInterval};
+
+    use crate::peer::{PeerChannels, ResetEpoch};
+    use crate::peer_manager::PeerStatus;
+    use tokio::io::AsyncWriteExt;
+
+    use crate::sockets::{read_json_to_end, write_json};
 
     use super::*;
 
     struct TestLogReloader {}
     impl LogReloader for TestLogReloader {
         fn update_log(&self, _f: EnvFilter) {}
+        fn update_format(&self, _format: LogFormat) {}
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_freq(&self, _freq: f64) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn update_clock(
+            &self,
+            _offset: NtpDuration,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+            _poll_interval: PollInterval,
+            _leap_status: NtpLeapIndicator,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    async fn request(path: &std::path::Path, request: &ConfigRequest) -> ConfigResponse {
+        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        write_json(&mut stream, request).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut msg = Vec::with_capacity(16 * 1024);
+        read_json_to_end(&mut stream, &mut msg).await.unwrap()
+    }
+
+    fn test_peers() -> Arc<RwLock<Peers<TestClock>>> {
+        Arc::new(RwLock::new(Peers::from_statuslist(
+            &[] as &[PeerStatus],
+            &[],
+            TestClock {},
+        )))
     }
 
     #[tokio::test]
@@ -129,31 +903,376 @@ mod tests {
             mode: 0o700,
         };
 
-        let handle = spawn(config, system_config, TestLogReloader {}).await;
+        let log_settings = Arc::new(RwLock::new(LogSettings::default()));
+        let handle = spawn(
+            config,
+            system_config,
+            log_settings.clone(),
+            test_peers(),
+            TestLogReloader {},
+        )
+        .await;
 
         // Ensure client has started.
         tokio::time::sleep(Duration::from_millis(10)).await;
 
-        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        // a client that never finishes its request does not hold up the others
+        let mut stuck = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stuck.write_all(b"{\"Update\"").await.unwrap();
 
-        write_json(
-            &mut stream,
-            &ConfigUpdate {
-                log_filter: Some("info".into()),
+        let response = request(
+            &path,
+            &ConfigRequest::Update(ConfigUpdate {
+                log_filter: Some("debug".into()),
                 panic_threshold: Some(600.),
-            },
+                ..Default::default()
+            }),
         )
-        .await
-        .unwrap();
+        .await;
+        match response {
+            ConfigResponse::Updated(changes) => {
+                assert!(changes.contains(&ConfigChange::new(
+                    "panic-threshold.forward",
+                    "1000".into(),
+                    "600".into()
+                )));
+                assert!(changes.contains(&ConfigChange::new(
+                    "log-filter",
+                    "info".into(),
+                    "debug".into()
+                )));
+            }
+            other => panic!("unexpected response {other:?}"),
+        }
 
-        // Ensure message is handled.
-        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the observer reports the new filter
+        assert_eq!(log_settings.read().await.filter.as_deref(), Some("debug"));
+
+        assert_eq!(
+            system_config_test.read().await.panic_threshold.forward,
+            Some(NtpDuration::from_seconds(600.))
+        );
+
+        // an invalid update is rejected as a whole
+        let response = request(
+            &path,
+            &ConfigRequest::Update(ConfigUpdate {
+                panic_threshold: Some(300.),
+                max_stratum: Some(16),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Error(_)));
 
         assert_eq!(
             system_config_test.read().await.panic_threshold.forward,
             Some(NtpDuration::from_seconds(600.))
         );
 
+        drop(stuck);
+        handle.abort();
+    }
+
+    #[test]
+    fn test_config_update_apply() {
+        let config = SystemConfig::default();
+
+        let update = ConfigUpdate {
+            panic_threshold_forward: Some(0.),
+            startup_panic_threshold_backward: Some(60.),
+            accumulated_threshold: Some(3600.),
+            min_intersection_survivors: Some(1),
+            frequency_tolerance: Some(100),
+            min_poll: Some(-2),
+            ..Default::default()
+        };
+        let new = update.apply(&config).unwrap();
+        assert_eq!(new.panic_threshold.forward, None);
+        assert_eq!(
+            new.panic_threshold.backward,
+            config.panic_threshold.backward
+        );
+        assert_eq!(
+            new.startup_panic_threshold.backward,
+            Some(NtpDuration::from_seconds(60.))
+        );
+        assert_eq!(
+            new.accumulated_threshold,
+            Some(NtpDuration::from_seconds(3600.))
+        );
+        assert_eq!(new.min_intersection_survivors, 1);
+        assert_eq!(new.poll_limits.min, PollInterval::from_log(-2).unwrap());
+
+        let changes = system_config_changes(&config, &new);
+        assert_eq!(changes.len(), 6);
+        assert!(changes.contains(&ConfigChange::new(
+            "panic-threshold.forward",
+            "1000".into(),
+            "none".into()
+        )));
+        assert!(changes.contains(&ConfigChange::new(
+            "frequency-tolerance",
+            "15".into(),
+            "100".into()
+        )));
+        assert!(system_config_changes(&new, &new).is_empty());
+
+        for update in [
+            ConfigUpdate {
+                panic_threshold: Some(-1.),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                accumulated_threshold: Some(1e-7),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                spike_threshold: Some(f64::NAN),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                distance_threshold: Some(0.),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                min_cluster_survivors: Some(0),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                min_poll: Some(10),
+                max_poll: Some(8),
+                ..Default::default()
+            },
+            ConfigUpdate {
+                max_poll: Some(30),
+                ..Default::default()
+            },
+        ] {
+            assert!(update.apply(&config).is_err(), "{update:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_peers() {
+        let system_config = Arc::new(RwLock::new(SystemConfig::default()));
+
+        // the peer task we spawn needs live channels to the (absent) system
+        let (msg_for_system_tx, _msg_for_system_rx) = tokio::sync::mpsc::channel(32);
+        let (_reset_tx, reset_rx) = tokio::sync::watch::channel(ResetEpoch::default());
+        let channels = PeerChannels {
+            msg_for_system_sender: msg_for_system_tx,
+            system_snapshots: Default::default(),
+            system_config: system_config.clone(),
+            reset: reset_rx,
+            system_snapshot_updates: tokio::sync::watch::channel(Default::default()).1,
+        };
+        let peers = Arc::new(RwLock::new(Peers::new(channels, TestClock {})));
+
+        let path = std::env::temp_dir().join("ntp-test-stream-5");
+        let config = ConfigureConfig {
+            path: Some(path.clone()),
+            mode: 0o700,
+        };
+
+        let handle = spawn(
+            config,
+            system_config,
+            Default::default(),
+            peers.clone(),
+            TestLogReloader {},
+        )
+        .await;
+
+        // Ensure client has started.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let response = request(
+            &path,
+            &ConfigRequest::AddPeer(
+                AddPeer {
+                    addr: "127.0.0.1:8016".into(),
+                    ..Default::default()
+                }
+                .to_config()
+                .unwrap(),
+            ),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Ok));
+        assert_eq!(peers.read().await.size(), 1);
+
+        match request(&path, &ConfigRequest::ListPeers).await {
+            ConfigResponse::Peers(list) => {
+                assert_eq!(list.len(), 1);
+                assert_eq!(list[0].address, "127.0.0.1:8016");
+                assert_eq!(list[0].resolved, "127.0.0.1:8016".parse().unwrap());
+                assert_eq!(list[0].mode, PeerHostMode::Server);
+            }
+            other => panic!("unexpected response {other:?}"),
+        }
+
+        let response = request(
+            &path,
+            &ConfigRequest::RemovePeer(RemovePeer {
+                addr: "127.0.0.1:8016".into(),
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Ok));
+        assert_eq!(peers.read().await.size(), 0);
+
+        let response = request(
+            &path,
+            &ConfigRequest::RemovePeer(RemovePeer {
+                addr: "127.0.0.1:8016".into(),
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Error(_)));
+
+        handle.abort();
+    }
+
+    #[test]
+    fn test_add_peer_options() {
+        let add_peer = AddPeer {
+            addr: "example.com".into(),
+            pool: true,
+            max_peers: Some(3),
+            min_poll: Some(4),
+            prefer: true,
+            max_delay: Some("50ms".into()),
+            offset: Some("-1.5ms".into()),
+            ..Default::default()
+        };
+
+        let config = add_peer.to_config().unwrap();
+        assert!(matches!(config, PeerConfig::Pool(ref pool) if pool.max_peers == 3));
+        assert_eq!(config.addr().as_str(), "example.com:123");
+
+        let options = config.options();
+        assert_eq!(options.min_poll, PollInterval::from_log(4));
+        assert!(options.prefer);
+        assert_eq!(options.max_delay, Some(NtpDuration::from_seconds(0.05)));
+        assert_eq!(options.offset, NtpDuration::from_seconds(-0.0015));
+
+        // the same checks as for a peer in the configuration file
+        let conflicting = AddPeer {
+            addr: "example.com".into(),
+            prefer: true,
+            noselect: true,
+            ..Default::default()
+        };
+        assert!(conflicting.to_config().is_err());
+
+        let invalid_delay = AddPeer {
+            addr: "example.com".into(),
+            max_delay: Some("50 parsecs".into()),
+            ..Default::default()
+        };
+        assert!(invalid_delay.to_config().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_servers() {
+        let system_config = Arc::new(RwLock::new(SystemConfig::default()));
+        let peers = test_peers();
+
+        let path = std::env::temp_dir().join("ntp-test-stream-6");
+        let config = ConfigureConfig {
+            path: Some(path.clone()),
+            mode: 0o700,
+        };
+
+        let handle = spawn(
+            config,
+            system_config,
+            Default::default(),
+            peers.clone(),
+            TestLogReloader {},
+        )
+        .await;
+
+        // Ensure client has started.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let server = || ServerArgs {
+            addr: "127.0.0.1:9014".parse().unwrap(),
+            allowlist: None,
+            allowlist_action: None,
+            denylist: None,
+            denylist_action: None,
+        };
+
+        let response = request(&path, &ConfigRequest::AddServer(server())).await;
+        assert!(matches!(response, ConfigResponse::Ok));
+
+        // only one server per address
+        let response = request(&path, &ConfigRequest::AddServer(server())).await;
+        assert!(matches!(response, ConfigResponse::Error(_)));
+
+        let response = request(
+            &path,
+            &ConfigRequest::UpdateServer(ServerArgs {
+                denylist: Some(vec!["127.0.0.0/8".parse().unwrap()]),
+                denylist_action: Some(FilterAction::Deny),
+                ..server()
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Ok));
+        {
+            let peers = peers.read().await;
+            let config = peers.server_configs().next().unwrap();
+            assert!(config.denylist.is_in(&"127.0.0.1".parse().unwrap()));
+            assert_eq!(config.denylist_action, FilterAction::Deny);
+        }
+
+        // a long list does not arrive in a single read
+        let denylist: Vec<IpSubnet> = (0..65536u32)
+            .map(|i| IpSubnet {
+                addr: std::net::Ipv4Addr::from(0x0a00_0000 | i).into(),
+                mask: 32,
+            })
+            .collect();
+        let response = request(
+            &path,
+            &ConfigRequest::UpdateServer(ServerArgs {
+                denylist: Some(denylist),
+                denylist_action: Some(FilterAction::Deny),
+                ..server()
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Ok));
+        {
+            let peers = peers.read().await;
+            let config = peers.server_configs().next().unwrap();
+            assert!(config.denylist.is_in(&"10.0.255.255".parse().unwrap()));
+        }
+
+        // an invalid request is answered with an error, and later requests still work
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"{\"AddServer\": 42}").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut msg = Vec::new();
+        let response = read_json_to_end(&mut stream, &mut msg).await.unwrap();
+        assert!(matches!(response, ConfigResponse::Error(_)));
+
+        let response = request(
+            &path,
+            &ConfigRequest::RemoveServer(RemoveServer {
+                addr: "127.0.0.1:9014".parse().unwrap(),
+            }),
+        )
+        .await;
+        assert!(matches!(response, ConfigResponse::Ok));
+        assert_eq!(peers.read().await.server_configs().count(), 0);
+
+        let response = request(&path, &ConfigRequest::UpdateServer(server())).await;
+        assert!(matches!(response, ConfigResponse::Error(_)));
+
         handle.abort();
     }
 }
@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::Subscriber;
 use tracing_subscriber::{
@@ -42,6 +42,21 @@ impl<'de> Deserialize<'de> for LogFormat {
     }
 }
 
+impl Serialize for LogFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let name = match self {
+            LogFormat::Full(_) => "full",
+            LogFormat::Compact(_) => "compact",
+            LogFormat::Pretty(_) => "pretty",
+            LogFormat::Json(_) => "json",
+        };
+        serializer.serialize_str(name)
+    }
+}
+
 impl Default for LogFormat {
     fn default() -> Self {
         LogFormat::Full(Default::default())
@@ -1,26 +1,32 @@
 pub mod dynamic;
 pub mod format;
 mod peer;
+pub mod reload;
 mod server;
+pub mod sources;
 pub mod subnet;
+pub mod validate;
 
 pub use peer::*;
 pub use server::*;
 
 use clap::Parser;
 use ntp_proto::SystemConfig;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    io::ErrorKind,
     path::{Path, PathBuf},
     sync::Arc,
 };
 use thiserror::Error;
-use tokio::{fs::read_to_string, io};
-use tracing::{info, warn};
+use tokio::io;
+use tracing::{error, info, warn};
 use tracing_subscriber::filter::EnvFilter;
 
-use self::format::LogFormat;
+use self::{
+    format::LogFormat,
+    sources::ConfigSources,
+    validate::{ConfigIssue, Severity},
+};
 
 fn deserialize_option_env_filter<'de, D>(deserializer: D) -> Result<Option<EnvFilter>, D::Error>
 where
@@ -40,6 +46,20 @@ where
     }
 }
 
+// without a filter, the daemon logs at the info level
+fn serialize_option_env_filter<S>(
+    filter: &Option<EnvFilter>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match filter {
+        Some(filter) => serializer.collect_str(filter),
+        None => serializer.serialize_str("info"),
+    }
+}
+
 fn parse_env_filter(input: &str) -> Result<Arc<EnvFilter>, tracing_subscriber::filter::ParseError> {
     EnvFilter::builder()
         .with_regex(false)
@@ -97,9 +117,37 @@ pub struct CmdArgs {
         help = "Override the servers to run from the configuration file"
     )]
     pub servers: Vec<ServerConfig>,
+
+    #[arg(
+        long,
+        help = "Check the configuration file for errors and exit without starting the daemon"
+    )]
+    pub validate: bool,
+
+    #[arg(
+        long,
+        help = "Print the settings from the configuration files and the file each came from, then exit"
+    )]
+    pub show_origins: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "toml",
+        help = "Print the effective configuration, including defaults and command line overrides, then exit"
+    )]
+    pub print_config: Option<ConfigFormat>,
+}
+
+/// The formats in which the configuration can be written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     #[serde(alias = "peer")]
@@ -108,7 +156,11 @@ pub struct Config {
     pub servers: Vec<ServerConfig>,
     #[serde(default)]
     pub system: SystemConfig,
-    #[serde(deserialize_with = "deserialize_option_env_filter", default)]
+    #[serde(
+        deserialize_with = "deserialize_option_env_filter",
+        serialize_with = "serialize_option_env_filter",
+        default
+    )]
     pub log_filter: Option<EnvFilter>,
     #[serde(default)]
     pub log_format: LogFormat,
@@ -125,9 +177,9 @@ const fn default_observe_permissions() -> u32 {
     0o777
 }
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct ObserveConfig {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub path: Option<PathBuf>,
     #[serde(default = "default_observe_permissions")]
     pub mode: u32,
@@ -146,9 +198,9 @@ impl Default for ObserveConfig {
     }
 }
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct ConfigureConfig {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub path: Option<std::path::PathBuf>,
     #[serde(default = "default_configure_permissions")]
     pub mode: u32,
@@ -163,10 +215,28 @@ impl Default for ConfigureConfig {
     }
 }
 
+/// The log settings the daemon currently runs with. These change at runtime through the
+/// configuration socket and when the configuration is reloaded, so they are shared.
+#[derive(Debug, Clone, Default)]
+pub struct LogSettings {
+    pub filter: Option<String>,
+    pub format: LogFormat,
+}
+
+impl From<&Config> for LogSettings {
+    fn from(config: &Config) -> Self {
+        LogSettings {
+            filter: config.log_filter.as_ref().map(ToString::to_string),
+            format: config.log_format.clone(),
+        }
+    }
+}
+
 #[cfg(feature = "sentry")]
-#[derive(Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct SentryConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub dsn: Option<String>,
     #[serde(default = "default_sample_rate")]
     pub sample_rate: f32,
@@ -183,34 +253,33 @@ pub enum ConfigError {
     Io(#[from] io::Error),
     #[error("config toml parsing error: {0}")]
     Toml(#[from] toml::de::Error),
+    #[error("error in {}: {error}", path.display())]
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("error in the includes of {}: {reason}", path.display())]
+    Include { path: PathBuf, reason: String },
+    #[error("could not merge configuration files: {0}")]
+    Merge(#[from] toml::ser::Error),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+    #[error("invalid value in environment variable {name}: {message}")]
+    Env { name: String, message: String },
 }
 
 impl Config {
-    async fn from_file(file: impl AsRef<Path>) -> Result<Config, ConfigError> {
-        let contents = read_to_string(file).await?;
-        Ok(toml::de::from_str(&contents)?)
-    }
-
     async fn from_first_file(file: Option<impl AsRef<Path>>) -> Result<Config, ConfigError> {
-        // if an explicit file is given, always use that one
-        if let Some(f) = file {
-            return Config::from_file(f).await;
-        }
+        let mut sources = ConfigSources::from_first_file(file)
+            .await?
+            .unwrap_or_default();
+        sources.apply_env(std::env::vars())?;
 
-        // try ntp.toml in working directory or skip if file doesn't exist
-        match Config::from_file("./ntp.toml").await {
-            Err(ConfigError::Io(e)) if e.kind() == ErrorKind::NotFound => {}
-            other => return other,
-        }
-
-        // for the global file we also ignore it when there are permission errors
-        match Config::from_file("/etc/ntp.toml").await {
-            Err(ConfigError::Io(e))
-                if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::PermissionDenied => {}
-            other => return other,
+        if sources.is_empty() {
+            Ok(Config::default())
+        } else {
+            sources.config()
         }
-
-        Ok(Config::default())
     }
 
     pub async fn from_args(
@@ -237,27 +306,104 @@ impl Config {
         Ok(config)
     }
 
-    /// Check that the config is reasonable. This function may panic if the
-    /// configuration is egregious, although it doesn't do so currently.
-    pub fn check(&self) {
+    /// Check that the config is reasonable, and log the problems found. Returns whether the
+    /// config is free of errors. This does not touch the filesystem, see `check_files` for that.
+    /// This function may panic if the configuration is egregious, although it doesn't do so
+    /// currently.
+    pub fn check(&self) -> bool {
         // Note: since we only check once logging is fully configured,
         // using those fields should always work. This is also
         // probably a good policy in general (config should always work
         // but we may panic here to protect the user from themselves)
-        if self.peers.is_empty() {
-            warn!("No peers configured. Daemon will not do anything.");
+        log_issues(&self.validate())
+    }
+
+    /// Check the files and directories the config refers to, and log the problems found.
+    /// Returns whether there are no errors.
+    pub async fn check_files(&self) -> bool {
+        log_issues(&self.validate_files().await)
+    }
+
+    /// Use the log settings from the command line (or the environment) instead of the ones
+    /// from the configuration file
+    pub fn apply_log_overrides(&mut self, filter: Option<&EnvFilter>, format: Option<&LogFormat>) {
+        if let Some(filter) = filter {
+            self.log_filter = Some(
+                EnvFilter::builder()
+                    .with_regex(false)
+                    .parse_lossy(filter.to_string()),
+            );
         }
 
-        if self.peers.len() < self.system.min_intersection_survivors {
-            warn!("Fewer peers configured than are required to agree on the current time. Daemon will not do anything.");
+        if let Some(format) = format {
+            self.log_format = format.clone();
+        }
+    }
+
+    /// Write out the configuration in a form that can be read back as a configuration file
+    pub fn render(&self, format: ConfigFormat) -> Result<String, String> {
+        match format {
+            ConfigFormat::Toml => {
+                // going through a value puts plain values before tables, as toml requires
+                let value = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+                toml::to_string_pretty(&value).map_err(|e| e.to_string())
+            }
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
         }
     }
 }
 
+/// Print the configuration the daemon would run with, after applying the command line
+/// overrides. Returns the exit code.
+pub async fn print_config(args: &CmdArgs, format: ConfigFormat) -> i32 {
+    let mut config = match Config::from_args(
+        args.config.as_ref(),
+        args.peers.clone(),
+        args.servers.clone(),
+    )
+    .await
+    {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return exitcode::CONFIG;
+        }
+    };
+
+    config.apply_log_overrides(args.log_filter.as_deref(), args.log_format.as_ref());
+
+    match config.render(format) {
+        Ok(rendered) => {
+            println!("{}", rendered.trim_end());
+            exitcode::OK
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            exitcode::SOFTWARE
+        }
+    }
+}
+
+/// Log configuration problems, returns whether none of them is an error
+fn log_issues(issues: &[ConfigIssue]) -> bool {
+    for issue in issues.iter() {
+        match (issue.severity, issue.key.is_empty()) {
+            (Severity::Error, true) => error!("{}", issue.message),
+            (Severity::Error, false) => error!(key = issue.key, "{}", issue.message),
+            (Severity::Warning, true) => warn!("{}", issue.message),
+            (Severity::Warning, false) => warn!(key = issue.key, "{}", issue.message),
+        }
+    }
+
+    !issues.iter().any(|issue| issue.severity == Severity::Error)
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, ffi::OsString};
 
+    use ntp_proto::NtpDuration;
+
     use super::*;
 
     #[test]
@@ -267,6 +413,7 @@ mod tests {
             config.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("example.com:123"),
+                options: PeerOptions::default(),
             })]
         );
 
@@ -277,6 +424,7 @@ mod tests {
             config.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("example.com:123"),
+                options: PeerOptions::default(),
             })]
         );
 
@@ -287,6 +435,7 @@ mod tests {
             config.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("example.com:123"),
+                options: PeerOptions::default(),
             })]
         );
 
@@ -297,11 +446,32 @@ mod tests {
             config.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("example.com:123"),
+                options: PeerOptions::default(),
             })]
         );
         assert!(config.system.panic_threshold.forward.is_none());
         assert!(config.system.panic_threshold.backward.is_none());
 
+        let config: Config = toml::from_str(
+            "[[peers]]\naddr = \"example.com\"\n[system]\npoll-limits = { min = -2, max = 6 }",
+        )
+        .unwrap();
+        assert_eq!(config.system.poll_limits.min.as_log(), -2);
+        assert_eq!(config.system.poll_limits.max.as_log(), 6);
+
+        let config: Result<Config, _> = toml::from_str(
+            "[[peers]]\naddr = \"example.com\"\n[system]\npoll-limits = { min = 6, max = 4 }",
+        );
+        assert!(config.is_err());
+
+        let config: Config =
+            toml::from_str("[[peers]]\naddr = \"example.com\"\n[system]\nmax-stratum = 3").unwrap();
+        assert_eq!(config.system.max_stratum, 3);
+
+        let config: Result<Config, _> =
+            toml::from_str("[[peers]]\naddr = \"example.com\"\n[system]\nmax-stratum = 16");
+        assert!(config.is_err());
+
         let config: Config = toml::from_str(
             r#"
             log-filter = "info"
@@ -331,6 +501,7 @@ mod tests {
             config.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("example.com:123"),
+                options: PeerOptions::default(),
             })]
         );
     }
@@ -463,6 +634,7 @@ mod tests {
             parsed_empty.peers,
             vec![PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("foo.nl:123"),
+                options: PeerOptions::default(),
             })]
         );
         assert!(parsed_empty.config.is_none());
@@ -476,9 +648,11 @@ mod tests {
             vec![
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("foo.rs:123"),
+                    options: PeerOptions::default(),
                 }),
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("spam.nl:123"),
+                    options: PeerOptions::default(),
                 }),
             ]
         );
@@ -503,4 +677,327 @@ mod tests {
 
         assert!(config.is_err());
     }
+
+    #[test]
+    fn test_render_config() {
+        let source = r#"
+            log-filter = "warn"
+            log-format = "json"
+
+            [[peers]]
+            addr = "0.pool.ntp.org"
+            mode = "Pool"
+            max_peers = 4
+            max-delay = 0.25
+
+            [[peers]]
+            addr = "example.com"
+            min-poll = 2
+            offset = -0.001
+
+            [[server]]
+            addr = "[::]:123"
+            denylist = ["fe80::/10"]
+            denylist-action = "Deny"
+            denylist-file = "/etc/ntpd-rs/denylist"
+            rate-limit = { interval = "2s", burst = 4 }
+            unsynchronized-action = "Ignore"
+            access = [
+                { subnet = "203.0.113.0/24", action = "Ignore" },
+                { subnet = "10.0.0.0/8", action = "RateLimit", rate-limit = { burst = 2 } },
+            ]
+
+            [system]
+            panic-threshold = { forward = 1000, backward = 0 }
+            accumulated-threshold = 2.5
+            min-intersection-survivors = 2
+
+            [observe]
+            path = "/run/ntpd-rs/observe"
+            "#;
+        let config: Config = toml::from_str(source).unwrap();
+
+        for format in [ConfigFormat::Toml, ConfigFormat::Json] {
+            let rendered = config.render(format).unwrap();
+            let parsed: Config = match format {
+                ConfigFormat::Toml => toml::from_str(&rendered).unwrap(),
+                ConfigFormat::Json => serde_json::from_str(&rendered).unwrap(),
+            };
+
+            assert_eq!(parsed.peers, config.peers);
+            assert_eq!(parsed.servers, config.servers);
+            assert_eq!(parsed.log_filter.unwrap().to_string(), "warn");
+            assert!(matches!(parsed.log_format, LogFormat::Json(_)));
+            assert_eq!(parsed.observe, config.observe);
+            assert_eq!(parsed.configure, config.configure);
+            assert_eq!(parsed.system.min_intersection_survivors, 2);
+            assert_eq!(
+                parsed.system.accumulated_threshold,
+                config.system.accumulated_threshold
+            );
+            assert_eq!(parsed.system.panic_threshold.backward, None);
+            assert_eq!(
+                parsed.system.panic_threshold.forward,
+                config.system.panic_threshold.forward
+            );
+        }
+
+        // defaults are filled in
+        let rendered = Config::default().render(ConfigFormat::Toml).unwrap();
+        assert!(rendered.contains("log-filter = 'info'"), "{rendered}");
+        assert!(rendered.contains("min-intersection-survivors = 3"));
+        assert!(rendered.contains("accumulated-threshold = 'unlimited'"));
+    }
+
+    #[test]
+    fn test_rate_limit_config() {
+        let config: Config = toml::from_str(
+            r#"
+            peers = []
+
+            [[server]]
+            addr = "0.0.0.0:123"
+
+            [[server]]
+            addr = "[::]:123"
+            rate-limit = {}
+
+            [[server]]
+            addr = "[::]:1123"
+            rate-limit = { interval = "1m", burst = 2, ipv4-prefix = 32, ipv6-prefix = 48, max-clients = 1000, kod-rate = 0 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.servers[0].rate_limit, None);
+        assert_eq!(
+            config.servers[1].rate_limit,
+            Some(RateLimitConfig::default())
+        );
+        assert_eq!(
+            config.servers[2].rate_limit,
+            Some(RateLimitConfig {
+                interval: NtpDuration::from_seconds(60.),
+                burst: 2,
+                ipv4_prefix: 32,
+                ipv6_prefix: 48,
+                max_clients: 1000,
+                kod_rate: 0,
+            })
+        );
+
+        for invalid in [
+            "interval = 0",
+            "burst = 0",
+            "ipv4-prefix = 33",
+            "ipv6-prefix = 129",
+            "max-clients = 0",
+            "average = 8",
+        ] {
+            let source = format!(
+                "peers = []\n[[server]]\naddr = \"[::]:123\"\nrate-limit = {{ {invalid} }}"
+            );
+            assert!(toml::from_str::<Config>(&source).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_interface_config() {
+        let config: Config = toml::from_str(
+            r#"
+            peers = []
+
+            [[server]]
+            interface = "eth0"
+
+            [[server]]
+            interface = "eth1"
+            addr = "[::]:1123"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.servers[0].interface.as_deref(), Some("eth0"));
+        assert_eq!(config.servers[0].addr, "0.0.0.0:123".parse().unwrap());
+        assert_eq!(config.servers[1].addr.port(), 1123);
+        assert!(!config.servers[0].same_listener(&config.servers[1]));
+
+        let rendered = config.render(ConfigFormat::Toml).unwrap();
+        let parsed: Config = toml::from_str(&rendered).unwrap();
+        assert!(parsed.servers[0].same_listener(&config.servers[0]));
+        assert!(parsed.servers[1].same_listener(&config.servers[1]));
+
+        // the address of the interface is not up to the configuration
+        let source = r#"
+            peers = []
+            [[server]]
+            interface = "eth0"
+            addr = "10.0.0.1:123"
+            "#;
+        assert!(toml::from_str::<Config>(source).is_err());
+    }
+
+    #[test]
+    fn test_list_files_config() {
+        let config: Config = toml::from_str(
+            r#"
+            peers = []
+
+            [[server]]
+            addr = "[::]:123"
+            allowlist-file = "/etc/ntpd-rs/allowlist"
+            allowlist-action = "Ignore"
+
+            [[server]]
+            addr = "[::]:1123"
+            denylist = ["10.0.0.0/8"]
+            denylist-action = "Deny"
+            denylist-file = "/etc/ntpd-rs/denylist"
+            "#,
+        )
+        .unwrap();
+
+        // with only a file, only the subnets in the file are allowed
+        let server = &config.servers[0];
+        assert_eq!(
+            server.allowlist_file.as_deref(),
+            Some(Path::new("/etc/ntpd-rs/allowlist"))
+        );
+        assert!(!server.allowlist.is_in(&"127.0.0.1".parse().unwrap()));
+
+        let server = &config.servers[1];
+        assert_eq!(
+            server.denylist_file.as_deref(),
+            Some(Path::new("/etc/ntpd-rs/denylist"))
+        );
+        assert!(server.denylist.is_in(&"10.0.0.1".parse().unwrap()));
+        assert!(server.allowlist.is_in(&"10.0.0.1".parse().unwrap()));
+
+        // a list file needs an action as well
+        let source = r#"
+            peers = []
+            [[server]]
+            addr = "[::]:123"
+            denylist-file = "/etc/ntpd-rs/denylist"
+            "#;
+        assert!(toml::from_str::<Config>(source).is_err());
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            peers = []
+
+            [[server]]
+            addr = "[::]:123"
+            rate-limit = { interval = 16 }
+            access = [
+                { subnet = "10.66.0.0/16", action = "Ignore" },
+                { subnet = "10.0.0.0/8", action = "Serve" },
+                { subnet = "192.0.2.0/24", action = "Deny" },
+                { subnet = "198.51.100.0/24", action = "Restrict" },
+                { subnet = "2001:db8::/32", action = "RateLimit", rate-limit = { interval = "1m" } },
+                { subnet = "0.0.0.0/0", action = "RateLimit" },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let server = &config.servers[0];
+        let lookup = |addr: &str| server.access.lookup(&addr.parse().unwrap());
+        let class = RateLimitConfig {
+            interval: NtpDuration::from_seconds(60.),
+            ..RateLimitConfig::default()
+        };
+
+        // the first match decides
+        assert_eq!(lookup("10.66.1.1"), Some((AccessAction::Ignore, None)));
+        assert_eq!(lookup("10.1.1.1"), Some((AccessAction::Serve, None)));
+        assert_eq!(lookup("192.0.2.1"), Some((AccessAction::Deny, None)));
+        assert_eq!(lookup("198.51.100.1"), Some((AccessAction::Restrict, None)));
+        assert_eq!(
+            lookup("2001:db8::1"),
+            Some((AccessAction::RateLimit, Some(class)))
+        );
+        assert_eq!(lookup("172.16.0.1"), Some((AccessAction::RateLimit, None)));
+        assert_eq!(lookup("2001:db9::1"), None);
+
+        let classes: Vec<_> = server.rate_limits().collect();
+        assert_eq!(classes, [server.rate_limit.unwrap(), class]);
+
+        // a rate limit class only makes sense for rate limited clients
+        let source = r#"
+            peers = []
+            [[server]]
+            addr = "[::]:123"
+            access = [{ subnet = "::/0", action = "Serve", rate-limit = {} }]
+            "#;
+        assert!(toml::from_str::<Config>(source).is_err());
+    }
+
+    #[test]
+    fn test_config_units() {
+        let config: Config = toml::from_str(
+            r#"
+            [[peers]]
+            addr = "example.com"
+            max-delay = "100ms"
+            offset = "-1.5ms"
+
+            [system]
+            panic-threshold = { forward = "30m", backward = "unlimited" }
+            startup-panic-threshold = "inf"
+            accumulated-threshold = "1h"
+            spike-threshold = "15m"
+            distance-threshold = "500ms"
+            frequency-tolerance = "20ppm"
+            "#,
+        )
+        .unwrap();
+
+        let options = config.peers[0].options();
+        assert_eq!(options.max_delay, Some(NtpDuration::from_seconds(0.1)));
+        assert_eq!(options.offset, NtpDuration::from_seconds(-1.5e-3));
+
+        let system = config.system;
+        assert_eq!(
+            system.panic_threshold.forward,
+            Some(NtpDuration::from_seconds(1800.))
+        );
+        assert_eq!(system.panic_threshold.backward, None);
+        assert_eq!(system.startup_panic_threshold.forward, None);
+        assert_eq!(system.startup_panic_threshold.backward, None);
+        assert_eq!(
+            system.accumulated_threshold,
+            Some(NtpDuration::from_seconds(3600.))
+        );
+        assert_eq!(system.spike_threshold, NtpDuration::from_seconds(900.));
+        assert_eq!(system.distance_threshold, NtpDuration::from_seconds(0.5));
+        assert_eq!(system.frequency_tolerance.to_ppm(), 20);
+
+        // plain numbers keep working, and so does 0 meaning no limit
+        let config: Config = toml::from_str(
+            "peers = []\n[system]\npanic-threshold = { forward = 0, backward = 10.5 }\nfrequency-tolerance = 15",
+        )
+        .unwrap();
+        assert_eq!(config.system.panic_threshold.forward, None);
+        assert_eq!(
+            config.system.panic_threshold.backward,
+            Some(NtpDuration::from_seconds(10.5))
+        );
+
+        for invalid in [
+            "panic-threshold = -5",
+            "panic-threshold = \"100ns\"",
+            "accumulated-threshold = 1e-7",
+            "panic-threshold = \"5 fortnights\"",
+            "accumulated-threshold = \"never\"",
+            "spike-threshold = \"unlimited\"",
+            "frequency-tolerance = \"15 ppb\"",
+        ] {
+            let source = format!("peers = []\n[system]\n{invalid}");
+            assert!(toml::from_str::<Config>(&source).is_err(), "{invalid}");
+        }
+    }
 }
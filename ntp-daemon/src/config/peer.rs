@@ -1,11 +1,14 @@
 use std::{fmt, net::SocketAddr};
 
+pub use ntp_proto::PeerOptions;
+use ntp_proto::{config_seconds, NtpDuration};
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum PeerHostMode {
     #[serde(alias = "server")]
     Server,
@@ -19,18 +22,20 @@ impl Default for PeerHostMode {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct StandardPeerConfig {
     pub addr: NormalizedAddress,
+    pub options: PeerOptions,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct PoolPeerConfig {
     pub addr: NormalizedAddress,
     pub max_peers: usize,
+    pub options: PeerOptions,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum PeerConfig {
     Standard(StandardPeerConfig),
     Pool(PoolPeerConfig),
@@ -41,6 +46,21 @@ impl PeerConfig {
     pub(crate) fn try_from_str(value: &str) -> Result<Self, std::io::Error> {
         Self::try_from(value)
     }
+
+    /// The address as configured, before any DNS lookup
+    pub fn addr(&self) -> &NormalizedAddress {
+        match self {
+            PeerConfig::Standard(StandardPeerConfig { addr, .. }) => addr,
+            PeerConfig::Pool(PoolPeerConfig { addr, .. }) => addr,
+        }
+    }
+
+    pub fn options(&self) -> &PeerOptions {
+        match self {
+            PeerConfig::Standard(StandardPeerConfig { options, .. }) => options,
+            PeerConfig::Pool(PoolPeerConfig { options, .. }) => options,
+        }
+    }
 }
 
 /// A normalized address has a host and a port part. However, the host may be
@@ -52,7 +72,7 @@ pub struct NormalizedAddress {
 
 impl NormalizedAddress {
     /// Specifically, this adds the `:123` port if no port is specified
-    fn from_string(mut address: String) -> std::io::Result<Self> {
+    pub(crate) fn from_string(mut address: String) -> std::io::Result<Self> {
         if address.split(':').count() > 2 {
             // IPv6, try to parse it as such
             match address.parse::<SocketAddr>() {
@@ -105,6 +125,7 @@ impl TryFrom<&str> for StandardPeerConfig {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         Ok(Self {
             addr: NormalizedAddress::from_string(value.to_string())?,
+            options: PeerOptions::default(),
         })
     }
 }
@@ -141,6 +162,17 @@ impl<'de> Deserialize<'de> for PeerConfig {
                 let mut addr = None;
                 let mut mode = None;
                 let mut max_peers = None;
+                let mut min_poll = None;
+                let mut max_poll = None;
+                let mut iburst = None;
+                let mut burst = None;
+                let mut prefer = None;
+                let mut noselect = None;
+                let mut trust = None;
+                let mut max_delay = None;
+                let mut max_delay_ratio = None;
+                let mut offset = None;
+                let mut asymmetry = None;
                 while let Some(key) = map.next_key::<&str>()? {
                     match key {
                         "addr" => {
@@ -167,10 +199,112 @@ impl<'de> Deserialize<'de> for PeerConfig {
                             }
                             max_peers = Some(map.next_value()?);
                         }
+                        "min-poll" => {
+                            if min_poll.is_some() {
+                                return Err(de::Error::duplicate_field("min-poll"));
+                            }
+                            min_poll = Some(map.next_value()?);
+                        }
+                        "max-poll" => {
+                            if max_poll.is_some() {
+                                return Err(de::Error::duplicate_field("max-poll"));
+                            }
+                            max_poll = Some(map.next_value()?);
+                        }
+                        "iburst" => {
+                            if iburst.is_some() {
+                                return Err(de::Error::duplicate_field("iburst"));
+                            }
+                            iburst = Some(map.next_value()?);
+                        }
+                        "burst" => {
+                            if burst.is_some() {
+                                return Err(de::Error::duplicate_field("burst"));
+                            }
+                            burst = Some(map.next_value()?);
+                        }
+                        "prefer" => {
+                            if prefer.is_some() {
+                                return Err(de::Error::duplicate_field("prefer"));
+                            }
+                            prefer = Some(map.next_value()?);
+                        }
+                        "noselect" => {
+                            if noselect.is_some() {
+                                return Err(de::Error::duplicate_field("noselect"));
+                            }
+                            noselect = Some(map.next_value()?);
+                        }
+                        "trust" => {
+                            if trust.is_some() {
+                                return Err(de::Error::duplicate_field("trust"));
+                            }
+                            trust = Some(map.next_value()?);
+                        }
+                        "max-delay" => {
+                            if max_delay.is_some() {
+                                return Err(de::Error::duplicate_field("max-delay"));
+                            }
+                            let delay: NtpDuration = map.next_value()?;
+                            if delay <= NtpDuration::ZERO {
+                                return Err(de::Error::invalid_value(
+                                    de::Unexpected::Float(delay.to_seconds()),
+                                    &"a positive duration",
+                                ));
+                            }
+                            max_delay = Some(delay);
+                        }
+                        "max-delay-ratio" => {
+                            if max_delay_ratio.is_some() {
+                                return Err(de::Error::duplicate_field("max-delay-ratio"));
+                            }
+                            let ratio: f64 = map.next_value()?;
+                            if ratio.is_nan() || ratio < 1.0 {
+                                return Err(de::Error::invalid_value(
+                                    de::Unexpected::Float(ratio),
+                                    &"a ratio of at least 1",
+                                ));
+                            }
+                            max_delay_ratio = Some(ratio);
+                        }
+                        "offset" => {
+                            if offset.is_some() {
+                                return Err(de::Error::duplicate_field("offset"));
+                            }
+                            offset = Some(map.next_value::<NtpDuration>()?);
+                        }
+                        "asymmetry" => {
+                            if asymmetry.is_some() {
+                                return Err(de::Error::duplicate_field("asymmetry"));
+                            }
+                            let fraction: f64 = map.next_value()?;
+                            if !(0.0..=1.0).contains(&fraction) {
+                                return Err(de::Error::invalid_value(
+                                    de::Unexpected::Float(fraction),
+                                    &"a fraction between 0 and 1",
+                                ));
+                            }
+                            asymmetry = Some(fraction);
+                        }
                         _ => {
                             return Err(de::Error::unknown_field(
                                 key,
-                                &["addr", "mode", "max_peers"],
+                                &[
+                                    "addr",
+                                    "mode",
+                                    "max_peers",
+                                    "min-poll",
+                                    "max-poll",
+                                    "iburst",
+                                    "burst",
+                                    "prefer",
+                                    "noselect",
+                                    "trust",
+                                    "max-delay",
+                                    "max-delay-ratio",
+                                    "offset",
+                                    "asymmetry",
+                                ],
                             ));
                         }
                     }
@@ -179,18 +313,48 @@ impl<'de> Deserialize<'de> for PeerConfig {
                 let addr = addr.ok_or_else(|| de::Error::missing_field("addr"))?;
                 let mode = mode.unwrap_or_default();
 
+                if let (Some(min_poll), Some(max_poll)) = (min_poll, max_poll) {
+                    if min_poll > max_poll {
+                        return Err(de::Error::custom("min-poll is larger than max-poll"));
+                    }
+                }
+
+                let options = PeerOptions {
+                    min_poll,
+                    max_poll,
+                    iburst: iburst.unwrap_or_default(),
+                    burst: burst.unwrap_or_default(),
+                    prefer: prefer.unwrap_or_default(),
+                    noselect: noselect.unwrap_or_default(),
+                    trust: trust.unwrap_or_default(),
+                    max_delay,
+                    max_delay_ratio,
+                    offset: offset.unwrap_or_default(),
+                    asymmetry,
+                };
+
+                if options.prefer && options.noselect {
+                    return Err(de::Error::custom(
+                        "a peer cannot have both prefer and noselect set",
+                    ));
+                }
+
                 match mode {
                     PeerHostMode::Server => {
                         if max_peers.is_some() {
-                            Err(de::Error::unknown_field("max_peers", &["addr", "mode"]))
+                            Err(de::Error::custom("max_peers is only allowed for a pool"))
                         } else {
-                            Ok(PeerConfig::Standard(StandardPeerConfig { addr }))
+                            Ok(PeerConfig::Standard(StandardPeerConfig { addr, options }))
                         }
                     }
                     PeerHostMode::Pool => {
                         let max_peers = max_peers.unwrap_or(1);
 
-                        Ok(PeerConfig::Pool(PoolPeerConfig { addr, max_peers }))
+                        Ok(PeerConfig::Pool(PoolPeerConfig {
+                            addr,
+                            max_peers,
+                            options,
+                        }))
                     }
                 }
             }
@@ -200,8 +364,55 @@ impl<'de> Deserialize<'de> for PeerConfig {
     }
 }
 
+// Written out in the same form the deserializer accepts, with the defaults filled in. Limits that
+// are not configured follow the system settings, so they are left out.
+impl Serialize for PeerConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("addr", self.addr().as_str())?;
+
+        match self {
+            PeerConfig::Standard(_) => map.serialize_entry("mode", &PeerHostMode::Server)?,
+            PeerConfig::Pool(pool) => {
+                map.serialize_entry("mode", &PeerHostMode::Pool)?;
+                map.serialize_entry("max_peers", &pool.max_peers)?;
+            }
+        }
+
+        let options = self.options();
+        if let Some(min_poll) = options.min_poll {
+            map.serialize_entry("min-poll", &min_poll)?;
+        }
+        if let Some(max_poll) = options.max_poll {
+            map.serialize_entry("max-poll", &max_poll)?;
+        }
+        map.serialize_entry("iburst", &options.iburst)?;
+        map.serialize_entry("burst", &options.burst)?;
+        map.serialize_entry("prefer", &options.prefer)?;
+        map.serialize_entry("noselect", &options.noselect)?;
+        map.serialize_entry("trust", &options.trust)?;
+        if let Some(max_delay) = options.max_delay {
+            map.serialize_entry("max-delay", &config_seconds(max_delay))?;
+        }
+        if let Some(max_delay_ratio) = options.max_delay_ratio {
+            map.serialize_entry("max-delay-ratio", &max_delay_ratio)?;
+        }
+        map.serialize_entry("offset", &config_seconds(options.offset))?;
+        if let Some(asymmetry) = options.asymmetry {
+            map.serialize_entry("asymmetry", &asymmetry)?;
+        }
+
+        map.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use ntp_proto::{PollInterval, PollIntervalLimits};
+
     use super::*;
 
     fn peer_addr(config: &PeerConfig) -> &str {
@@ -275,6 +486,209 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deserialize_peer_poll_limits() {
+        #[derive(Deserialize, Debug)]
+        struct TestConfig {
+            peer: PeerConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            min-poll = 0
+            max-poll = 1
+            "#,
+        )
+        .unwrap();
+        let limits = test
+            .peer
+            .options()
+            .poll_limits(PollIntervalLimits::default());
+        assert_eq!(limits.min, PollInterval::from_log(0).unwrap());
+        assert_eq!(limits.max, PollInterval::from_log(1).unwrap());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            mode = "Pool"
+            min-poll = 6
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(test.peer, PeerConfig::Pool(_)));
+        let limits = test
+            .peer
+            .options()
+            .poll_limits(PollIntervalLimits::default());
+        assert_eq!(limits.min, PollInterval::from_log(6).unwrap());
+        assert_eq!(limits.max, PollInterval::MAX);
+
+        // a single limit on the peer overrides the conflicting system limit
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            max-poll = 2
+            "#,
+        )
+        .unwrap();
+        let limits = test
+            .peer
+            .options()
+            .poll_limits(PollIntervalLimits::default());
+        assert_eq!(limits.min, PollInterval::from_log(2).unwrap());
+        assert_eq!(limits.max, PollInterval::from_log(2).unwrap());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            min-poll = 8
+            max-poll = 6
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            iburst = true
+            "#,
+        )
+        .unwrap();
+        assert!(test.peer.options().iburst);
+        assert!(!test.peer.options().burst);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            burst = true
+            "#,
+        )
+        .unwrap();
+        assert!(!test.peer.options().iburst);
+        assert!(test.peer.options().burst);
+
+        let test: TestConfig = toml::from_str("peer = \"example.com\"").unwrap();
+        assert!(!test.peer.options().iburst);
+        assert!(!test.peer.options().prefer);
+        assert!(!test.peer.options().noselect);
+        assert!(!test.peer.options().trust);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            prefer = true
+            trust = true
+            "#,
+        )
+        .unwrap();
+        assert!(test.peer.options().prefer);
+        assert!(!test.peer.options().noselect);
+        assert!(test.peer.options().trust);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            noselect = true
+            "#,
+        )
+        .unwrap();
+        assert!(test.peer.options().noselect);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            prefer = true
+            noselect = true
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            max-delay = 0.25
+            max-delay-ratio = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.peer.options().max_delay,
+            Some(NtpDuration::from_seconds(0.25))
+        );
+        assert_eq!(test.peer.options().max_delay_ratio, Some(3.0));
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            max-delay = -1
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            max-delay-ratio = 0.5
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            offset = -0.002
+            asymmetry = 0.6
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.peer.options().offset,
+            NtpDuration::from_seconds(-0.002)
+        );
+        assert_eq!(test.peer.options().asymmetry, Some(0.6));
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            asymmetry = 1.5
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            iburst = 1
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [peer]
+            addr = "example.com"
+            min-poll = -10
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
     #[test]
     fn test_peer_from_string() {
         let peer = PeerConfig::try_from("example.com").unwrap();
@@ -0,0 +1,460 @@
+use std::{path::PathBuf, sync::Arc};
+
+use ntp_proto::{NtpClock, SystemConfig};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::RwLock,
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use super::{
+    dynamic::{resolve_peer, system_config_changes, LogReloader},
+    Config, ConfigureConfig, LogSettings, ObserveConfig, PeerConfig, ServerConfig,
+};
+use crate::{list_files::ListFiles, peer_manager::Peers};
+
+/// Everything needed to load the configuration again the same way it was loaded on startup
+#[derive(Debug)]
+pub struct ReloadConfig {
+    /// The configuration file given on the command line
+    pub file: Option<PathBuf>,
+    /// Peers given on the command line, these override the configuration file
+    pub peers: Vec<PeerConfig>,
+    /// Servers given on the command line, these override the configuration file
+    pub servers: Vec<ServerConfig>,
+    pub has_log_override: bool,
+    pub has_format_override: bool,
+    /// The sockets can not be reopened, so changes to these are only reported
+    pub observe: ObserveConfig,
+    pub configure: ConfigureConfig,
+}
+
+pub async fn spawn<H: LogReloader + Send + Sync + 'static, C: NtpClock + Sync>(
+    config: ReloadConfig,
+    system_config: Arc<RwLock<SystemConfig>>,
+    log_settings: Arc<RwLock<LogSettings>>,
+    peers: Arc<RwLock<Peers<C>>>,
+    log_reload_handle: H,
+) -> JoinHandle<std::io::Result<()>> {
+    tokio::spawn(async move {
+        let result = reload_on_hangup(
+            config,
+            system_config,
+            log_settings,
+            peers,
+            log_reload_handle,
+        )
+        .await;
+        if let Err(ref e) = result {
+            error!("Abnormal termination of configuration reloader: {}", e);
+        }
+        result
+    })
+}
+
+async fn reload_on_hangup<H: LogReloader + Sync, C: NtpClock + Sync>(
+    config: ReloadConfig,
+    system_config: Arc<RwLock<SystemConfig>>,
+    log_settings: Arc<RwLock<LogSettings>>,
+    peers: Arc<RwLock<Peers<C>>>,
+    log_reload_handle: H,
+) -> std::io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+
+        let new = match Config::from_args(
+            config.file.as_ref(),
+            config.peers.clone(),
+            config.servers.clone(),
+        )
+        .await
+        {
+            Ok(new) => new,
+            Err(e) => {
+                error!(
+                    "There was an error loading the config, keeping the current configuration: {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &log_reload_handle,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Bring the running daemon in line with the new configuration. Peers and servers that did not
+/// change are left running. Nothing changes when the new configuration has errors.
+async fn reload<H: LogReloader, C: NtpClock>(
+    config: &ReloadConfig,
+    mut new: Config,
+    system_config: &RwLock<SystemConfig>,
+    log_settings: &RwLock<LogSettings>,
+    peers: &RwLock<Peers<C>>,
+    log_reload_handle: &H,
+) {
+    // the checks log what is wrong, the running daemon is left alone
+    if !(new.check() && new.check_files().await) {
+        error!("The new configuration has errors, keeping the current configuration");
+        return;
+    }
+
+    if !config.has_log_override {
+        let filter = new.log_filter.take();
+        log_settings.write().await.filter = filter.as_ref().map(ToString::to_string);
+        log_reload_handle.update_log(filter.unwrap_or_else(|| EnvFilter::new("info")));
+    }
+
+    if !config.has_format_override {
+        log_settings.write().await.format = new.log_format.clone();
+        log_reload_handle.update_format(new.log_format.clone());
+    }
+
+    {
+        let mut system_config = system_config.write().await;
+        for change in system_config_changes(&system_config, &new.system) {
+            info!(field = change.field, old = ?change.old, new = change.new, "system setting changed");
+        }
+        *system_config = new.system;
+    }
+
+    let (removed, added): (Vec<_>, Vec<_>) = {
+        let peers = peers.read().await;
+        let current: Vec<_> = peers.peer_configs().map(|(c, _)| c.clone()).collect();
+
+        (
+            difference(&current, &new.peers),
+            difference(&new.peers, &current),
+        )
+    };
+
+    for peer_config in removed.iter() {
+        peers.write().await.remove_peer_config(peer_config);
+    }
+
+    for peer_config in added {
+        // resolve before taking the lock, the system should not wait on DNS
+        match resolve_peer(&peer_config).await {
+            Ok(addr) => peers.write().await.add_resolved_peer(peer_config, addr),
+            Err(e) => error!("{}, not adding peer", e),
+        }
+    }
+
+    // read the list files of new servers before taking the lock, they can be long
+    let running: Vec<_> = peers.read().await.server_configs().cloned().collect();
+    let mut new_files = Vec::with_capacity(new.servers.len());
+    for server_config in new.servers.iter() {
+        let is_new = !running
+            .iter()
+            .any(|config| config.same_listener(server_config));
+        new_files.push(match is_new {
+            true => Some(ListFiles::read(server_config).await),
+            false => None,
+        });
+    }
+
+    let mut peers = peers.write().await;
+
+    let removed: Vec<_> = peers
+        .server_configs()
+        .filter(|running| !new.servers.iter().any(|c| c.same_listener(running)))
+        .map(|running| (running.addr, running.interface.clone()))
+        .collect();
+    for (addr, interface) in removed {
+        peers.remove_server(addr, interface.as_deref());
+    }
+
+    for (server_config, files) in new.servers.into_iter().zip(new_files) {
+        let running = peers
+            .server_configs()
+            .find(|running| running.same_listener(&server_config))
+            .cloned();
+
+        match running {
+            None => {
+                // a server that was removed in the meantime has its files read by its task
+                let files = files.unwrap_or_else(|| ListFiles::new(&server_config));
+                peers.add_server(server_config, files);
+            }
+            Some(running) if running != server_config => {
+                peers.update_server(server_config);
+            }
+            Some(_) => {}
+        }
+    }
+
+    // list files that were edited in place may not have been noticed yet
+    peers.reload_list_files();
+
+    if new.observe != config.observe {
+        warn!("Changes to the observation socket only take effect after a restart");
+    }
+
+    if new.configure != config.configure {
+        warn!("Changes to the configuration socket only take effect after a restart");
+    }
+
+    info!("Configuration reloaded");
+}
+
+/// The peers in `from` that have no counterpart in `other`. Identical peers are counted, so when
+/// one of two identical peers is removed from the configuration, one of them is stopped.
+fn difference(from: &[PeerConfig], other: &[PeerConfig]) -> Vec<PeerConfig> {
+    let mut unmatched: Vec<_> = other.iter().collect();
+
+    from.iter()
+        .filter(
+            |config| match unmatched.iter().position(|other| other == config) {
+                Some(index) => {
+                    unmatched.swap_remove(index);
+                    false
+                }
+                None => true,
+            },
+        )
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{NtpDuration, NtpLeapIndicator, NtpTimestamp, PollInterval};
+
+    use crate::config::format::LogFormat;
+    use crate::peer::{PeerChannels, ResetEpoch};
+
+    use super::*;
+
+    struct TestLogReloader {}
+    impl LogReloader for TestLogReloader {
+        fn update_log(&self, _f: EnvFilter) {}
+        fn update_format(&self, _format: LogFormat) {}
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_freq(&self, _freq: f64) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn update_clock(
+            &self,
+            _offset: NtpDuration,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+            _poll_interval: PollInterval,
+            _leap_status: NtpLeapIndicator,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn reload_config() -> ReloadConfig {
+        ReloadConfig {
+            file: None,
+            peers: vec![],
+            servers: vec![],
+            has_log_override: false,
+            has_format_override: false,
+            observe: ObserveConfig::default(),
+            configure: ConfigureConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let system_config = Arc::new(RwLock::new(SystemConfig::default()));
+
+        // the peer tasks we spawn need live channels to the (absent) system
+        let (msg_for_system_tx, _msg_for_system_rx) = tokio::sync::mpsc::channel(32);
+        let (_reset_tx, reset_rx) = tokio::sync::watch::channel(ResetEpoch::default());
+        let channels = PeerChannels {
+            msg_for_system_sender: msg_for_system_tx,
+            system_snapshots: Default::default(),
+            system_config: system_config.clone(),
+            reset: reset_rx,
+            system_snapshot_updates: tokio::sync::watch::channel(Default::default()).1,
+        };
+        let peers = Arc::new(RwLock::new(Peers::new(channels, TestClock {})));
+
+        let log_settings = RwLock::new(LogSettings::default());
+
+        let config = reload_config();
+        let new: Config = toml::from_str(
+            r#"
+            log-filter = "debug"
+
+            [[peers]]
+            addr = "127.0.0.1:8017"
+
+            [[peers]]
+            addr = "127.0.0.1:8018"
+
+            [[server]]
+            addr = "127.0.0.1:9015"
+
+            [system]
+            min-intersection-survivors = 1
+            "#,
+        )
+        .unwrap();
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &TestLogReloader {},
+        )
+        .await;
+
+        assert_eq!(system_config.read().await.min_intersection_survivors, 1);
+        assert_eq!(log_settings.read().await.filter.as_deref(), Some("debug"));
+        assert_eq!(peers.read().await.size(), 2);
+        assert_eq!(peers.read().await.server_configs().count(), 1);
+
+        let new: Config = toml::from_str(
+            r#"
+            [[peers]]
+            addr = "127.0.0.1:8017"
+
+            [[peers]]
+            addr = "127.0.0.1:8019"
+            iburst = true
+
+            [[server]]
+            addr = "127.0.0.1:9015"
+            denylist = ["127.0.0.0/8"]
+            denylist-action = "Deny"
+
+            [system]
+            min-intersection-survivors = 2
+            "#,
+        )
+        .unwrap();
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &TestLogReloader {},
+        )
+        .await;
+
+        assert_eq!(system_config.read().await.min_intersection_survivors, 2);
+        assert_eq!(log_settings.read().await.filter, None);
+
+        {
+            let peers = peers.read().await;
+            let mut addresses: Vec<_> = peers
+                .peer_configs()
+                .map(|(c, _)| c.addr().as_str().to_string())
+                .collect();
+            addresses.sort();
+            assert_eq!(addresses, ["127.0.0.1:8017", "127.0.0.1:8019"]);
+
+            let servers: Vec<_> = peers.server_configs().collect();
+            assert_eq!(servers.len(), 1);
+            assert!(servers[0].denylist.is_in(&"127.0.0.1".parse().unwrap()));
+        }
+
+        // identical peers are counted
+        let duplicated = r#"
+            [[peers]]
+            addr = "127.0.0.1:8017"
+
+            [[peers]]
+            addr = "127.0.0.1:8017"
+
+            [system]
+            min-intersection-survivors = 1
+            "#;
+        let new: Config = toml::from_str(duplicated).unwrap();
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &TestLogReloader {},
+        )
+        .await;
+        assert_eq!(peers.read().await.size(), 2);
+
+        let single = r#"
+            [[peers]]
+            addr = "127.0.0.1:8017"
+
+            [system]
+            min-intersection-survivors = 1
+            "#;
+        let new: Config = toml::from_str(single).unwrap();
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &TestLogReloader {},
+        )
+        .await;
+        assert_eq!(peers.read().await.size(), 1);
+
+        // a configuration with errors is not applied at all
+        let invalid = r#"
+            log-filter = "trace"
+
+            [[peers]]
+            addr = "127.0.0.1:8019"
+
+            [system]
+            min-intersection-survivors = 0
+            "#;
+        let new: Config = toml::from_str(invalid).unwrap();
+        reload(
+            &config,
+            new,
+            &system_config,
+            &log_settings,
+            &peers,
+            &TestLogReloader {},
+        )
+        .await;
+        assert_eq!(system_config.read().await.min_intersection_survivors, 1);
+        assert_eq!(log_settings.read().await.filter, None);
+        let addresses: Vec<_> = peers
+            .read()
+            .await
+            .peer_configs()
+            .map(|(c, _)| c.addr().as_str().to_string())
+            .collect();
+        assert_eq!(addresses, ["127.0.0.1:8017"]);
+    }
+}
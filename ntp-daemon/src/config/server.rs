@@ -1,48 +1,309 @@
 use std::{
     fmt,
-    net::{AddrParseError, SocketAddr},
+    net::{AddrParseError, IpAddr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
 };
 
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 
+use ntp_proto::{config_seconds, NtpDuration};
+
 use crate::{config::subnet::IpSubnet, ipfilter::IpFilter};
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize, clap::ValueEnum)]
 pub enum FilterAction {
     Ignore,
     Deny,
 }
 
+fn default_rate_limit_interval() -> NtpDuration {
+    NtpDuration::from_seconds(8.0)
+}
+
+const fn default_rate_limit_burst() -> u32 {
+    8
+}
+
+const fn default_ipv4_prefix() -> u8 {
+    24
+}
+
+const fn default_ipv6_prefix() -> u8 {
+    64
+}
+
+const fn default_max_clients() -> usize {
+    65536
+}
+
+const fn default_kod_rate() -> u32 {
+    100
+}
+
+fn serialize_interval<S: Serializer>(
+    interval: &NtpDuration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(config_seconds(*interval))
+}
+
+/// Limits on how often a single client may query a server. Clients are grouped by prefix, so
+/// all addresses in e.g. a /24 share one budget.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Average time between requests of a client
+    #[serde(
+        default = "default_rate_limit_interval",
+        serialize_with = "serialize_interval"
+    )]
+    pub interval: NtpDuration,
+    /// Number of requests a client may send in quick succession
+    #[serde(default = "default_rate_limit_burst")]
+    pub burst: u32,
+    #[serde(default = "default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    /// Number of clients that are tracked, which bounds the memory used
+    #[serde(default = "default_max_clients")]
+    pub max_clients: usize,
+    /// Maximum number of RATE kiss-o'-death packets sent per second, to all clients together.
+    /// Requests beyond that are ignored.
+    #[serde(default = "default_kod_rate")]
+    pub kod_rate: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            interval: default_rate_limit_interval(),
+            burst: default_rate_limit_burst(),
+            ipv4_prefix: default_ipv4_prefix(),
+            ipv6_prefix: default_ipv6_prefix(),
+            max_clients: default_max_clients(),
+            kod_rate: default_kod_rate(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn check(&self) -> Result<(), String> {
+        if self.interval <= NtpDuration::ZERO {
+            Err("rate-limit interval must be greater than zero".into())
+        } else if self.burst == 0 {
+            Err("rate-limit burst must be at least 1".into())
+        } else if self.ipv4_prefix > 32 {
+            Err(format!(
+                "rate-limit ipv4-prefix must be at most 32, got {}",
+                self.ipv4_prefix
+            ))
+        } else if self.ipv6_prefix > 128 {
+            Err(format!(
+                "rate-limit ipv6-prefix must be at most 128, got {}",
+                self.ipv6_prefix
+            ))
+        } else if self.max_clients == 0 {
+            Err("rate-limit max-clients must be at least 1".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What to do with a client that matches an access rule
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum AccessAction {
+    /// Answer every request
+    Serve,
+    /// Answer requests within the rate limit, see [`RateLimitConfig`]
+    RateLimit,
+    /// Do not answer
+    Ignore,
+    /// Reply with a DENY kiss-o'-death
+    Deny,
+    /// Reply with a RSTR kiss-o'-death
+    Restrict,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AccessRule {
+    pub subnet: IpSubnet,
+    pub action: AccessAction,
+    /// The rate limit class of a `RateLimit` rule. When not given, the rate limit of the server
+    /// is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl AccessRule {
+    fn check(&self) -> Result<(), String> {
+        match (self.action, &self.rate_limit) {
+            (AccessAction::RateLimit, Some(rate_limit)) => rate_limit.check(),
+            (_, Some(_)) => Err(format!(
+                "access rule for {} has a rate-limit, but its action is not RateLimit",
+                self.subnet
+            )),
+            (_, None) => Ok(()),
+        }
+    }
+}
+
+/// An ordered list of access rules, where the first rule that matches a client decides what
+/// happens to it
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AccessRules {
+    rules: Vec<AccessRule>,
+    /// Consecutive rules with the same outcome, merged into one filter
+    filters: Vec<(IpFilter, AccessAction, Option<RateLimitConfig>)>,
+}
+
+impl AccessRules {
+    pub fn new(rules: Vec<AccessRule>) -> Self {
+        let mut filters = vec![];
+        let mut start = 0;
+        while start < rules.len() {
+            let first = &rules[start];
+            let end = rules[start..]
+                .iter()
+                .position(|rule| rule.action != first.action || rule.rate_limit != first.rate_limit)
+                .map(|len| start + len)
+                .unwrap_or(rules.len());
+
+            let subnets: Vec<_> = rules[start..end]
+                .iter()
+                .map(|rule| rule.subnet.clone())
+                .collect();
+            filters.push((IpFilter::new(&subnets), first.action, first.rate_limit));
+            start = end;
+        }
+
+        AccessRules { rules, filters }
+    }
+
+    pub fn rules(&self) -> &[AccessRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The action and rate limit class of the first rule that matches `addr`
+    pub fn lookup(&self, addr: &IpAddr) -> Option<(AccessAction, Option<RateLimitConfig>)> {
+        self.filters
+            .iter()
+            .find(|(filter, _, _)| filter.is_in(addr))
+            .map(|(_, action, rate_limit)| (*action, *rate_limit))
+    }
+}
+
+/// What a server does while the clock is not synchronized
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum UnsynchronizedAction {
+    /// Do not answer
+    Ignore,
+    /// Answer with stratum 16 and an unknown leap indicator, so clients do not synchronize to us
+    Unsynchronized,
+    /// Answer as if the clock is synchronized
+    Serve,
+}
+
+// deriving `Default` for enums needs a more recent compiler than we support
+#[allow(clippy::derivable_impls)]
+impl Default for UnsynchronizedAction {
+    fn default() -> Self {
+        UnsynchronizedAction::Unsynchronized
+    }
+}
+
+/// The port of a server on an interface, when no `addr` is given
+const DEFAULT_PORT: u16 = 123;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ServerConfig {
+    /// With an `interface`, only the port is used
     pub addr: SocketAddr,
+    /// Serve on all addresses of this network interface, following them as they change
+    pub interface: Option<String>,
     pub denylist: IpFilter,
     pub denylist_action: FilterAction,
+    /// File with more subnets for the denylist, read again when it changes
+    pub denylist_file: Option<PathBuf>,
     pub allowlist: IpFilter,
     pub allowlist_action: FilterAction,
+    /// File with more subnets for the allowlist, read again when it changes
+    pub allowlist_file: Option<PathBuf>,
+    /// No rate limiting is done when `None`
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Checked before the allow and deny lists
+    pub access: AccessRules,
+    pub unsynchronized_action: UnsynchronizedAction,
+    /// Number of tasks that serve requests, each with their own socket
+    pub workers: usize,
+}
+
+impl ServerConfig {
+    /// Whether both configs listen on the same addresses, which makes them the same server
+    pub fn same_listener(&self, other: &ServerConfig) -> bool {
+        self.addr == other.addr && self.interface == other.interface
+    }
+
+    /// Every rate limit class used by this server
+    pub fn rate_limits(&self) -> impl Iterator<Item = RateLimitConfig> + '_ {
+        let uses_default = self.access.rules().iter().any(|rule| {
+            rule.action == AccessAction::RateLimit
+                && rule.rate_limit.is_none()
+                && self.rate_limit.is_none()
+        });
+
+        self.rate_limit
+            .into_iter()
+            .chain(uses_default.then(RateLimitConfig::default))
+            .chain(
+                self.access
+                    .rules()
+                    .iter()
+                    .filter_map(|rule| rule.rate_limit),
+            )
+    }
 }
 
 impl ServerConfig {
     pub(crate) fn try_from_str(value: &str) -> Result<Self, <Self as TryFrom<&str>>::Error> {
         Self::try_from(value)
     }
+
+    /// A server on `addr` with the default settings
+    pub(crate) fn from_addr(addr: SocketAddr) -> Self {
+        ServerConfig {
+            addr,
+            interface: None,
+            denylist: IpFilter::none(),
+            denylist_action: FilterAction::Ignore,
+            denylist_file: None,
+            allowlist: IpFilter::all(),
+            allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::default(),
+            workers: 1,
+        }
+    }
 }
 
 impl TryFrom<&str> for ServerConfig {
     type Error = AddrParseError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Ok(ServerConfig {
-            addr: SocketAddr::from_str(value)?,
-            denylist: IpFilter::none(),
-            denylist_action: FilterAction::Ignore,
-            allowlist: IpFilter::all(),
-            allowlist_action: FilterAction::Ignore,
-        })
+        Ok(ServerConfig::from_addr(SocketAddr::from_str(value)?))
     }
 }
 
@@ -68,10 +329,17 @@ impl<'de> Deserialize<'de> for ServerConfig {
 
             fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<ServerConfig, M::Error> {
                 let mut addr = None;
+                let mut interface = None;
                 let mut allowlist = None;
                 let mut allowlist_action = None;
+                let mut allowlist_file = None;
                 let mut denylist = None;
                 let mut denylist_action = None;
+                let mut denylist_file = None;
+                let mut rate_limit = None;
+                let mut access = None;
+                let mut unsynchronized_action = None;
+                let mut workers = None;
                 while let Some(key) = map.next_key::<&str>()? {
                     match key {
                         "addr" => {
@@ -80,6 +348,12 @@ impl<'de> Deserialize<'de> for ServerConfig {
                             }
                             addr = Some(map.next_value::<SocketAddr>()?);
                         }
+                        "interface" => {
+                            if interface.is_some() {
+                                return Err(de::Error::duplicate_field("interface"));
+                            }
+                            interface = Some(map.next_value::<String>()?);
+                        }
                         "allowlist" => {
                             if allowlist.is_some() {
                                 return Err(de::Error::duplicate_field("allowlist"));
@@ -93,6 +367,12 @@ impl<'de> Deserialize<'de> for ServerConfig {
                             }
                             allowlist_action = Some(map.next_value::<FilterAction>()?);
                         }
+                        "allowlist-file" => {
+                            if allowlist_file.is_some() {
+                                return Err(de::Error::duplicate_field("allowlist-file"));
+                            }
+                            allowlist_file = Some(map.next_value::<PathBuf>()?);
+                        }
                         "denylist" => {
                             if denylist.is_some() {
                                 return Err(de::Error::duplicate_field("denylist"));
@@ -106,35 +386,108 @@ impl<'de> Deserialize<'de> for ServerConfig {
                             }
                             denylist_action = Some(map.next_value::<FilterAction>()?);
                         }
+                        "denylist-file" => {
+                            if denylist_file.is_some() {
+                                return Err(de::Error::duplicate_field("denylist-file"));
+                            }
+                            denylist_file = Some(map.next_value::<PathBuf>()?);
+                        }
+                        "rate-limit" => {
+                            if rate_limit.is_some() {
+                                return Err(de::Error::duplicate_field("rate-limit"));
+                            }
+                            let config = map.next_value::<RateLimitConfig>()?;
+                            config.check().map_err(de::Error::custom)?;
+                            rate_limit = Some(config);
+                        }
+                        "access" => {
+                            if access.is_some() {
+                                return Err(de::Error::duplicate_field("access"));
+                            }
+                            let rules: Vec<AccessRule> = map.next_value()?;
+                            for rule in rules.iter() {
+                                rule.check().map_err(de::Error::custom)?;
+                            }
+                            access = Some(AccessRules::new(rules));
+                        }
+                        "unsynchronized-action" => {
+                            if unsynchronized_action.is_some() {
+                                return Err(de::Error::duplicate_field("unsynchronized-action"));
+                            }
+                            unsynchronized_action = Some(map.next_value::<UnsynchronizedAction>()?);
+                        }
+                        "workers" => {
+                            if workers.is_some() {
+                                return Err(de::Error::duplicate_field("workers"));
+                            }
+                            let count = map.next_value::<usize>()?;
+                            if count == 0 {
+                                return Err(de::Error::custom("workers must be at least 1"));
+                            }
+                            workers = Some(count);
+                        }
                         _ => {
-                            return Err(de::Error::unknown_field(key, &["addr"]));
+                            return Err(de::Error::unknown_field(
+                                key,
+                                &[
+                                    "addr",
+                                    "interface",
+                                    "allowlist",
+                                    "allowlist-action",
+                                    "allowlist-file",
+                                    "denylist",
+                                    "denylist-action",
+                                    "denylist-file",
+                                    "rate-limit",
+                                    "access",
+                                    "unsynchronized-action",
+                                    "workers",
+                                ],
+                            ));
                         }
                     }
                 }
 
-                let addr = addr.ok_or_else(|| de::Error::missing_field("addr"))?;
-                let (allowlist, allowlist_action) = match allowlist {
-                    Some(allowlist) => (
-                        allowlist,
+                let addr = match (addr, &interface) {
+                    (Some(addr), Some(_)) if !addr.ip().is_unspecified() => {
+                        return Err(de::Error::custom(
+                            "with an interface, addr must be an unspecified address such as \"0.0.0.0:123\", only its port is used",
+                        ));
+                    }
+                    (Some(addr), _) => addr,
+                    (None, Some(_)) => SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+                    (None, None) => return Err(de::Error::missing_field("addr")),
+                };
+                // with only a file, the allowlist holds just the subnets in that file
+                let (allowlist, allowlist_action) = match (allowlist, &allowlist_file) {
+                    (None, None) => (IpFilter::all(), FilterAction::Ignore),
+                    (allowlist, _) => (
+                        allowlist.unwrap_or_else(IpFilter::none),
                         allowlist_action
                             .ok_or_else(|| de::Error::missing_field("allowlist-action"))?,
                     ),
-                    None => (IpFilter::all(), FilterAction::Ignore),
                 };
-                let (denylist, denylist_action) = match denylist {
-                    Some(denylist) => (
-                        denylist,
+                let (denylist, denylist_action) = match (denylist, &denylist_file) {
+                    (None, None) => (IpFilter::none(), FilterAction::Ignore),
+                    (denylist, _) => (
+                        denylist.unwrap_or_else(IpFilter::none),
                         denylist_action
                             .ok_or_else(|| de::Error::missing_field("denylist-action"))?,
                     ),
-                    None => (IpFilter::none(), FilterAction::Ignore),
                 };
                 Ok(ServerConfig {
                     addr,
+                    interface,
                     allowlist,
                     allowlist_action,
+                    allowlist_file,
                     denylist,
                     denylist_action,
+                    denylist_file,
+                    rate_limit,
+                    access: access.unwrap_or_default(),
+                    unsynchronized_action: unsynchronized_action.unwrap_or_default(),
+                    workers: workers.unwrap_or(1),
                 })
             }
         }
@@ -142,3 +495,35 @@ impl<'de> Deserialize<'de> for ServerConfig {
         deserializer.deserialize_any(ServerConfigVisitor)
     }
 }
+
+impl Serialize for ServerConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("addr", &self.addr)?;
+        if let Some(interface) = &self.interface {
+            map.serialize_entry("interface", interface)?;
+        }
+        map.serialize_entry("allowlist", self.allowlist.subnets())?;
+        map.serialize_entry("allowlist-action", &self.allowlist_action)?;
+        if let Some(path) = &self.allowlist_file {
+            map.serialize_entry("allowlist-file", path)?;
+        }
+        map.serialize_entry("denylist", self.denylist.subnets())?;
+        map.serialize_entry("denylist-action", &self.denylist_action)?;
+        if let Some(path) = &self.denylist_file {
+            map.serialize_entry("denylist-file", path)?;
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            map.serialize_entry("rate-limit", rate_limit)?;
+        }
+        if !self.access.is_empty() {
+            map.serialize_entry("access", self.access.rules())?;
+        }
+        map.serialize_entry("unsynchronized-action", &self.unsynchronized_action)?;
+        map.serialize_entry("workers", &self.workers)?;
+        map.end()
+    }
+}
@@ -0,0 +1,984 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs::{canonicalize, metadata, read_dir, read_to_string};
+use toml::{value::Table, Value};
+use tracing::warn;
+
+use super::{CmdArgs, Config, ConfigError};
+
+/// Prefix of the environment variables that override settings from the configuration files
+const ENV_PREFIX: &str = "NTPD_";
+
+/// Drop-in files that are read after `/etc/ntp.toml`, in alphabetical order
+const DROP_IN_PATTERN: &str = "/etc/ntpd-rs/conf.d/*.toml";
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone)]
+struct Origin {
+    source: Source,
+    /// The key within that file, which differs from the merged key for entries of `peers` and
+    /// `servers`
+    key: String,
+    value: Value,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    /// Index into the list of files
+    File(usize),
+    /// Name of an environment variable
+    Env(String),
+}
+
+/// Where a setting was set, as returned by [`ConfigSources::origin`]
+#[derive(Debug)]
+pub enum Location<'a> {
+    File {
+        path: &'a Path,
+        contents: &'a str,
+        /// The key of the setting within the file
+        key: String,
+    },
+    Env(&'a str),
+}
+
+/// A configuration file together with all the files it includes, merged into one.
+///
+/// Files are merged in the order in which they are read: first the main file, then every
+/// included file, depth first and in the order of the `include` list. Entries in `peers` and
+/// `servers` are appended, all other settings are overridden by files that come later.
+/// Environment variables, see [`ConfigSources::apply_env`], override all files.
+#[derive(Debug, Default)]
+pub struct ConfigSources {
+    /// The path and contents of every file read, in the order in which they were merged
+    pub files: Vec<(PathBuf, String)>,
+    merged: Table,
+    origins: BTreeMap<String, Origin>,
+    /// Whether any environment variable overrides a setting
+    has_env: bool,
+}
+
+impl ConfigSources {
+    pub async fn load(path: impl AsRef<Path>) -> Result<ConfigSources, ConfigError> {
+        let mut sources = ConfigSources::default();
+        sources.read(vec![path.as_ref().to_path_buf()]).await?;
+        Ok(sources)
+    }
+
+    /// Merge the files in `pending`, last one first, together with the files they include
+    async fn read(&mut self, mut pending: Vec<PathBuf>) -> Result<(), ConfigError> {
+        let mut seen = self.canonical_paths().await;
+
+        while let Some(path) = pending.pop() {
+            let contents = match read_to_string(&path).await {
+                Ok(contents) => contents,
+                // the main file may legitimately be absent, see `Config::from_first_file`
+                Err(e) if self.files.is_empty() => return Err(e.into()),
+                Err(e) => {
+                    return Err(ConfigError::Include {
+                        path,
+                        reason: e.to_string(),
+                    })
+                }
+            };
+
+            let canonical = canonicalize(&path).await.unwrap_or_else(|_| path.clone());
+            if seen.contains(&canonical) {
+                return Err(ConfigError::Include {
+                    path,
+                    reason: "file is included more than once".into(),
+                });
+            }
+            seen.push(canonical);
+
+            let mut table: Table =
+                toml::from_str(&contents).map_err(|error| ConfigError::Parse {
+                    path: path.clone(),
+                    error,
+                })?;
+
+            let patterns = match table.remove("include") {
+                None => vec![],
+                Some(Value::String(pattern)) => vec![pattern],
+                Some(Value::Array(patterns)) => patterns
+                    .into_iter()
+                    .map(|pattern| match pattern {
+                        Value::String(pattern) => Ok(pattern),
+                        _ => Err(()),
+                    })
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ConfigError::Include {
+                        path: path.clone(),
+                        reason: "include must be a string or a list of strings".into(),
+                    })?,
+                Some(_) => {
+                    return Err(ConfigError::Include {
+                        path,
+                        reason: "include must be a string or a list of strings".into(),
+                    })
+                }
+            };
+
+            // relative patterns are relative to the file that contains them
+            let directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+            let mut included = vec![];
+            for pattern in patterns {
+                let files =
+                    expand(&directory, &pattern)
+                        .await
+                        .map_err(|reason| ConfigError::Include {
+                            path: path.clone(),
+                            reason,
+                        })?;
+                included.extend(files);
+            }
+
+            let file = self.files.len();
+            self.merge(table, Source::File(file));
+            self.files.push((path, contents));
+
+            // the stack is popped from the back
+            pending.extend(included.into_iter().rev());
+        }
+
+        Ok(())
+    }
+
+    async fn canonical_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        for (path, _) in self.files.iter() {
+            paths.push(canonicalize(path).await.unwrap_or_else(|_| path.clone()));
+        }
+        paths
+    }
+
+    /// Merge the files matching `pattern` that were not read yet, after all files read so far
+    async fn read_drop_ins(&mut self, pattern: &str) -> Result<(), ConfigError> {
+        let seen = self.canonical_paths().await;
+
+        let mut pending = vec![];
+        for path in expand(Path::new(""), pattern)
+            .await
+            .map_err(ConfigError::Invalid)?
+        {
+            let canonical = canonicalize(&path).await.unwrap_or_else(|_| path.clone());
+            if !seen.contains(&canonical) {
+                pending.push(path);
+            }
+        }
+
+        // the stack is popped from the back
+        pending.reverse();
+        self.read(pending).await
+    }
+
+    /// Load the configuration file given on the command line, or otherwise the first of
+    /// `./ntp.toml` and `/etc/ntp.toml` that exists. `/etc/ntp.toml` is followed by the drop-in
+    /// files in `/etc/ntpd-rs/conf.d`. Returns `None` when there is no file.
+    pub async fn from_first_file(
+        file: Option<impl AsRef<Path>>,
+    ) -> Result<Option<ConfigSources>, ConfigError> {
+        // if an explicit file is given, always use that one
+        if let Some(f) = file {
+            return ConfigSources::load(f).await.map(Some);
+        }
+
+        // try ntp.toml in working directory or skip if file doesn't exist
+        match ConfigSources::load("./ntp.toml").await {
+            Err(ConfigError::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+            other => return other.map(Some),
+        }
+
+        // for the global file we also ignore it when there are permission errors
+        let mut sources = match ConfigSources::load("/etc/ntp.toml").await {
+            Err(ConfigError::Io(e))
+                if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::PermissionDenied =>
+            {
+                return Ok(None)
+            }
+            other => other?,
+        };
+
+        // drop-ins that the main file includes itself are not read twice
+        sources.read_drop_ins(DROP_IN_PATTERN).await?;
+
+        Ok(Some(sources))
+    }
+
+    /// Whether there is nothing to configure, neither files nor environment variables
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && !self.has_env
+    }
+
+    /// Override settings with the `NTPD_*` variables among `vars`. The name of a variable is the
+    /// key of the setting with sections separated by `__` and dashes replaced by `_`, so
+    /// `NTPD_SYSTEM__PANIC_THRESHOLD` sets `system.panic-threshold`. `NTPD_PEERS` and
+    /// `NTPD_SERVERS` take a comma separated list of addresses, and replace the peers and servers
+    /// from the files.
+    ///
+    /// Variables that do not correspond to a setting are ignored with a warning: orchestrators
+    /// may define `NTPD_*` variables of their own, such as `NTPD_PORT` for a service called
+    /// `ntpd` in Kubernetes.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), ConfigError> {
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        if vars.is_empty() {
+            return Ok(());
+        }
+        // the environment is unordered, so apply e.g. `NTPD_SYSTEM` before
+        // `NTPD_SYSTEM__PANIC_THRESHOLD`
+        vars.sort();
+
+        let schema = schema();
+        for (name, raw) in vars {
+            let key = env_key(&name);
+            if !is_known(&schema, &key) {
+                warn!("ignoring environment variable {name}: there is no setting {key}");
+                continue;
+            }
+
+            let value = env_value(&key, &raw);
+            let table = nest(&key, value);
+
+            // check the variable on its own, so problems are reported on the variable
+            if let Err(error) = toml::from_str::<Config>(&with_peers(&table)?) {
+                return Err(ConfigError::Env {
+                    name,
+                    message: without_key(&error),
+                });
+            }
+
+            // the peers and servers in the environment replace those from the files
+            for key in ["peers", "servers"] {
+                if table.contains_key(key) {
+                    self.merged.remove(key);
+                    let nested = format!("{key}[");
+                    self.origins.retain(|k, _| !k.starts_with(&nested));
+                }
+            }
+
+            self.merge(table, Source::Env(name));
+            self.has_env = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        if let ([(path, contents)], false) = (self.files.as_slice(), self.has_env) {
+            // keep the positions in errors meaningful
+            return toml::from_str(contents).map_err(|error| ConfigError::Parse {
+                path: path.clone(),
+                error,
+            });
+        }
+
+        // without a file the peers may only be set through the environment
+        let merged = if self.files.is_empty() {
+            with_peers(&self.merged)?
+        } else {
+            toml::to_string(&self.merged)?
+        };
+        toml::from_str(&merged).map_err(|merged_error| {
+            // positions in the merged configuration mean nothing to the user, so try to find the
+            // file the problem is in
+            let message = without_position(&merged_error);
+            for (path, contents) in self.files.iter() {
+                if let Err(error) = toml::from_str::<Config>(contents) {
+                    if without_key(&error) == without_key(&merged_error) {
+                        return ConfigError::Parse {
+                            path: path.clone(),
+                            error,
+                        };
+                    }
+                }
+            }
+
+            ConfigError::Invalid(message)
+        })
+    }
+
+    /// The file or environment variable a setting like `system.panic-threshold` or
+    /// `servers[1].allowlist` was set in. Returns `None` for settings that have their default
+    /// value.
+    pub fn origin(&self, key: &str) -> Option<Location<'_>> {
+        let mut prefix = key;
+        loop {
+            if let Some(origin) = self.origins.get(prefix) {
+                return Some(match &origin.source {
+                    Source::File(file) => {
+                        let (path, contents) = &self.files[*file];
+                        Location::File {
+                            path,
+                            contents,
+                            key: format!("{}{}", origin.key, &key[prefix.len()..]),
+                        }
+                    }
+                    Source::Env(name) => Location::Env(name),
+                });
+            }
+
+            prefix = &prefix[..prefix.rfind(['.', '['])?];
+        }
+    }
+
+    fn merge(&mut self, table: Table, source: Source) {
+        for (key, value) in table {
+            // both `[[peer]]` and `[[peers]]` are accepted
+            let key = match key.as_str() {
+                "peer" => "peers".to_string(),
+                "server" => "servers".to_string(),
+                _ => key,
+            };
+
+            match value {
+                Value::Array(entries) if key == "peers" || key == "servers" => {
+                    let existing = match self.merged.get_mut(&key) {
+                        Some(Value::Array(existing)) => existing,
+                        _ => {
+                            self.merged.insert(key.clone(), Value::Array(vec![]));
+                            match self.merged.get_mut(&key) {
+                                Some(Value::Array(existing)) => existing,
+                                _ => unreachable!(),
+                            }
+                        }
+                    };
+
+                    for (index, entry) in entries.into_iter().enumerate() {
+                        // the short form `peers = ["example.com"]` can not be mixed with tables
+                        // when writing the merged configuration
+                        let entry = match entry {
+                            Value::String(addr) => {
+                                let mut table = Table::new();
+                                table.insert("addr".into(), Value::String(addr));
+                                Value::Table(table)
+                            }
+                            other => other,
+                        };
+
+                        self.origins.insert(
+                            format!("{}[{}]", key, existing.len()),
+                            Origin {
+                                source: source.clone(),
+                                key: format!("{}[{}]", key, index),
+                                value: entry.clone(),
+                            },
+                        );
+                        existing.push(entry);
+                    }
+                }
+                value => merge_value(&mut self.merged, &mut self.origins, &source, "", key, value),
+            }
+        }
+    }
+
+    /// Every setting that is not at its default value, with the file or environment variable it
+    /// was set in
+    pub fn settings(&self) -> impl Iterator<Item = (&str, String, &Value)> {
+        self.origins.iter().map(|(key, origin)| {
+            let source = match &origin.source {
+                Source::File(file) => self.files[*file].0.display().to_string(),
+                Source::Env(name) => name.clone(),
+            };
+            (key.as_str(), source, &origin.value)
+        })
+    }
+}
+
+fn merge_value(
+    into: &mut Table,
+    origins: &mut BTreeMap<String, Origin>,
+    source: &Source,
+    prefix: &str,
+    key: String,
+    value: Value,
+) {
+    let full_key = if prefix.is_empty() {
+        key.clone()
+    } else {
+        format!("{prefix}.{key}")
+    };
+
+    match (into.get_mut(&key), value) {
+        (Some(Value::Table(existing)), Value::Table(table)) => {
+            for (key, value) in table {
+                merge_value(existing, origins, source, &full_key, key, value);
+            }
+        }
+        (_, value) => {
+            // a table may be replaced by a plain value, forget where its contents came from
+            let nested = format!("{full_key}.");
+            origins.retain(|key, _| key != &full_key && !key.starts_with(&nested));
+            record(origins, source, &full_key, &value);
+            into.insert(key, value);
+        }
+    }
+}
+
+fn record(origins: &mut BTreeMap<String, Origin>, source: &Source, key: &str, value: &Value) {
+    match value {
+        Value::Table(table) if !table.is_empty() => {
+            for (nested, value) in table {
+                record(origins, source, &format!("{key}.{nested}"), value);
+            }
+        }
+        value => {
+            origins.insert(
+                key.to_string(),
+                Origin {
+                    source: source.clone(),
+                    key: key.to_string(),
+                    value: value.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Serialize `table`, adding an empty list of peers when it does not have any
+fn with_peers(table: &Table) -> Result<String, toml::ser::Error> {
+    let serialized = toml::to_string(table)?;
+    if table.contains_key("peers") {
+        Ok(serialized)
+    } else {
+        // plain values must come before tables
+        Ok(format!("peers = []\n{serialized}"))
+    }
+}
+
+/// Every setting there is, with its default value
+fn schema() -> Table {
+    let mut config = Config::default();
+    // optional settings are not serialized when they are not set
+    config.observe.path = Some(PathBuf::new());
+    config.configure.path = Some(PathBuf::new());
+    #[cfg(feature = "sentry")]
+    {
+        config.sentry.dsn = Some(String::new());
+    }
+
+    match Value::try_from(config) {
+        Ok(Value::Table(table)) => table,
+        _ => Table::new(),
+    }
+}
+
+/// Whether `key` is a setting, or part of one, like `system.panic-threshold.forward`
+fn is_known(schema: &Table, key: &str) -> bool {
+    let mut table = schema;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        match table.get(part) {
+            Some(Value::Table(nested)) => table = nested,
+            Some(_) => return parts.peek().is_none(),
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// The key of the setting that an environment variable overrides, e.g. `system.panic-threshold`
+/// for `NTPD_SYSTEM__PANIC_THRESHOLD`
+fn env_key(name: &str) -> String {
+    name.trim_start_matches(ENV_PREFIX)
+        .split("__")
+        .map(|part| part.to_lowercase().replace('_', "-"))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Interpret the value of an environment variable. Anything that is a valid toml value is used
+/// as such, so `10` is a number and `{ forward = 10 }` a table. Everything else is a string, so
+/// quotes are only needed for strings that would otherwise be something else.
+fn env_value(key: &str, raw: &str) -> Value {
+    let parsed = toml::from_str::<Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"));
+
+    match parsed {
+        Some(value) => value,
+        None if key == "peers" || key == "servers" => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(|addr| Value::String(addr.to_string()))
+                .collect(),
+        ),
+        None => Value::String(raw.to_string()),
+    }
+}
+
+/// A table that contains `value` at `key`
+fn nest(key: &str, value: Value) -> Table {
+    let (parent, last) = match key.rsplit_once('.') {
+        Some((parent, last)) => (Some(parent), last),
+        None => (None, key),
+    };
+
+    let mut table = Table::new();
+    table.insert(last.to_string(), value);
+    match parent {
+        Some(parent) => nest(parent, Value::Table(table)),
+        None => table,
+    }
+}
+
+/// Expand an include pattern. Only the file name may contain the wildcards `*` and `?`. A
+/// pattern with wildcards that matches nothing, for instance because the directory does not
+/// exist, is not an error.
+async fn expand(directory: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern = directory.join(pattern);
+    let is_wildcard = |c| c == '*' || c == '?';
+
+    let (parent, name) = match (
+        pattern.parent(),
+        pattern.file_name().and_then(|n| n.to_str()),
+    ) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(format!("{} does not name a file", pattern.display())),
+    };
+
+    if parent.to_string_lossy().contains(is_wildcard) {
+        return Err(format!(
+            "{}: wildcards are only supported in the file name",
+            pattern.display()
+        ));
+    }
+
+    if !name.contains(is_wildcard) {
+        return Ok(vec![pattern.clone()]);
+    }
+
+    let mut entries = match read_dir(parent).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("{}: {}", parent.display(), e)),
+    };
+
+    let pattern: Vec<char> = name.chars().collect();
+    let mut files = vec![];
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| format!("{}: {}", parent.display(), e))?
+    {
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+
+        // like shells do, leave hidden files (such as editor backups) alone
+        if file_name.starts_with('.') && !name.starts_with('.') {
+            continue;
+        }
+
+        let chars: Vec<char> = file_name.chars().collect();
+        if matches(&pattern, &chars) {
+            let path = entry.path();
+            if metadata(&path).await.map(|m| m.is_file()).unwrap_or(false) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', rest)), _) => {
+            matches(rest, name) || (!name.is_empty() && matches(pattern, &name[1..]))
+        }
+        (Some(('?', rest)), Some((_, name_rest))) => matches(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) => p == n && matches(rest, name_rest),
+        _ => false,
+    }
+}
+
+/// The message of an error without the key it is about, which is `server` in a file that uses
+/// `[[server]]` but always `servers` in the merged configuration
+fn without_key(error: &toml::de::Error) -> String {
+    let message = without_position(error);
+    match message.rsplit_once(" for key ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
+
+fn without_position(error: &toml::de::Error) -> String {
+    let message = error.to_string();
+    match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
+
+fn print_setting(source: &str, key: &str, value: &Value) {
+    match value {
+        // entries of `peers` and `servers`
+        Value::Table(table) => {
+            for (nested, value) in table {
+                print_setting(source, &format!("{key}.{nested}"), value);
+            }
+        }
+        value => println!("{source}: {key} = {value}"),
+    }
+}
+
+/// Print every setting from the configuration files and the environment together with the file
+/// or variable it came from. Returns the exit code.
+pub async fn print(args: &CmdArgs) -> i32 {
+    let sources = ConfigSources::from_first_file(args.config.as_ref())
+        .await
+        .and_then(|sources| {
+            let mut sources = sources.unwrap_or_default();
+            sources.apply_env(std::env::vars())?;
+            Ok(sources)
+        });
+
+    let sources = match sources {
+        Ok(sources) if sources.is_empty() => {
+            println!("no configuration file found, using the default configuration");
+            return exitcode::OK;
+        }
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return exitcode::CONFIG;
+        }
+    };
+
+    for (path, _) in sources.files.iter() {
+        println!("# {}", path.display());
+    }
+
+    for (key, source, value) in sources.settings() {
+        let overridden = (key.starts_with("peers[") && !args.peers.is_empty())
+            || (key.starts_with("servers[") && !args.servers.is_empty());
+        if !overridden {
+            print_setting(&source, key, value);
+        }
+    }
+
+    if !args.peers.is_empty() {
+        println!("command line: peers");
+    }
+
+    if !args.servers.is_empty() {
+        println!("command line: servers");
+    }
+
+    exitcode::OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(directory: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = directory.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn file_origin(sources: &ConfigSources, key: &str) -> (PathBuf, String) {
+        match sources.origin(key) {
+            Some(Location::File { path, key, .. }) => (path.to_path_buf(), key),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        let m = |pattern: &str, name: &str| {
+            matches(
+                &pattern.chars().collect::<Vec<_>>(),
+                &name.chars().collect::<Vec<_>>(),
+            )
+        };
+
+        assert!(m("*.toml", "10-peers.toml"));
+        assert!(m("*.toml", ".toml"));
+        assert!(m("??-*.toml", "10-peers.toml"));
+        assert!(!m("*.toml", "10-peers.toml.bak"));
+        assert!(!m("?-*.toml", "10-peers.toml"));
+        assert!(m("peers.toml", "peers.toml"));
+    }
+
+    #[tokio::test]
+    async fn test_include() {
+        let directory = std::env::temp_dir().join("ntp-test-include");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("conf.d")).unwrap();
+
+        let main = write(
+            &directory,
+            "ntp.toml",
+            r#"
+include = ["conf.d/*.toml", "missing.d/*.toml"]
+peers = ["example.com"]
+
+[system]
+panic-threshold = 10
+min-intersection-survivors = 2
+"#,
+        );
+        write(
+            &directory,
+            "conf.d/20-servers.toml",
+            r#"
+[[server]]
+addr = "0.0.0.0:123"
+denylist = ["10.0.0.0/8"]
+denylist-action = "Deny"
+
+[system]
+min-intersection-survivors = 1
+"#,
+        );
+        write(
+            &directory,
+            "conf.d/10-peers.toml",
+            "[[peers]]\naddr = \"example.net\"\n",
+        );
+        write(&directory, "conf.d/.10-peers.toml.swp", "garbage");
+
+        let sources = ConfigSources::load(&main).await.unwrap();
+        let names: Vec<_> = sources
+            .files
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ntp.toml", "10-peers.toml", "20-servers.toml"]);
+
+        let config = sources.config().unwrap();
+        let peers: Vec<_> = config.peers.iter().map(|p| p.addr().as_str()).collect();
+        assert_eq!(peers, ["example.com:123", "example.net:123"]);
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(config.system.min_intersection_survivors, 1);
+        assert_eq!(
+            config.system.panic_threshold.forward,
+            Some(ntp_proto::NtpDuration::from_seconds(10.))
+        );
+
+        let (path, key) = file_origin(&sources, "system.panic-threshold");
+        assert_eq!(path, main);
+        assert_eq!(key, "system.panic-threshold");
+
+        let (path, key) = file_origin(&sources, "system.min-intersection-survivors");
+        assert!(path.ends_with("20-servers.toml"));
+        assert_eq!(key, "system.min-intersection-survivors");
+
+        let (path, key) = file_origin(&sources, "peers[1].addr");
+        assert!(path.ends_with("10-peers.toml"));
+        assert_eq!(key, "peers[0].addr");
+
+        let (path, key) = file_origin(&sources, "servers[0].allowlist");
+        assert!(path.ends_with("20-servers.toml"));
+        assert_eq!(key, "servers[0].allowlist");
+
+        assert!(sources.origin("observe.path").is_none());
+
+        // errors are reported in the file they are in
+        write(
+            &directory,
+            "conf.d/30-typo.toml",
+            "[[server]]\naddr = \"[::]:123\"\ndenylst = []\n",
+        );
+        match ConfigSources::load(&main).await.unwrap().config() {
+            Err(ConfigError::Parse { path, error }) => {
+                assert!(path.ends_with("30-typo.toml"));
+                assert!(error.to_string().contains("denylst"), "{error}");
+                assert_eq!(error.line_col().map(|(line, _)| line), Some(0));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        // including a file twice is almost certainly a mistake
+        write(
+            &directory,
+            "conf.d/30-typo.toml",
+            "include = \"../ntp.toml\"\n",
+        );
+        assert!(matches!(
+            ConfigSources::load(&main).await,
+            Err(ConfigError::Include { .. })
+        ));
+
+        write(
+            &directory,
+            "conf.d/30-typo.toml",
+            "include = \"absent.toml\"\n",
+        );
+        assert!(matches!(
+            ConfigSources::load(&main).await,
+            Err(ConfigError::Include { .. })
+        ));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop_ins() {
+        let directory = std::env::temp_dir().join("ntp-test-drop-ins");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("conf.d")).unwrap();
+
+        let main = write(
+            &directory,
+            "ntp.toml",
+            "include = \"conf.d/10-peers.toml\"\npeers = [\"example.com\"]\n",
+        );
+        write(
+            &directory,
+            "conf.d/20-peers.toml",
+            "peers = [\"example.org\"]\n",
+        );
+        write(
+            &directory,
+            "conf.d/10-peers.toml",
+            "peers = [\"example.net\"]\n",
+        );
+
+        let pattern = directory.join("conf.d/*.toml");
+        let mut sources = ConfigSources::load(&main).await.unwrap();
+        sources
+            .read_drop_ins(pattern.to_str().unwrap())
+            .await
+            .unwrap();
+
+        // the drop-in that is included explicitly is not read again
+        let config = sources.config().unwrap();
+        let peers: Vec<_> = config.peers.iter().map(|p| p.addr().as_str()).collect();
+        assert_eq!(
+            peers,
+            ["example.com:123", "example.net:123", "example.org:123"]
+        );
+
+        // without a drop-in directory, there is nothing to add
+        let pattern = directory.join("missing.d/*.toml");
+        let mut sources = ConfigSources::load(&main).await.unwrap();
+        sources
+            .read_drop_ins(pattern.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(sources.files.len(), 2);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_env_key() {
+        assert_eq!(
+            env_key("NTPD_SYSTEM__PANIC_THRESHOLD"),
+            "system.panic-threshold"
+        );
+        assert_eq!(
+            env_key("NTPD_SYSTEM__PANIC_THRESHOLD__FORWARD"),
+            "system.panic-threshold.forward"
+        );
+        assert_eq!(env_key("NTPD_LOG_FILTER"), "log-filter");
+        assert_eq!(env_key("NTPD_PEERS"), "peers");
+
+        let schema = schema();
+        assert!(is_known(&schema, "system.panic-threshold"));
+        assert!(is_known(&schema, "system.panic-threshold.forward"));
+        assert!(is_known(&schema, "observe.path"));
+        assert!(is_known(&schema, "peers"));
+        assert!(!is_known(&schema, "peers.addr"));
+        assert!(!is_known(&schema, "port"));
+        assert!(!is_known(&schema, "system.panic-treshold"));
+    }
+
+    #[test]
+    fn test_env() {
+        let mut sources = ConfigSources::default();
+        let contents = r#"
+peers = ["example.com", "example.net"]
+
+[system]
+panic-threshold = 10
+min-intersection-survivors = 2
+"#;
+        sources.merge(toml::from_str(contents).unwrap(), Source::File(0));
+        sources
+            .files
+            .push((PathBuf::from("ntp.toml"), contents.to_string()));
+
+        sources
+            .apply_env(env(&[
+                ("NTPD_SYSTEM__PANIC_THRESHOLD", "1h"),
+                ("NTPD_OBSERVE__PATH", "/run/ntpd-rs/observe"),
+                ("NTPD_OBSERVE__MODE", "0o660"),
+                ("NTPD_PEERS", "a.example.com, b.example.com:1234"),
+                // as defined by kubernetes for a service called ntpd
+                ("NTPD_PORT", "udp://10.0.0.1:123"),
+                ("NTP_LOG", "debug"),
+            ]))
+            .unwrap();
+
+        let config = sources.config().unwrap();
+        let peers: Vec<_> = config.peers.iter().map(|p| p.addr().as_str()).collect();
+        assert_eq!(peers, ["a.example.com:123", "b.example.com:1234"]);
+        assert_eq!(
+            config.system.panic_threshold.forward,
+            Some(ntp_proto::NtpDuration::from_seconds(3600.))
+        );
+        assert_eq!(config.system.min_intersection_survivors, 2);
+        assert_eq!(
+            config.observe.path,
+            Some(PathBuf::from("/run/ntpd-rs/observe"))
+        );
+        assert_eq!(config.observe.mode, 0o660);
+
+        assert!(matches!(
+            sources.origin("system.panic-threshold"),
+            Some(Location::Env("NTPD_SYSTEM__PANIC_THRESHOLD"))
+        ));
+        assert!(matches!(
+            sources.origin("peers[1].addr"),
+            Some(Location::Env("NTPD_PEERS"))
+        ));
+        assert!(matches!(
+            sources.origin("system.min-intersection-survivors"),
+            Some(Location::File { .. })
+        ));
+
+        // without any file
+        let mut sources = ConfigSources::default();
+        sources
+            .apply_env(env(&[(
+                "NTPD_PEERS",
+                r#"[{ addr = "example.com", iburst = true }]"#,
+            )]))
+            .unwrap();
+        let config = sources.config().unwrap();
+        assert_eq!(config.peers.len(), 1);
+
+        // errors name the variable
+        let mut sources = ConfigSources::default();
+        match sources.apply_env(env(&[("NTPD_SYSTEM__PANIC_THRESHOLD", "5 fortnights")])) {
+            Err(ConfigError::Env { name, .. }) => assert_eq!(name, "NTPD_SYSTEM__PANIC_THRESHOLD"),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::net::{AddrParseError, IpAddr};
 use thiserror::Error;
 
@@ -36,6 +36,39 @@ impl std::str::FromStr for IpSubnet {
     }
 }
 
+impl IpSubnet {
+    /// Whether every address in `other` is also part of this subnet
+    pub fn contains(&self, other: &IpSubnet) -> bool {
+        let (this, that, bits) = match (self.addr, other.addr) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) as u128, u32::from(b) as u128, 32),
+            (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a), u128::from(b), 128),
+            _ => return false,
+        };
+
+        if self.mask > other.mask {
+            return false;
+        }
+
+        let shift = bits - self.mask as u32;
+        this.checked_shr(shift).unwrap_or(0) == that.checked_shr(shift).unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for IpSubnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.mask)
+    }
+}
+
+impl Serialize for IpSubnet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 impl<'de> Deserialize<'de> for IpSubnet {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -54,5 +87,22 @@ mod tests {
     fn test_subnet_parsing() {
         let a = "0.0.0.0/0".parse::<IpSubnet>().unwrap();
         assert_eq!(a.mask, 0);
+
+        let b = "fe80::/10".parse::<IpSubnet>().unwrap();
+        assert_eq!(b.to_string().parse::<IpSubnet>().unwrap(), b);
+    }
+
+    #[test]
+    fn test_subnet_contains() {
+        let subnet = |s: &str| s.parse::<IpSubnet>().unwrap();
+
+        assert!(subnet("0.0.0.0/0").contains(&subnet("10.1.0.0/16")));
+        assert!(subnet("10.0.0.0/8").contains(&subnet("10.1.0.0/16")));
+        assert!(subnet("10.0.0.0/8").contains(&subnet("10.0.0.0/8")));
+        assert!(!subnet("10.1.0.0/16").contains(&subnet("10.0.0.0/8")));
+        assert!(!subnet("10.0.0.0/8").contains(&subnet("11.0.0.0/16")));
+        assert!(subnet("192.168.1.0/32").contains(&subnet("192.168.1.0/32")));
+        assert!(subnet("fe80::/10").contains(&subnet("fe80::1/128")));
+        assert!(!subnet("0.0.0.0/0").contains(&subnet("::/0")));
     }
 }
@@ -0,0 +1,590 @@
+use std::path::Path;
+
+use ntp_proto::NtpDuration;
+
+use crate::list_files::read_subnets;
+
+use super::{
+    sources::{ConfigSources, Location},
+    Config, ConfigError, PeerConfig,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The daemon runs, but probably not as intended
+    Warning,
+    /// The daemon can not run as intended
+    Error,
+}
+
+/// A problem with a configuration that parsed successfully
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    /// Where in the configuration the problem is, e.g. `system.accumulated-threshold` or
+    /// `servers[1].allowlist`. Empty when it concerns the configuration as a whole.
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(key: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: Severity::Error,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warning(key: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: Severity::Warning,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks that go beyond what parsing the configuration already enforces
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+
+        let peer_count: usize = self
+            .peers
+            .iter()
+            .map(|peer| match peer {
+                PeerConfig::Standard(_) => 1,
+                PeerConfig::Pool(pool) => pool.max_peers,
+            })
+            .sum();
+
+        if self.peers.is_empty() {
+            issues.push(ConfigIssue::warning(
+                "peers",
+                "No peers configured. Daemon will not do anything.",
+            ));
+        } else if peer_count < self.system.min_intersection_survivors {
+            issues.push(ConfigIssue::error(
+                "system.min-intersection-survivors",
+                "Fewer peers configured than are required to agree on the current time. Daemon will not do anything.",
+            ));
+        }
+
+        // the same limits as for changes through the configuration socket
+        for (key, value) in [
+            (
+                "system.min-intersection-survivors",
+                self.system.min_intersection_survivors,
+            ),
+            (
+                "system.min-cluster-survivors",
+                self.system.min_cluster_survivors,
+            ),
+        ] {
+            if value == 0 {
+                issues.push(ConfigIssue::error(key, "must be at least 1"));
+            }
+        }
+
+        if self.system.distance_threshold <= NtpDuration::ZERO {
+            issues.push(ConfigIssue::error(
+                "system.distance-threshold",
+                "must be positive",
+            ));
+        }
+
+        if let Some(accumulated) = self.system.accumulated_threshold {
+            let panic = self.system.panic_threshold;
+            if [panic.forward, panic.backward]
+                .iter()
+                .flatten()
+                .any(|threshold| accumulated < *threshold)
+            {
+                issues.push(ConfigIssue::error(
+                    "system.accumulated-threshold",
+                    "the accumulated threshold is smaller than the panic threshold",
+                ));
+            }
+        }
+
+        for (section, mode) in [
+            ("observe", self.observe.mode),
+            ("configure", self.configure.mode),
+        ] {
+            if mode > 0o777 {
+                issues.push(ConfigIssue::error(
+                    format!("{section}.mode"),
+                    format!("{mode:#o} is not a valid socket permission mode"),
+                ));
+            }
+        }
+
+        if self.configure.path.is_some() && self.configure.mode & 0o002 != 0 {
+            issues.push(ConfigIssue::warning(
+                "configure.mode",
+                "the configuration socket is writable by all users",
+            ));
+        }
+
+        for (i, server) in self.servers.iter().enumerate() {
+            if self.servers[..i]
+                .iter()
+                .any(|other| other.same_listener(server))
+            {
+                let place = match &server.interface {
+                    Some(interface) => format!("port {} of {interface}", server.addr.port()),
+                    None => server.addr.to_string(),
+                };
+                issues.push(ConfigIssue::error(
+                    format!("servers[{i}].addr"),
+                    format!("there already is a server on {place}"),
+                ));
+            }
+
+            for allowed in server.allowlist.subnets() {
+                if let Some(denied) = server
+                    .denylist
+                    .subnets()
+                    .iter()
+                    .find(|denied| denied.contains(allowed))
+                {
+                    issues.push(ConfigIssue::error(
+                        format!("servers[{i}].allowlist"),
+                        format!(
+                            "allowlist entry {allowed} has no effect, it is covered by denylist entry {denied}"
+                        ),
+                    ));
+                }
+            }
+
+            let rules = server.access.rules();
+            for (j, rule) in rules.iter().enumerate() {
+                if let Some(earlier) = rules[..j]
+                    .iter()
+                    .find(|earlier| earlier.subnet.contains(&rule.subnet))
+                {
+                    issues.push(ConfigIssue::warning(
+                        format!("servers[{i}].access"),
+                        format!(
+                            "access rule for {} never matches, it is covered by the earlier rule for {}",
+                            rule.subnet, earlier.subnet
+                        ),
+                    ));
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Checks of the files and directories the configuration refers to, separate from `validate`
+    /// because they touch the filesystem. Parsing long list files takes a while, so the checks run
+    /// on a blocking thread.
+    pub async fn validate_files(&self) -> Vec<ConfigIssue> {
+        let sockets: Vec<_> = [
+            ("observe", &self.observe.path),
+            ("configure", &self.configure.path),
+        ]
+        .into_iter()
+        .filter_map(|(section, path)| Some((section, path.clone()?)))
+        .collect();
+
+        let mut lists = vec![];
+        for (i, server) in self.servers.iter().enumerate() {
+            for (key, path) in [
+                ("allowlist-file", &server.allowlist_file),
+                ("denylist-file", &server.denylist_file),
+            ] {
+                if let Some(path) = path {
+                    lists.push((format!("servers[{i}].{key}"), path.clone()));
+                }
+            }
+        }
+
+        let checks = tokio::task::spawn_blocking(move || {
+            let mut issues = vec![];
+
+            for (section, path) in sockets {
+                if let Some(issue) = check_socket_path(section, &path) {
+                    issues.push(issue);
+                }
+            }
+
+            for (key, path) in lists {
+                if let Err(e) = read_subnets(&path) {
+                    issues.push(ConfigIssue::error(key, e.to_string()));
+                }
+            }
+
+            issues
+        });
+
+        checks.await.unwrap_or_default()
+    }
+}
+
+fn check_socket_path(section: &str, path: &Path) -> Option<ConfigIssue> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => return None,
+    };
+
+    match std::fs::metadata(parent) {
+        Ok(metadata) if metadata.is_dir() => None,
+        Ok(_) => Some(ConfigIssue::error(
+            format!("{section}.path"),
+            format!("{} is not a directory", parent.display()),
+        )),
+        Err(e) => Some(ConfigIssue::error(
+            format!("{section}.path"),
+            format!("cannot use directory {}: {e}", parent.display()),
+        )),
+    }
+}
+
+/// Find the line and column (both starting at 1) of a key like `system.spike-threshold` or
+/// `servers[1].allowlist` in the source of a configuration file. This only understands the
+/// plain table syntax, and gives up for anything else.
+fn locate(source: &str, key: &str) -> Option<(usize, usize)> {
+    let (table, field) = match key.rsplit_once('.') {
+        Some((table, field)) => (Some(table), field),
+        None => (None, key),
+    };
+
+    let mut lines = source.lines().enumerate();
+
+    if let Some(table) = table {
+        let (name, index) = match table.split_once('[') {
+            Some((name, index)) => (name, index.trim_end_matches(']').parse::<usize>().ok()),
+            None => (table, None),
+        };
+        // both `[[servers]]` and `[[server]]` are accepted
+        let singular = name.strip_suffix('s').unwrap_or(name);
+
+        let mut remaining = index.unwrap_or(0);
+        loop {
+            let (_, line) = lines.next()?;
+            let header = line.trim();
+            let matches = match index {
+                Some(_) => header == format!("[[{name}]]") || header == format!("[[{singular}]]"),
+                None => header == format!("[{name}]"),
+            };
+            if matches {
+                if remaining == 0 {
+                    break;
+                }
+                remaining -= 1;
+            }
+        }
+    }
+
+    for (number, line) in lines {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            return None;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix(field) {
+            if rest.trim_start().starts_with('=') {
+                return Some((number + 1, line.len() - trimmed.len() + 1));
+            }
+        }
+    }
+
+    None
+}
+
+/// Check a configuration file, and the files it includes, and print every problem found. Returns
+/// the exit code: zero when there are no errors, `exitcode::CONFIG` when a file can not be read or
+/// parsed, and `exitcode::DATAERR` when the configuration parses but has errors.
+pub async fn run(file: Option<&Path>) -> i32 {
+    let sources = ConfigSources::from_first_file(file)
+        .await
+        .and_then(|sources| {
+            let mut sources = sources.unwrap_or_default();
+            sources.apply_env(std::env::vars())?;
+            Ok(sources)
+        });
+
+    let sources = match sources {
+        Ok(sources) if sources.is_empty() => {
+            eprintln!("error: no configuration file found");
+            return exitcode::CONFIG;
+        }
+        Ok(sources) => sources,
+        Err(e) => {
+            print_error(file, e);
+            return exitcode::CONFIG;
+        }
+    };
+
+    let config = match sources.config() {
+        Ok(config) => config,
+        Err(e) => {
+            print_error(file, e);
+            return exitcode::CONFIG;
+        }
+    };
+
+    // settings that were not set anywhere are reported on the main file
+    let main = match sources.files.first() {
+        Some((path, _)) => path.display().to_string(),
+        None => "environment".to_string(),
+    };
+
+    let mut issues = config.validate();
+    issues.extend(config.validate_files().await);
+
+    for issue in issues.iter() {
+        let severity = match issue.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        let (source, position) = match sources.origin(&issue.key) {
+            Some(Location::File {
+                path,
+                contents,
+                key,
+            }) => (path.display().to_string(), locate(contents, &key)),
+            Some(Location::Env(name)) => (name.to_string(), None),
+            None => (main.clone(), None),
+        };
+
+        match position {
+            Some((line, column)) => eprintln!(
+                "{}:{}:{}: {}: {}",
+                source, line, column, severity, issue.message
+            ),
+            None if issue.key.is_empty() => {
+                eprintln!("{}: {}: {}", source, severity, issue.message)
+            }
+            None => eprintln!(
+                "{}: {}: {} ({})",
+                source, severity, issue.message, issue.key
+            ),
+        }
+    }
+
+    if issues.iter().any(|issue| issue.severity == Severity::Error) {
+        exitcode::DATAERR
+    } else {
+        println!("{main}: configuration is valid");
+        exitcode::OK
+    }
+}
+
+fn print_error(file: Option<&Path>, error: ConfigError) {
+    match error {
+        ConfigError::Parse { path, error } => {
+            let message = error.to_string();
+            // the position is already part of our output
+            let message = match message.rsplit_once(" at line ") {
+                Some((message, _)) => message.to_string(),
+                None => message,
+            };
+
+            match error.line_col() {
+                Some((line, column)) => eprintln!(
+                    "{}:{}:{}: error: {}",
+                    path.display(),
+                    line + 1,
+                    column + 1,
+                    message
+                ),
+                None => eprintln!("{}: error: {}", path.display(), message),
+            }
+        }
+        ConfigError::Include { path, reason } => eprintln!("{}: error: {}", path.display(), reason),
+        ConfigError::Env { name, message } => eprintln!("{name}: error: {message}"),
+        ConfigError::Io(e) => match file {
+            Some(file) => eprintln!("{}: error: {}", file.display(), e),
+            None => eprintln!("error: {e}"),
+        },
+        other => eprintln!("error: {other}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue_keys(source: &str) -> Vec<String> {
+        let config: Config = toml::from_str(source).unwrap();
+        config
+            .validate()
+            .into_iter()
+            .map(|issue| issue.key)
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            issue_keys(
+                r#"
+                [[peers]]
+                addr = "0.pool.ntp.org"
+                mode = "Pool"
+                max_peers = 4
+                "#
+            ),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(issue_keys("peers = []"), ["peers"]);
+
+        assert_eq!(
+            issue_keys(
+                r#"
+                [[peers]]
+                addr = "example.com"
+
+                [system]
+                panic-threshold = 100
+                accumulated-threshold = 10
+                "#
+            ),
+            [
+                "system.min-intersection-survivors",
+                "system.accumulated-threshold"
+            ]
+        );
+
+        assert_eq!(
+            issue_keys(
+                r#"
+                [[peers]]
+                addr = "example.com"
+
+                [system]
+                min-intersection-survivors = 0
+                min-cluster-survivors = 0
+                distance-threshold = 0
+                "#
+            ),
+            [
+                "system.min-intersection-survivors",
+                "system.min-cluster-survivors",
+                "system.distance-threshold"
+            ]
+        );
+
+        assert_eq!(
+            issue_keys(
+                r#"
+                [[peers]]
+                addr = "example.com"
+
+                [[server]]
+                addr = "0.0.0.0:123"
+                allowlist = ["10.1.0.0/16", "192.168.0.0/16"]
+                allowlist-action = "Ignore"
+                denylist = ["10.0.0.0/8"]
+                denylist-action = "Deny"
+
+                [[server]]
+                addr = "0.0.0.0:123"
+                denylist-file = "/does/not/exist/denylist"
+                denylist-action = "Ignore"
+                access = [
+                    { subnet = "10.0.0.0/8", action = "Serve" },
+                    { subnet = "10.1.0.0/16", action = "Ignore" },
+                ]
+
+                [system]
+                min-intersection-survivors = 1
+
+                [observe]
+                path = "/does/not/exist/observe"
+
+                [configure]
+                mode = 0o1777
+                "#
+            ),
+            [
+                "configure.mode",
+                "servers[0].allowlist",
+                "servers[1].addr",
+                "servers[1].access"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_files() {
+        let config: Config = toml::from_str(
+            r#"
+            [[peers]]
+            addr = "example.com"
+
+            [[server]]
+            addr = "0.0.0.0:123"
+            denylist-file = "/does/not/exist/denylist"
+            denylist-action = "Ignore"
+
+            [observe]
+            path = "/does/not/exist/observe"
+
+            [configure]
+            path = "/tmp/configure"
+            "#,
+        )
+        .unwrap();
+
+        // none of these are checked without touching the filesystem
+        assert!(config
+            .validate()
+            .iter()
+            .all(|issue| issue.key.starts_with("system.")));
+
+        let keys: Vec<_> = config
+            .validate_files()
+            .await
+            .into_iter()
+            .map(|issue| issue.key)
+            .collect();
+        assert_eq!(keys, ["observe.path", "servers[0].denylist-file"]);
+    }
+
+    #[test]
+    fn test_locate() {
+        let source = r#"log-filter = "info"
+
+[[server]]
+addr = "0.0.0.0:123"
+
+[[server]]
+  addr = "[::]:123"
+  allowlist = ["::/0"]
+
+[system]
+accumulated-threshold = 10
+"#;
+
+        assert_eq!(locate(source, "log-filter"), Some((1, 1)));
+        assert_eq!(locate(source, "servers[0].addr"), Some((4, 1)));
+        assert_eq!(locate(source, "servers[1].allowlist"), Some((8, 3)));
+        assert_eq!(locate(source, "servers[0].allowlist"), None);
+        assert_eq!(
+            locate(source, "system.accumulated-threshold"),
+            Some((11, 1))
+        );
+        assert_eq!(locate(source, "observe.path"), None);
+    }
+
+    #[test]
+    fn test_unknown_field_diagnostics() {
+        let error = toml::from_str::<Config>(
+            "[[peers]]\naddr = \"example.com\"\n[system]\npanic-threshold = { forwards = 10 }",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("`forward`"), "{error}");
+
+        let error = toml::from_str::<Config>("[[server]]\naddr = \"0.0.0.0:123\"\ndenylst = []")
+            .unwrap_err();
+        assert!(error.to_string().contains("`denylist`"), "{error}");
+        assert_eq!(error.line_col().map(|(line, _)| line), Some(0));
+    }
+}
@@ -173,6 +173,8 @@ impl BitTree {
 pub struct IpFilter {
     ipv4_filter: BitTree,
     ipv6_filter: BitTree,
+    /// The subnets the filter was created from
+    subnets: Vec<IpSubnet>,
 }
 
 impl IpFilter {
@@ -197,6 +199,7 @@ impl IpFilter {
         IpFilter {
             ipv4_filter: BitTree::create(ipv4list.as_mut_slice()),
             ipv6_filter: BitTree::create(ipv6list.as_mut_slice()),
+            subnets: subnets.to_vec(),
         }
     }
 
@@ -206,6 +209,16 @@ impl IpFilter {
         IpFilter {
             ipv4_filter: BitTree::create(&mut temp_v4),
             ipv6_filter: BitTree::create(&mut temp_v6),
+            subnets: vec![
+                IpSubnet {
+                    addr: Ipv4Addr::UNSPECIFIED.into(),
+                    mask: 0,
+                },
+                IpSubnet {
+                    addr: Ipv6Addr::UNSPECIFIED.into(),
+                    mask: 0,
+                },
+            ],
         }
     }
 
@@ -215,9 +228,15 @@ impl IpFilter {
         IpFilter {
             ipv4_filter: BitTree::create(&mut temp_v4),
             ipv6_filter: BitTree::create(&mut temp_v6),
+            subnets: vec![],
         }
     }
 
+    /// The subnets this filter was created from
+    pub fn subnets(&self) -> &[IpSubnet] {
+        &self.subnets
+    }
+
     /// Check whether a given ip address is contained in the filter.
     /// Complexity: O(1)
     pub fn is_in(&self, addr: &IpAddr) -> bool {
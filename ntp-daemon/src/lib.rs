@@ -2,18 +2,24 @@
 
 pub mod config;
 mod ipfilter;
+mod list_files;
 pub mod observer;
 mod peer;
 mod peer_manager;
+mod ratelimit;
 mod server;
+mod server_stats;
 pub mod sockets;
 mod system;
 pub mod tracing;
 
-pub use config::dynamic::ConfigUpdate;
+pub use config::dynamic::{
+    AddPeer, ConfigRequest, ConfigResponse, ConfigUpdate, RemovePeer, RemoveServer, ServerArgs,
+};
 pub use config::Config;
-pub use observer::{ObservablePeerState, ObservableState};
+pub use observer::{ObservablePeerState, ObservableServerState, ObservableState};
 pub use peer_manager::Peers;
+pub use server_stats::{ObservableClient, ServerStatistics};
 pub use system::spawn;
 //#[cfg(fuzz)]
 pub use ipfilter::fuzz::fuzz_ipfilter;
@@ -0,0 +1,383 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use thiserror::Error;
+use tokio::sync::{watch, Notify};
+use tracing::{error, info};
+
+use crate::{
+    config::{
+        subnet::{IpSubnet, SubnetParseError},
+        AccessRules, FilterAction, ServerConfig,
+    },
+    ipfilter::IpFilter,
+};
+
+/// How often the list files of a server are checked for changes
+pub const LIST_FILE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum ListFileError {
+    #[error("cannot read {}: {error}", .path.display())]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("{}:{line}: {error}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        error: SubnetParseError,
+    },
+}
+
+/// Parse a list with one subnet per line. An address without a prefix length is a subnet with
+/// just that address. Everything after a `#` is a comment, and empty lines are skipped. On
+/// failure, returns the number of the first line that could not be parsed.
+pub fn parse_subnets(contents: &str) -> Result<Vec<IpSubnet>, (usize, SubnetParseError)> {
+    let mut subnets = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let entry = match line.split_once('#') {
+            Some((entry, _comment)) => entry,
+            None => line,
+        }
+        .trim();
+
+        if entry.is_empty() {
+            continue;
+        }
+
+        let subnet = if entry.contains('/') {
+            entry.parse()
+        } else {
+            entry
+                .parse::<IpAddr>()
+                .map(|addr| IpSubnet {
+                    addr,
+                    mask: if addr.is_ipv4() { 32 } else { 128 },
+                })
+                .map_err(SubnetParseError::from)
+        };
+
+        subnets.push(subnet.map_err(|error| (index + 1, error))?);
+    }
+
+    Ok(subnets)
+}
+
+/// Read a list of subnets from a file, see [`parse_subnets`]
+pub fn read_subnets(path: &Path) -> Result<Vec<IpSubnet>, ListFileError> {
+    let contents = std::fs::read_to_string(path).map_err(|error| ListFileError::Io {
+        path: path.to_owned(),
+        error,
+    })?;
+
+    parse_subnets(&contents).map_err(|(line, error)| ListFileError::Parse {
+        path: path.to_owned(),
+        line,
+        error,
+    })
+}
+
+#[derive(Debug)]
+struct ListFile {
+    path: PathBuf,
+    /// Modification time and size of the file when it was last read, to notice changes
+    version: Option<(SystemTime, u64)>,
+    /// `None` until the file was read successfully
+    subnets: Option<Vec<IpSubnet>>,
+}
+
+impl ListFile {
+    fn new(path: &Path) -> Self {
+        ListFile {
+            path: path.to_owned(),
+            version: None,
+            subnets: None,
+        }
+    }
+
+    /// Read the file when it changed since it was last read, or always when `force` is set. A
+    /// file that can not be read or parsed keeps its previous subnets. Returns whether the
+    /// subnets changed.
+    async fn refresh(&mut self, force: bool) -> bool {
+        let version = tokio::fs::metadata(&self.path)
+            .await
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+
+        if !force && version == self.version {
+            return false;
+        }
+
+        // also remember the version of a broken file, so its errors are reported only once
+        self.version = version;
+
+        let path = self.path.clone();
+        let result = match tokio::task::spawn_blocking(move || read_subnets(&path)).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!(error = ?e, "list file reader panicked");
+                return false;
+            }
+        };
+
+        match result {
+            Ok(subnets) => {
+                if self.subnets.as_ref() == Some(&subnets) {
+                    return false;
+                }
+
+                info!(path = ?self.path, subnets = subnets.len(), "read list file");
+                self.subnets = Some(subnets);
+                true
+            }
+            Err(e) => {
+                match self.subnets {
+                    Some(_) => error!("{}, keeping the previous list", e),
+                    None => error!("{}, not serving any clients until it is fixed", e),
+                }
+                false
+            }
+        }
+    }
+}
+
+/// The allow and deny list files of a server
+#[derive(Debug)]
+pub struct ListFiles {
+    allowlist: Option<ListFile>,
+    denylist: Option<ListFile>,
+}
+
+impl ListFiles {
+    /// The list files of `config`. Nothing is read until the first [`ListFiles::refresh`].
+    pub fn new(config: &ServerConfig) -> Self {
+        ListFiles {
+            allowlist: config.allowlist_file.as_deref().map(ListFile::new),
+            denylist: config.denylist_file.as_deref().map(ListFile::new),
+        }
+    }
+
+    /// The list files of `config`, all read once
+    pub async fn read(config: &ServerConfig) -> Self {
+        let mut files = Self::new(config);
+        files.refresh(true).await;
+        files
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allowlist.is_none() && self.denylist.is_none()
+    }
+
+    /// Whether these are the list files of `config`
+    fn is_for(&self, config: &ServerConfig) -> bool {
+        self.allowlist.as_ref().map(|file| file.path.as_path()) == config.allowlist_file.as_deref()
+            && self.denylist.as_ref().map(|file| file.path.as_path())
+                == config.denylist_file.as_deref()
+    }
+
+    /// Read the files that changed, or all of them when `force` is set. Returns whether any of
+    /// the lists changed.
+    pub async fn refresh(&mut self, force: bool) -> bool {
+        let mut changed = false;
+        for file in self.allowlist.iter_mut().chain(self.denylist.iter_mut()) {
+            changed |= file.refresh(force).await;
+        }
+        changed
+    }
+
+    /// The config the server task works with: the lists of `config` extended with the subnets
+    /// from the list files. While a list file was never read successfully, no clients are served.
+    pub fn apply(&self, config: &ServerConfig) -> ServerConfig {
+        let files = self.allowlist.iter().chain(self.denylist.iter());
+        if files.clone().any(|file| file.subnets.is_none()) {
+            return ServerConfig {
+                allowlist: IpFilter::none(),
+                allowlist_action: FilterAction::Ignore,
+                access: AccessRules::default(),
+                ..config.clone()
+            };
+        }
+
+        ServerConfig {
+            allowlist: combine(&config.allowlist, self.allowlist.as_ref()),
+            denylist: combine(&config.denylist, self.denylist.as_ref()),
+            ..config.clone()
+        }
+    }
+}
+
+fn combine(filter: &IpFilter, file: Option<&ListFile>) -> IpFilter {
+    match file.and_then(|file| file.subnets.as_ref()) {
+        Some(subnets) => {
+            let combined: Vec<_> = filter.subnets().iter().chain(subnets).cloned().collect();
+            IpFilter::new(&combined)
+        }
+        None => filter.clone(),
+    }
+}
+
+/// Keeps the config of a running server task in line with the config of the server and its list
+/// files. The server config arrives on `configs`, and the config with the combined filters is
+/// sent on `filters`. The list files are checked every `poll_interval`, and are all read again
+/// when `reload` is notified.
+pub async fn maintain_filters(
+    mut files: ListFiles,
+    mut configs: watch::Receiver<Arc<ServerConfig>>,
+    filters: watch::Sender<Arc<ServerConfig>>,
+    reload: Arc<Notify>,
+    poll_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(poll_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let (config_changed, mut force) = tokio::select! {
+            result = configs.changed() => match result {
+                Ok(()) => (true, false),
+                // the server was removed
+                Err(_) => break,
+            },
+            () = reload.notified() => (false, true),
+            _ = interval.tick(), if !files.is_empty() => (false, false),
+        };
+
+        let config = configs.borrow_and_update().clone();
+        if !files.is_for(&config) {
+            files = ListFiles::new(&config);
+            force = true;
+        }
+
+        let lists_changed = files.refresh(force).await;
+        if config_changed || lists_changed {
+            // the task keeps the receiver alive for as long as it runs
+            filters.send(Arc::new(files.apply(&config))).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subnets() {
+        let contents =
+            "# blocked networks\n\n10.0.0.0/8\n  192.168.1.1  # a single host\nfe80::/10\n";
+        let subnets = parse_subnets(contents).unwrap();
+        assert_eq!(
+            subnets,
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "192.168.1.1/32".parse().unwrap(),
+                "fe80::/10".parse().unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            parse_subnets("10.0.0.0/8\n# comment\n10.0.0.0/40\n"),
+            Err((3, SubnetParseError::InvalidMask))
+        );
+        assert!(matches!(
+            parse_subnets("10.0.0.0/8\nexample.com\n"),
+            Err((2, SubnetParseError::InvalidIp(_)))
+        ));
+    }
+
+    #[test]
+    fn test_large_list() {
+        let contents: String = (0..131072u32)
+            .map(|i| format!("{}/32\n", std::net::Ipv4Addr::from(0x0a00_0000 | (i * 7))))
+            .collect();
+
+        let subnets = parse_subnets(&contents).unwrap();
+        assert_eq!(subnets.len(), 131072);
+
+        let filter = IpFilter::new(&subnets);
+        assert!(filter.is_in(&"10.0.0.7".parse().unwrap()));
+        assert!(!filter.is_in(&"10.0.0.8".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_maintain_filters() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("ntp-test-denylist-1");
+        std::fs::write(&path, "127.0.0.0/8\n").unwrap();
+
+        let config = ServerConfig {
+            denylist_file: Some(path.clone()),
+            denylist_action: FilterAction::Deny,
+            ..ServerConfig::from_addr("127.0.0.1:123".parse().unwrap())
+        };
+
+        let mut files = ListFiles::new(&config);
+        assert!(files.refresh(true).await);
+        let applied = files.apply(&config);
+        assert!(applied.denylist.is_in(&"127.0.0.1".parse().unwrap()));
+        assert!(!applied.denylist.is_in(&"10.0.0.1".parse().unwrap()));
+
+        // a broken file keeps the previous list
+        std::fs::write(&path, "127.0.0.0/8\n10.0.0.0/99\n").unwrap();
+        assert!(!files.refresh(true).await);
+        assert!(files
+            .apply(&config)
+            .denylist
+            .is_in(&"127.0.0.1".parse().unwrap()));
+
+        let (config_sender, config_receiver) = watch::channel(Arc::new(config));
+        let (filter_sender, mut filter_receiver) = watch::channel(Arc::new(applied));
+        let reload = Arc::new(Notify::new());
+        let task = tokio::spawn(maintain_filters(
+            files,
+            config_receiver,
+            filter_sender,
+            reload.clone(),
+            Duration::from_secs(3600),
+        ));
+
+        std::fs::write(&path, "127.0.0.0/8\n10.0.0.0/8\n").unwrap();
+        reload.notify_one();
+        filter_receiver.changed().await.unwrap();
+        assert!(filter_receiver
+            .borrow_and_update()
+            .denylist
+            .is_in(&"10.0.0.1".parse().unwrap()));
+
+        // dropping the file from the config drops its subnets
+        let config = ServerConfig {
+            denylist_file: None,
+            ..(**config_sender.borrow()).clone()
+        };
+        config_sender.send(Arc::new(config)).unwrap();
+        filter_receiver.changed().await.unwrap();
+        assert!(!filter_receiver
+            .borrow_and_update()
+            .denylist
+            .is_in(&"10.0.0.1".parse().unwrap()));
+
+        drop(config_sender);
+        task.await.unwrap();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_file_serves_nobody() {
+        let config = ServerConfig {
+            allowlist_file: Some("/nonexistent/ntp-test-allowlist".into()),
+            access: AccessRules::new(vec![]),
+            ..ServerConfig::from_addr("127.0.0.1:123".parse().unwrap())
+        };
+
+        let mut files = ListFiles::new(&config);
+        assert!(!files.refresh(true).await);
+        let applied = files.apply(&config);
+        assert!(!applied.allowlist.is_in(&"127.0.0.1".parse().unwrap()));
+        assert_eq!(applied.allowlist_action, FilterAction::Ignore);
+    }
+}
@@ -1,16 +1,36 @@
 #![forbid(unsafe_code)]
 
 use clap::Parser;
-use ntp_daemon::config::{CmdArgs, Config};
+use ntp_daemon::config::{reload::ReloadConfig, CmdArgs, Config, LogSettings};
 use std::{error::Error, sync::Arc};
+use tokio::sync::RwLock;
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = CmdArgs::parse();
+
+    if args.validate {
+        let exit_code = ntp_daemon::config::validate::run(args.config.as_deref()).await;
+        std::process::exit(exit_code);
+    }
+
+    if let Some(format) = args.print_config {
+        let exit_code = ntp_daemon::config::print_config(&args, format).await;
+        std::process::exit(exit_code);
+    }
+
+    if args.show_origins {
+        let exit_code = ntp_daemon::config::sources::print(&args).await;
+        std::process::exit(exit_code);
+    }
+
     let has_log_override = args.log_filter.is_some();
     let has_format_override = args.log_format.is_some();
+    // setting up tracing consumes the log settings, keep them to report the effective configuration
+    let log_filter_override = args.log_filter.as_ref().map(|filter| filter.to_string());
+    let log_format_override = args.log_format.clone();
     let log_filter = args
         .log_filter
         // asserts that the arc is not shared. There is no reason it would be,
@@ -23,7 +43,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let finish_tracing_init =
         ntp_daemon::tracing::init(log_filter, args.log_format.unwrap_or_default());
 
-    let mut config = match Config::from_args(args.config, args.peers, args.servers).await {
+    let mut config = match Config::from_args(
+        args.config.as_ref(),
+        args.peers.clone(),
+        args.servers.clone(),
+    )
+    .await
+    {
         Ok(c) => c,
         Err(e) => {
             // print to stderr because tracing is not yet setup
@@ -32,6 +58,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
+    let effective_log_filter =
+        log_filter_override.or_else(|| config.log_filter.as_ref().map(ToString::to_string));
+
     // Sentry has a guard we need to keep alive, so store it.
     // The compiler will optimize this away when not using sentry.
     let tracing_state =
@@ -44,19 +73,55 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         };
 
+    config.log_filter = effective_log_filter.map(EnvFilter::new);
+    if let Some(format) = log_format_override {
+        config.log_format = format;
+    }
+
     // Warn/error if the config is unreasonable. We do this after finishing
     // tracing setup to ensure logging is fully configured.
     config.check();
+    config.check_files().await;
 
     debug!("Configuration loaded, spawning daemon jobs");
     let (main_loop_handle, channels) =
         ntp_daemon::spawn(config.system, &config.peers, &config.servers).await?;
 
-    ntp_daemon::observer::spawn(&config.observe, channels.peers, channels.system).await;
+    let log_settings = Arc::new(RwLock::new(LogSettings::from(&config)));
+
+    ntp_daemon::observer::spawn(
+        &config,
+        log_settings.clone(),
+        channels.peers.clone(),
+        channels.system,
+        channels.config.clone(),
+    )
+    .await;
+
+    let reload_config = ReloadConfig {
+        file: args.config,
+        peers: args.peers,
+        servers: args.servers,
+        has_log_override,
+        has_format_override,
+        observe: config.observe,
+        configure: config.configure.clone(),
+    };
+
+    ntp_daemon::config::reload::spawn(
+        reload_config,
+        channels.config.clone(),
+        log_settings.clone(),
+        channels.peers.clone(),
+        tracing_state.reload_handle.clone(),
+    )
+    .await;
 
     ntp_daemon::config::dynamic::spawn(
         config.configure,
         channels.config,
+        log_settings,
+        channels.peers,
         tracing_state.reload_handle,
     )
     .await;
@@ -1,10 +1,18 @@
+use crate::config::{Config, ConfigureConfig, LogSettings, ObserveConfig, PeerConfig};
+use crate::server_stats::{ObservableClient, ServerStatistics};
 use crate::sockets::create_unix_socket;
 use crate::Peers;
-use ntp_proto::{NtpClock, PeerStatistics, Reach, ReferenceId, SystemSnapshot};
+use ntp_proto::{
+    IgnoreStatistics, NtpClock, NtpDuration, PeerStatistics, Reach, ReferenceId, SystemConfig,
+    SystemSnapshot,
+};
+use std::net::SocketAddr;
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
+use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tracing::error;
+use tracing_subscriber::EnvFilter;
 
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +20,12 @@ use serde::{Deserialize, Serialize};
 pub struct ObservableState {
     pub system: SystemSnapshot,
     pub peers: Vec<ObservablePeerState>,
+    /// Absent when talking to an older daemon
+    #[serde(default)]
+    pub servers: Vec<ObservableServerState>,
+    /// The configuration the daemon currently runs with. Absent when talking to an older daemon
+    #[serde(default)]
+    pub config: Option<Config>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,22 +33,91 @@ pub enum ObservablePeerState {
     Nothing,
     Observable {
         statistics: PeerStatistics,
+        /// Offset before the configured static offset and asymmetry correction. Absent when
+        /// talking to an older daemon
+        #[serde(default)]
+        raw_offset: NtpDuration,
         reachability: Reach,
         uptime: std::time::Duration,
         poll_interval: std::time::Duration,
         peer_id: ReferenceId,
         address: String,
+        /// Absent when talking to an older daemon
+        #[serde(default)]
+        ignored: IgnoreStatistics,
     },
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ObservableServerState {
+    /// For a server on an interface, only the port is meaningful
+    pub address: SocketAddr,
+    /// The interface the server serves on, absent for a server on a single address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    pub statistics: ServerStatistics,
+    /// The clients that were seen most recently, the most recent first
+    pub clients: Vec<ObservableClient>,
+}
+
+/// The settings that are reported as they were at startup, because the sockets are not reopened
+/// at runtime. The other settings are taken from the running daemon instead.
+#[derive(Debug, Clone)]
+struct ConfigTemplate {
+    log: Arc<RwLock<LogSettings>>,
+    observe: ObserveConfig,
+    configure: ConfigureConfig,
+}
+
+impl ConfigTemplate {
+    fn new(config: &Config, log: Arc<RwLock<LogSettings>>) -> Self {
+        ConfigTemplate {
+            log,
+            observe: config.observe.clone(),
+            configure: config.configure.clone(),
+        }
+    }
+
+    async fn current<C: NtpClock>(
+        &self,
+        peers: &Peers<C>,
+        system_config: &RwLock<SystemConfig>,
+    ) -> Config {
+        // a pool shows up once for every peer it resolved to
+        let mut peer_configs: Vec<PeerConfig> = vec![];
+        for (config, _) in peers.peer_configs() {
+            if !peer_configs.contains(config) {
+                peer_configs.push(config.clone());
+            }
+        }
+
+        let log = self.log.read().await.clone();
+
+        Config {
+            peers: peer_configs,
+            servers: peers.server_configs().cloned().collect(),
+            system: *system_config.read().await,
+            log_filter: log.filter.as_deref().map(EnvFilter::new),
+            log_format: log.format,
+            // never hand out the sentry credentials
+            #[cfg(feature = "sentry")]
+            sentry: Default::default(),
+            observe: self.observe.clone(),
+            configure: self.configure.clone(),
+        }
+    }
+}
+
 pub async fn spawn<C: NtpClock + Sync + Send + 'static>(
-    config: &crate::config::ObserveConfig,
-    peers_reader: Arc<tokio::sync::RwLock<Peers<C>>>,
-    system_reader: Arc<tokio::sync::RwLock<SystemSnapshot>>,
+    config: &Config,
+    log_settings: Arc<RwLock<LogSettings>>,
+    peers_reader: Arc<RwLock<Peers<C>>>,
+    system_reader: Arc<RwLock<SystemSnapshot>>,
+    system_config: Arc<RwLock<SystemConfig>>,
 ) -> JoinHandle<std::io::Result<()>> {
-    let config = config.clone();
+    let template = ConfigTemplate::new(config, log_settings);
     tokio::spawn(async move {
-        let result = observer(config, peers_reader, system_reader).await;
+        let result = observer(template, peers_reader, system_reader, system_config).await;
         if let Err(ref e) = result {
             error!("Abnormal termination of state observer: {}", e);
         }
@@ -43,12 +126,13 @@ pub async fn spawn<C: NtpClock + Sync + Send + 'static>(
 }
 
 async fn observer<C: NtpClock>(
-    config: crate::config::ObserveConfig,
-    peers_reader: Arc<tokio::sync::RwLock<Peers<C>>>,
-    system_reader: Arc<tokio::sync::RwLock<SystemSnapshot>>,
+    template: ConfigTemplate,
+    peers_reader: Arc<RwLock<Peers<C>>>,
+    system_reader: Arc<RwLock<SystemSnapshot>>,
+    system_config: Arc<RwLock<SystemConfig>>,
 ) -> std::io::Result<()> {
-    let path = match config.path {
-        Some(path) => path,
+    let path = match &template.observe.path {
+        Some(path) => path.clone(),
         None => return Ok(()),
     };
 
@@ -57,15 +141,25 @@ async fn observer<C: NtpClock>(
     // this binary needs to run as root to be able to adjust the system clock.
     // by default, the socket inherits root permissions, but the client should not need
     // elevated permissions to read from the socket. So we explicitly set the permissions
-    let permissions: std::fs::Permissions = PermissionsExt::from_mode(config.mode);
+    let permissions: std::fs::Permissions = PermissionsExt::from_mode(template.observe.mode);
     std::fs::set_permissions(&path, permissions)?;
 
     loop {
         let (mut stream, _addr) = peers_listener.accept().await?;
 
-        let observe = ObservableState {
-            peers: peers_reader.read().await.observe().collect(),
-            system: *system_reader.read().await,
+        let observe = {
+            let peers = peers_reader.read().await;
+            let mut system = *system_reader.read().await;
+            let frequency_tolerance = system_config.read().await.frequency_tolerance;
+            if let Ok(now) = peers.clock().now() {
+                system.root_dispersion = system.root_dispersion_at(now, frequency_tolerance);
+            }
+            ObservableState {
+                peers: peers.observe().collect(),
+                servers: peers.observe_servers().collect(),
+                system,
+                config: Some(template.current(&peers, &system_config).await),
+            }
         };
 
         crate::sockets::write_json(&mut stream, &observe).await?;
@@ -78,12 +172,12 @@ mod tests {
 
     use ntp_proto::{
         NtpDuration, NtpInstant, NtpLeapIndicator, NtpTimestamp, PeerSnapshot, PeerStatistics,
-        PollInterval, Reach, ReferenceId,
+        PollInterval, Reach, ReferenceId, SyncState,
     };
     use tokio::{io::AsyncReadExt, net::UnixStream};
 
     use crate::{
-        config::{NormalizedAddress, PeerConfig, StandardPeerConfig},
+        config::{NormalizedAddress, PeerConfig, PeerOptions, StandardPeerConfig},
         peer_manager::PeerStatus,
     };
 
@@ -123,10 +217,16 @@ mod tests {
     async fn test_observation() {
         // be careful with copying: tests run concurrently and should use a unique socket name!
         let path = std::env::temp_dir().join("ntp-test-stream-2");
-        let config = crate::config::ObserveConfig {
-            path: Some(path.clone()),
-            mode: 0o700,
-        };
+        let template = ConfigTemplate::new(
+            &Config {
+                observe: ObserveConfig {
+                    path: Some(path.clone()),
+                    mode: 0o700,
+                },
+                ..Default::default()
+            },
+            Default::default(),
+        );
 
         let status_list = [
             PeerStatus::NoMeasurement,
@@ -149,18 +249,26 @@ mod tests {
                 leap_indicator: NtpLeapIndicator::NoWarning,
                 root_delay: NtpDuration::from_seconds(0.2),
                 root_dispersion: NtpDuration::from_seconds(0.02),
+                prefer: false,
+                noselect: false,
+                trust: false,
+                ignored: Default::default(),
+                raw_offset: NtpDuration::from_seconds(0.05),
             }),
         ];
 
         let peer_configs = [
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.1:123"),
+                options: PeerOptions::default(),
             }),
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.2:123"),
+                options: PeerOptions::default(),
             }),
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.3:123"),
+                options: PeerOptions::default(),
             }),
         ];
 
@@ -180,10 +288,14 @@ mod tests {
             leap_indicator: NtpLeapIndicator::Leap59,
             accumulated_steps: NtpDuration::ZERO,
             accumulated_steps_threshold: None,
+            reference_timestamp: NtpTimestamp::default(),
+            sync_state: SyncState::Synchronized,
         }));
 
         let handle = tokio::spawn(async move {
-            observer(config, peers_reader, system_reader).await.unwrap();
+            observer(template, peers_reader, system_reader, Default::default())
+                .await
+                .unwrap();
         });
 
         tokio::time::sleep(Duration::from_millis(10)).await;
@@ -203,6 +315,11 @@ mod tests {
         }
         assert_eq!(count, 1);
 
+        let config = result.config.unwrap();
+        assert_eq!(config.peers.len(), 3);
+        assert_eq!(config.system.min_intersection_survivors, 3);
+        assert_eq!(config.observe.mode, 0o700);
+
         handle.abort();
     }
 
@@ -210,10 +327,16 @@ mod tests {
     async fn test_block_during_read() {
         // be careful with copying: tests run concurrently and should use a unique socket name!
         let path = std::env::temp_dir().join("ntp-test-stream-3");
-        let config = crate::config::ObserveConfig {
-            path: Some(path.clone()),
-            mode: 0o700,
-        };
+        let template = ConfigTemplate::new(
+            &Config {
+                observe: ObserveConfig {
+                    path: Some(path.clone()),
+                    mode: 0o700,
+                },
+                ..Default::default()
+            },
+            Default::default(),
+        );
 
         let status_list = [
             PeerStatus::NoMeasurement,
@@ -236,18 +359,26 @@ mod tests {
                 leap_indicator: NtpLeapIndicator::NoWarning,
                 root_delay: NtpDuration::from_seconds(0.2),
                 root_dispersion: NtpDuration::from_seconds(0.02),
+                prefer: false,
+                noselect: false,
+                trust: false,
+                ignored: Default::default(),
+                raw_offset: NtpDuration::from_seconds(0.05),
             }),
         ];
 
         let peer_configs = [
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.1:123"),
+                options: PeerOptions::default(),
             }),
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.2:123"),
+                options: PeerOptions::default(),
             }),
             PeerConfig::Standard(StandardPeerConfig {
                 addr: NormalizedAddress::new_unchecked("127.0.0.3:123"),
+                options: PeerOptions::default(),
             }),
         ];
 
@@ -269,12 +400,16 @@ mod tests {
             leap_indicator: NtpLeapIndicator::Leap59,
             accumulated_steps: NtpDuration::ZERO,
             accumulated_steps_threshold: None,
+            reference_timestamp: NtpTimestamp::default(),
+            sync_state: SyncState::Synchronized,
         }));
 
         let system_writer = system_reader.clone();
 
         let handle = tokio::spawn(async move {
-            observer(config, peers_reader, system_reader).await.unwrap();
+            observer(template, peers_reader, system_reader, Default::default())
+                .await
+                .unwrap();
         });
 
         tokio::time::sleep(Duration::from_millis(10)).await;
@@ -294,4 +429,47 @@ mod tests {
 
         handle.abort();
     }
+
+    #[test]
+    fn test_older_daemon() {
+        // the state as reported before the peer ignore counters, the raw offset and the sync
+        // state were added
+        let state = r#"{
+            "system": {
+                "poll_interval": 4,
+                "precision": 3.814697266513178e-6,
+                "stratum": 2,
+                "root_delay": 0.0,
+                "root_dispersion": 0.0,
+                "reference_id": 0,
+                "leap_indicator": "NoWarning",
+                "accumulated_steps": 0.0,
+                "accumulated_steps_threshold": null
+            },
+            "peers": [{
+                "Observable": {
+                    "statistics": {"offset": 0.0, "delay": 0.0, "dispersion": 0.0, "jitter": 0.0},
+                    "reachability": 1,
+                    "uptime": {"secs": 11, "nanos": 0},
+                    "poll_interval": {"secs": 16, "nanos": 0},
+                    "peer_id": 0,
+                    "address": "example.com:123"
+                }
+            }]
+        }"#;
+
+        let state: ObservableState = serde_json::from_str(state).unwrap();
+        assert_eq!(state.system.sync_state, SyncState::Unsynchronized);
+        match &state.peers[0] {
+            ObservablePeerState::Observable {
+                raw_offset,
+                ignored,
+                ..
+            } => {
+                assert_eq!(*raw_offset, NtpDuration::ZERO);
+                assert_eq!(*ignored, IgnoreStatistics::default());
+            }
+            ObservablePeerState::Nothing => panic!("expected an observable peer"),
+        }
+    }
 }
@@ -19,7 +19,23 @@ use tokio::{
     time::{Instant, Sleep},
 };
 
-use crate::peer_manager::PeerIndex;
+use crate::{config::PeerOptions, peer_manager::PeerIndex};
+
+/// Number of polls in the burst sent at startup and after a reset when `iburst` is enabled.
+/// This is enough to fill the clock filter.
+const IBURST_POLLS: u8 = 8;
+
+/// Time between the polls of a startup burst, and between the requests within a single poll
+/// when `burst` is enabled
+const IBURST_SPACING: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// The system resolver does not expose the TTL of DNS records, so instead addresses are looked
+/// up again after this period.
+const ADDRESS_REFRESH_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Number of polls the reach register covers. The address of a peer is looked up again when none
+/// of that many polls were answered.
+const REACH_WINDOW: u32 = 8;
 
 /// Trait needed to allow injecting of futures other than tokio::time::Sleep for testing
 pub trait Wait: Future<Output = ()> {
@@ -54,6 +70,12 @@ pub enum MsgForSystem {
     MustDemobilize(PeerIndex),
     /// Experienced a network issue and must be restarted
     NetworkIssue(PeerIndex),
+    /// The peer stopped responding or its address is stale, and the configured address should be
+    /// looked up again
+    ResolveAddress(PeerIndex),
+    /// A fresh lookup no longer returns the address the peer is using, and the peer should move
+    /// to the given address
+    AddressChanged(PeerIndex, SocketAddr),
     /// Received an acceptable packet and made a new peer snapshot
     /// A new measurement should try to trigger a clock select
     NewMeasurement(PeerIndex, ResetEpoch, PeerSnapshot),
@@ -68,6 +90,8 @@ pub struct PeerChannels {
     pub system_snapshots: Arc<tokio::sync::RwLock<SystemSnapshot>>,
     pub system_config: Arc<tokio::sync::RwLock<SystemConfig>>,
     pub reset: watch::Receiver<ResetEpoch>,
+    /// The system snapshot for tasks that cannot afford to take a lock for every use
+    pub system_snapshot_updates: watch::Receiver<SystemSnapshot>,
 }
 
 impl PeerChannels {
@@ -80,6 +104,7 @@ impl PeerChannels {
             system_snapshots: Arc::new(tokio::sync::RwLock::new(SystemSnapshot::default())),
             system_config: Arc::new(tokio::sync::RwLock::new(SystemConfig::default())),
             reset: rx,
+            system_snapshot_updates: watch::channel(SystemSnapshot::default()).1,
         }
     }
 }
@@ -90,6 +115,7 @@ pub(crate) struct PeerTask<C: 'static + NtpClock + Send, T: Wait> {
     clock: C,
     socket: UdpSocket,
     channels: PeerChannels,
+    options: PeerOptions,
 
     peer: Peer,
 
@@ -97,14 +123,27 @@ pub(crate) struct PeerTask<C: 'static + NtpClock + Send, T: Wait> {
     // system time to the network (and could make attacks easier). So instead there is some
     // garbage data in the origin_timestamp field, and we need to track and pass along the
     // actual origin timestamp ourselves.
-    /// Timestamp of the last packet that we sent
-    last_send_timestamp: Option<NtpTimestamp>,
+    /// Transmit timestamp and actual send timestamp of the requests of the current poll.
+    /// Outside of a burst, this contains at most one entry.
+    send_timestamps: Vec<(NtpTimestamp, NtpTimestamp)>,
 
-    /// Instant last poll message was sent (used for timing the wait)
+    /// Instant last request was sent (used for timing the wait)
     last_poll_sent: Instant,
 
+    /// Instant the first request of the current poll was sent. The next poll is timed from
+    /// here, so the requests of a burst do not delay it.
+    poll_started: Instant,
+
     /// Number of resets that this peer has performed
     reset_epoch: ResetEpoch,
+
+    /// Instant we last asked the system to look up the address of this peer
+    last_address_check: Instant,
+    /// Number of polls sent since then
+    polls_since_address_check: u32,
+
+    /// Number of polls of the startup burst that still need to be sent
+    iburst_remaining: u8,
 }
 
 #[derive(Debug)]
@@ -125,34 +164,51 @@ where
     T: Wait,
 {
     /// Set the next deadline for the poll interval based on current state
-    fn update_poll_wait(&self, poll_wait: &mut Pin<&mut T>, system_snapshot: SystemSnapshot) {
-        let poll_interval = self
-            .peer
-            .current_poll_interval(system_snapshot)
-            .as_system_duration();
+    fn update_poll_wait(
+        &self,
+        poll_wait: &mut Pin<&mut T>,
+        system_snapshot: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) {
+        // the requests of a burst follow each other, polls are timed from their first request
+        let (since, poll_interval) = if self.peer.burst_requests_remaining() > 0 {
+            (self.last_poll_sent, IBURST_SPACING)
+        } else if self.iburst_remaining > 0 {
+            // a startup burst still never polls faster than the peer's minimum poll interval
+            let min_poll = self.peer.poll_limits(system_config).min;
+            let spacing = IBURST_SPACING.max(min_poll.as_system_duration());
+            (self.poll_started, spacing)
+        } else {
+            let poll_interval = self
+                .peer
+                .current_poll_interval(system_snapshot, system_config);
+            (self.poll_started, poll_interval.as_system_duration())
+        };
 
         // randomize the poll interval a little to make it harder to predict poll requests
         let poll_interval = poll_interval.mul_f64(thread_rng().gen_range(1.01..=1.05));
 
-        poll_wait
-            .as_mut()
-            .reset(self.last_poll_sent + poll_interval);
+        poll_wait.as_mut().reset(since + poll_interval);
     }
 
     async fn handle_poll(&mut self, poll_wait: &mut Pin<&mut T>) -> PollResult {
         let system_snapshot = *self.channels.system_snapshots.read().await;
-        let packet = self.peer.generate_poll_message(system_snapshot);
+        // the system config can change at runtime, so the peer always works with the current one
+        let system_config = *self.channels.system_config.read().await;
+
+        let packet = if self.peer.burst_requests_remaining() > 0 {
+            self.peer
+                .generate_burst_message(system_snapshot, &system_config)
+        } else {
+            self.poll_started = Instant::now();
+            self.start_poll(system_snapshot, &system_config).await
+        };
 
-        // Sent a poll, so update waiting to match deadline of next
+        // Sent a request, so update waiting to match deadline of next
         self.last_poll_sent = Instant::now();
-        self.update_poll_wait(poll_wait, system_snapshot);
+        self.update_poll_wait(poll_wait, system_snapshot, &system_config);
 
-        // NOTE: fitness check is not performed here, but by System
-        let snapshot = PeerSnapshot::from_peer(&self.peer);
-        let msg = MsgForSystem::UpdatedSnapshot(self.index, self.reset_epoch, snapshot);
-        self.channels.msg_for_system_sender.send(msg).await.ok();
-
-        match self.clock.now() {
+        let send_timestamp = match self.clock.now() {
             Err(e) => {
                 // we cannot determine the origin_timestamp
                 error!(error = ?e, "There was an error retrieving the current time");
@@ -160,10 +216,8 @@ where
                 // report as no permissions, since this seems the most likely
                 std::process::exit(exitcode::NOPERM);
             }
-            Ok(ts) => {
-                self.last_send_timestamp = Some(ts);
-            }
-        }
+            Ok(ts) => ts,
+        };
 
         match self.socket.send(&packet.serialize()).await {
             Err(error) => {
@@ -178,14 +232,68 @@ where
                 }
             }
             Ok((_written, opt_send_timestamp)) => {
-                // update the last_send_timestamp with the one given by the kernel, if available
-                self.last_send_timestamp = opt_send_timestamp.or(self.last_send_timestamp);
+                // use the send timestamp given by the kernel, if available
+                let send_timestamp = opt_send_timestamp.unwrap_or(send_timestamp);
+                self.send_timestamps
+                    .push((packet.transmit_timestamp, send_timestamp));
             }
         }
 
         PollResult::Ok
     }
 
+    /// Start a new poll, returning its first request
+    async fn start_poll(
+        &mut self,
+        system_snapshot: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) -> NtpHeader {
+        // An incomplete burst of the previous poll still yields a measurement
+        if let Some(update) = self.peer.finish_burst(system_snapshot, system_config) {
+            self.send_update(update).await;
+        }
+
+        // Responses to the requests of earlier polls are ignored from here on
+        self.send_timestamps.clear();
+
+        let packet = self
+            .peer
+            .generate_poll_message(system_snapshot, system_config);
+        self.iburst_remaining = self.iburst_remaining.saturating_sub(1);
+
+        // NOTE: fitness check is not performed here, but by System
+        let snapshot = PeerSnapshot::from_peer(&self.peer);
+        let msg = MsgForSystem::UpdatedSnapshot(self.index, self.reset_epoch, snapshot);
+        self.channels.msg_for_system_sender.send(msg).await.ok();
+
+        // The address may have moved when none of the polls of a full reach window were answered
+        // since the previous lookup, also when the peer was never reachable. Otherwise the result
+        // of the previous lookup eventually goes stale.
+        let unanswered =
+            !self.peer.reach().is_reachable() && self.polls_since_address_check >= REACH_WINDOW;
+        if unanswered || self.last_address_check.elapsed() >= ADDRESS_REFRESH_PERIOD {
+            self.last_address_check = Instant::now();
+            self.polls_since_address_check = 0;
+            let msg = MsgForSystem::ResolveAddress(self.index);
+            self.channels.msg_for_system_sender.send(msg).await.ok();
+        }
+        self.polls_since_address_check = self.polls_since_address_check.saturating_add(1);
+
+        packet
+    }
+
+    async fn send_update(&mut self, update: Update) {
+        let msg = match update {
+            Update::BareUpdate(update) => {
+                MsgForSystem::UpdatedSnapshot(self.index, self.reset_epoch, update)
+            }
+            Update::NewMeasurement(update) => {
+                MsgForSystem::NewMeasurement(self.index, self.reset_epoch, update)
+            }
+        };
+        self.channels.msg_for_system_sender.send(msg).await.ok();
+    }
+
     async fn handle_packet(
         &mut self,
         poll_wait: &mut Pin<&mut T>,
@@ -196,33 +304,30 @@ where
         let ntp_instant = NtpInstant::now();
 
         let system_snapshot = *self.channels.system_snapshots.read().await;
+        let system_config = *self.channels.system_config.read().await;
         let result = self.peer.handle_incoming(
             system_snapshot,
             packet,
             ntp_instant,
-            self.channels.system_config.read().await.frequency_tolerance,
+            &system_config,
             send_timestamp,
             recv_timestamp,
         );
 
+        // The remote asks us to slow down, so the rest of the startup burst is cancelled
+        if matches!(result, Err(IgnoreReason::KissIgnore)) && packet.is_kiss_rate() {
+            self.iburst_remaining = 0;
+        }
+
         // Handle incoming may have changed poll interval based on message, respect that change
-        self.update_poll_wait(poll_wait, system_snapshot);
+        self.update_poll_wait(poll_wait, system_snapshot, &system_config);
 
         match result {
             Ok(update) => {
                 debug!("packet accepted");
 
                 // NOTE: fitness check is not performed here, but by System
-
-                let msg = match update {
-                    Update::BareUpdate(update) => {
-                        MsgForSystem::UpdatedSnapshot(self.index, self.reset_epoch, update)
-                    }
-                    Update::NewMeasurement(update) => {
-                        MsgForSystem::NewMeasurement(self.index, self.reset_epoch, update)
-                    }
-                };
-                self.channels.msg_for_system_sender.send(msg).await.ok();
+                self.send_update(update).await;
             }
             Err(IgnoreReason::KissDemobilize) => {
                 warn!("Demobilizing peer connection on request of remote.");
@@ -256,21 +361,34 @@ where
                 result = self.channels.reset.changed() => {
                     if let Ok(()) = result {
                         // reset the measurement state (as if this association was just created).
-                        // crucially, this sets `self.next_expected_origin = None`, meaning that
+                        // crucially, this clears the expected origin timestamps, meaning that
                         // in-flight requests are ignored
                         self.peer.reset_measurements();
+                        self.send_timestamps.clear();
 
                         // our next measurement will have the new reset epoch
                         self.reset_epoch = *self.channels.reset.borrow_and_update();
+
+                        // refill the clock filter quickly
+                        if self.options.iburst {
+                            self.iburst_remaining = IBURST_POLLS;
+                            let system_snapshot = *self.channels.system_snapshots.read().await;
+                            let system_config = *self.channels.system_config.read().await;
+                            self.update_poll_wait(&mut poll_wait, system_snapshot, &system_config);
+                        }
                     }
                 }
                 result = self.socket.recv(&mut buf) => {
                     match accept_packet(result, &buf) {
                         AcceptResult::Accept(packet, recv_timestamp) => {
-                            let send_timestamp = match self.last_send_timestamp {
-                                Some(ts) => ts,
+                            let send_timestamp = match self
+                                .send_timestamps
+                                .iter()
+                                .find(|(transmit, _)| *transmit == packet.origin_timestamp)
+                            {
+                                Some((_, ts)) => *ts,
                                 None => {
-                                    warn!("we received a message without having sent one; discarding");
+                                    debug!("we received a message that is not a response to an outstanding request; discarding");
                                     continue;
                                 }
                             };
@@ -300,6 +418,7 @@ where
     pub fn spawn(
         index: PeerIndex,
         addr: SocketAddr,
+        options: PeerOptions,
         clock: C,
         network_wait_period: std::time::Duration,
         mut channels: PeerChannels,
@@ -325,8 +444,10 @@ where
                 // Unwrap should be safe because we know the socket was connected to a remote peer just before
                 let peer_id = ReferenceId::from_ip(socket.as_ref().peer_addr().unwrap().ip());
 
+                let system_config = *channels.system_config.read().await;
+
                 let local_clock_time = NtpInstant::now();
-                let peer = Peer::new(our_id, peer_id, local_clock_time);
+                let peer = Peer::new(our_id, peer_id, local_clock_time, options, &system_config);
 
                 let poll_wait = tokio::time::sleep(std::time::Duration::default());
                 tokio::pin!(poll_wait);
@@ -340,11 +461,16 @@ where
                     index,
                     clock,
                     channels,
+                    options,
                     socket,
                     peer,
-                    last_send_timestamp: None,
+                    send_timestamps: Vec::new(),
                     last_poll_sent: Instant::now(),
+                    poll_started: Instant::now(),
                     reset_epoch,
+                    last_address_check: Instant::now(),
+                    polls_since_address_check: 0,
+                    iburst_remaining: if options.iburst { IBURST_POLLS } else { 0 },
                 };
 
                 process.run(poll_wait).await
@@ -553,7 +679,13 @@ mod tests {
         let peer_id = ReferenceId::from_ip(socket.as_ref().peer_addr().unwrap().ip());
 
         let local_clock_time = NtpInstant::now();
-        let peer = Peer::new(our_id, peer_id, local_clock_time);
+        let peer = Peer::new(
+            our_id,
+            peer_id,
+            local_clock_time,
+            PeerOptions::default(),
+            &SystemConfig::default(),
+        );
 
         let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
         let system_config = Arc::new(RwLock::new(SystemConfig::default()));
@@ -569,12 +701,18 @@ mod tests {
                 system_snapshots,
                 system_config,
                 reset,
+                system_snapshot_updates: watch::channel(SystemSnapshot::default()).1,
             },
+            options: PeerOptions::default(),
             socket,
             peer,
-            last_send_timestamp: None,
+            send_timestamps: Vec::new(),
             last_poll_sent: Instant::now(),
+            poll_started: Instant::now(),
             reset_epoch: ResetEpoch::default(),
+            last_address_check: Instant::now(),
+            polls_since_address_check: 0,
+            iburst_remaining: 0,
         };
 
         (process, test_socket, msg_for_system_receiver, reset_send)
@@ -599,6 +737,7 @@ mod tests {
         let handle = PeerTask::spawn(
             PeerIndex::from_inner(0),
             SocketAddr::from((Ipv4Addr::LOCALHOST, 8003)),
+            PeerOptions::default(),
             TestClock {},
             std::time::Duration::from_secs(60),
             PeerChannels {
@@ -606,6 +745,7 @@ mod tests {
                 system_snapshots,
                 system_config,
                 reset,
+                system_snapshot_updates: watch::channel(SystemSnapshot::default()).1,
             },
         );
 
@@ -763,4 +903,148 @@ mod tests {
 
         handle.abort();
     }
+
+    #[tokio::test]
+    async fn test_unreachable_resolves_address() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, mut socket, mut msg_recv, _reset) = test_startup(8012).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+        let clock = TestClock {};
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+
+        let mut buf = [0; 48];
+        socket.recv(&mut buf).await.unwrap();
+
+        let rec_packet = NtpHeader::deserialize(&buf).unwrap();
+        let mut send_packet = NtpHeader::new();
+        send_packet.leap = NtpLeapIndicator::NoWarning;
+        send_packet.stratum = 1;
+        send_packet.mode = NtpAssociationMode::Server;
+        send_packet.origin_timestamp = rec_packet.transmit_timestamp;
+        send_packet.receive_timestamp = clock.now().unwrap();
+        send_packet.transmit_timestamp = clock.now().unwrap();
+
+        socket.send(&send_packet.serialize()).await.unwrap();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::NewMeasurement(_, _, _)));
+
+        // the response shifts out of the reach register after 8 more polls
+        for _ in 1..8 {
+            poll_send.notify();
+
+            let msg = msg_recv.recv().await.unwrap();
+            assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        }
+
+        poll_send.notify();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::ResolveAddress(_)));
+
+        // an unreachable peer does not ask again on every poll
+        for _ in 0..2 {
+            poll_send.notify();
+
+            let msg = msg_recv.recv().await.unwrap();
+            assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_never_reachable_resolves_address() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, _socket, mut msg_recv, _reset) = test_startup(8020).await;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        // a full window of polls goes unanswered
+        for _ in 0..8 {
+            poll_send.notify();
+
+            let msg = msg_recv.recv().await.unwrap();
+            assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        }
+
+        poll_send.notify();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::ResolveAddress(_)));
+
+        // and it asks again after the next window
+        for _ in 0..7 {
+            poll_send.notify();
+
+            let msg = msg_recv.recv().await.unwrap();
+            assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        }
+
+        poll_send.notify();
+
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::ResolveAddress(_)));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_iburst_stops_on_rate() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, mut msg_recv, _reset) = test_startup(8014).await;
+        process.options.iburst = true;
+        process.iburst_remaining = IBURST_POLLS;
+
+        let (poll_wait, _poll_send) = TestWait::new();
+        tokio::pin!(poll_wait);
+
+        process.handle_poll(&mut poll_wait).await;
+        let msg = msg_recv.recv().await.unwrap();
+        assert!(matches!(msg, MsgForSystem::UpdatedSnapshot(_, _, _)));
+        assert_eq!(process.iburst_remaining, IBURST_POLLS - 1);
+
+        let mut buf = [0; 48];
+        let (size, _, timestamp) = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(size, 48);
+
+        let rec_packet = NtpHeader::deserialize(&buf).unwrap();
+        let mut send_packet = NtpHeader::new();
+        send_packet.stratum = 0;
+        send_packet.mode = NtpAssociationMode::Server;
+        send_packet.origin_timestamp = rec_packet.transmit_timestamp;
+        send_packet.reference_id = ReferenceId::KISS_RATE;
+
+        let result = process
+            .handle_packet(
+                &mut poll_wait,
+                send_packet,
+                process.send_timestamps[0].1,
+                timestamp.unwrap(),
+            )
+            .await;
+        assert!(matches!(result, PacketResult::Ok));
+        assert_eq!(process.iburst_remaining, 0);
+    }
 }
@@ -1,14 +1,19 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use crate::{
-    config::{PeerConfig, PoolPeerConfig, ServerConfig, StandardPeerConfig},
-    observer::ObservablePeerState,
+    config::{NormalizedAddress, PeerConfig, ServerConfig},
+    list_files::{self, ListFiles, LIST_FILE_POLL_INTERVAL},
+    observer::{ObservablePeerState, ObservableServerState},
     peer::{MsgForSystem, PeerChannels, PeerTask, ResetEpoch},
     server::ServerTask,
+    server_stats::ServerStats,
 };
 use ntp_proto::{NtpClock, PeerSnapshot};
-use tokio::task::JoinHandle;
-use tracing::{debug, warn};
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
 
 const NETWORK_WAIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
 
@@ -55,12 +60,28 @@ impl PeerIndexIssuer {
 struct PeerData {
     status: PeerStatus,
     config: Arc<PeerConfig>,
+    /// The address the peer task is currently polling
+    addr: SocketAddr,
+    task: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct ServerData {
+    config: Arc<ServerConfig>,
+    /// Hands updated filters to the task that combines them with the list files
+    config_sender: watch::Sender<Arc<ServerConfig>>,
+    /// Makes the list files be read again
+    reload_lists: Arc<Notify>,
+    /// Kept until the server is removed, also when its config is updated
+    stats: Arc<ServerStats>,
+    task: JoinHandle<()>,
+    lists_task: JoinHandle<()>,
 }
 
 #[derive(Debug)]
 pub struct Peers<C: NtpClock> {
     peers: HashMap<PeerIndex, PeerData>,
-    servers: Vec<Arc<ServerConfig>>,
+    servers: Vec<ServerData>,
     indexer: PeerIndexIssuer,
 
     channels: PeerChannels,
@@ -78,20 +99,14 @@ impl<C: NtpClock> Peers<C> {
         }
     }
 
-    async fn add_peer_internal(&mut self, config: Arc<PeerConfig>) -> JoinHandle<()> {
-        let index = self.indexer.get();
-        let addr = loop {
-            let host = match &*config {
-                PeerConfig::Standard(StandardPeerConfig { addr, .. }) => {
-                    debug!(unresolved = ?&addr, "lookup host");
-                    addr.lookup_host().await.map(|mut i| i.next())
-                }
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
 
-                PeerConfig::Pool(PoolPeerConfig { addr, .. }) => {
-                    debug!(unresolved = ?&addr, "lookup host");
-                    addr.lookup_host().await.map(|mut i| i.next())
-                }
-            };
+    async fn add_peer_internal(&mut self, config: Arc<PeerConfig>) {
+        let addr = loop {
+            debug!(unresolved = ?config.addr(), "lookup host");
+            let host = config.addr().lookup_host().await.map(|mut i| i.next());
 
             match host {
                 Ok(Some(addr)) => {
@@ -108,38 +123,238 @@ impl<C: NtpClock> Peers<C> {
                 }
             }
         };
+
+        self.spawn_peer(config, addr);
+    }
+
+    fn spawn_peer(&mut self, config: Arc<PeerConfig>, addr: SocketAddr) {
+        let index = self.indexer.get();
+        let task = PeerTask::spawn(
+            index,
+            addr,
+            *config.options(),
+            self.clock.clone(),
+            NETWORK_WAIT_PERIOD,
+            self.channels.clone(),
+        );
         self.peers.insert(
             index,
             PeerData {
                 status: PeerStatus::NoMeasurement,
                 config,
+                addr,
+                task: Some(task),
             },
         );
-        PeerTask::spawn(
-            index,
-            addr,
-            self.clock.clone(),
-            NETWORK_WAIT_PERIOD,
-            self.channels.clone(),
-        )
     }
 
-    pub async fn add_peer(&mut self, config: PeerConfig) -> JoinHandle<()> {
+    /// Look up the address of a peer again in the background. When the current address is no
+    /// longer among the results, the system is told to move the peer to the new address.
+    fn resolve_address(&self, index: PeerIndex) {
+        let data = match self.peers.get(&index) {
+            Some(data) => data,
+            None => return,
+        };
+
+        let config = data.config.clone();
+        let current = data.addr;
+        let sender = self.channels.msg_for_system_sender.clone();
+
+        tokio::spawn(async move {
+            debug!(unresolved = ?config.addr(), "lookup host");
+            let addrs: Vec<_> = match config.addr().lookup_host().await {
+                Ok(addrs) => addrs.collect(),
+                Err(e) => {
+                    warn!(error = ?e, "error while resolving peer address, keeping current address");
+                    return;
+                }
+            };
+
+            if addrs.contains(&current) {
+                return;
+            }
+
+            match addrs.first() {
+                Some(&addr) => {
+                    let msg = MsgForSystem::AddressChanged(index, addr);
+                    sender.send(msg).await.ok();
+                }
+                None => warn!("Could not resolve peer address, keeping current address"),
+            }
+        });
+    }
+
+    pub async fn add_peer(&mut self, config: PeerConfig) {
         self.add_peer_internal(Arc::new(config)).await
     }
 
-    fn add_server_internal(&mut self, config: Arc<ServerConfig>) -> JoinHandle<()> {
-        self.servers.push(config.clone());
-        ServerTask::spawn(
-            config,
-            self.channels.system_snapshots.clone(),
+    /// Add a peer whose address was already resolved by the caller. Unlike `add_peer`, this never
+    /// waits for DNS, so it is safe to call while other tasks wait on the lock around `Peers`.
+    pub fn add_resolved_peer(&mut self, config: PeerConfig, addr: SocketAddr) {
+        info!(address = config.addr().as_str(), resolved = ?addr, "adding peer");
+        self.spawn_peer(Arc::new(config), addr)
+    }
+
+    /// Demobilize all peers configured with the given address, returning how many were removed
+    pub fn remove_peer(&mut self, addr: &NormalizedAddress) -> usize {
+        self.remove_peers_where(|config| config.addr() == addr)
+    }
+
+    /// Demobilize one peer with exactly the given configuration. Other peers with the same
+    /// configuration keep running. Returns whether a peer was removed.
+    pub fn remove_peer_config(&mut self, config: &PeerConfig) -> bool {
+        let index = self
+            .peers
+            .iter()
+            .find(|(_, data)| data.config.as_ref() == config)
+            .map(|(index, _)| *index);
+
+        match index.and_then(|index| self.demobilize(index)) {
+            Some(data) => {
+                info!(address = data.config.addr().as_str(), resolved = ?data.addr, "removed peer");
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_peers_where(&mut self, predicate: impl Fn(&PeerConfig) -> bool) -> usize {
+        let indices: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, data)| predicate(&data.config))
+            .map(|(index, _)| *index)
+            .collect();
+
+        for index in indices.iter() {
+            if let Some(data) = self.demobilize(*index) {
+                info!(address = data.config.addr().as_str(), resolved = ?data.addr, "removed peer");
+            }
+        }
+
+        indices.len()
+    }
+
+    /// Remove a peer and stop its task
+    fn demobilize(&mut self, index: PeerIndex) -> Option<PeerData> {
+        let mut data = self.peers.remove(&index)?;
+
+        if let Some(task) = data.task.take() {
+            task.abort();
+        }
+
+        Some(data)
+    }
+
+    /// The configuration of all current peers, together with the address they are polling
+    pub fn peer_configs(&self) -> impl Iterator<Item = (&PeerConfig, SocketAddr)> + '_ {
+        self.peers
+            .values()
+            .map(|data| (data.config.as_ref(), data.addr))
+    }
+
+    /// Start serving on the address or interface in the config, with the list files `files` of
+    /// that config. These are read by the caller, so that the peers are not locked while long
+    /// files are parsed. Returns false when a server is already listening there.
+    pub fn add_server(&mut self, config: ServerConfig, files: ListFiles) -> bool {
+        if self
+            .servers
+            .iter()
+            .any(|data| data.config.same_listener(&config))
+        {
+            return false;
+        }
+
+        let config = Arc::new(config);
+        let (config_sender, config_receiver) = watch::channel(config.clone());
+        let (filter_sender, filter_receiver) = watch::channel(Arc::new(files.apply(&config)));
+        let reload_lists = Arc::new(Notify::new());
+        let lists_task = tokio::spawn(list_files::maintain_filters(
+            files,
+            config_receiver,
+            filter_sender,
+            reload_lists.clone(),
+            LIST_FILE_POLL_INTERVAL,
+        ));
+
+        let stats = Arc::new(ServerStats::default());
+        let task = ServerTask::spawn(
+            filter_receiver,
+            stats.clone(),
+            self.channels.system_snapshot_updates.clone(),
+            self.channels.system_config.clone(),
             self.clock.clone(),
             NETWORK_WAIT_PERIOD,
-        )
+        );
+
+        self.servers.push(ServerData {
+            config,
+            config_sender,
+            reload_lists,
+            stats,
+            task,
+            lists_task,
+        });
+
+        true
+    }
+
+    /// Stop the server listening on the given address, or on the port in that address on the
+    /// given interface. Returns false when there is no such server.
+    pub fn remove_server(&mut self, addr: SocketAddr, interface: Option<&str>) -> bool {
+        match self.servers.iter().position(|data| {
+            data.config.addr == addr && data.config.interface.as_deref() == interface
+        }) {
+            Some(position) => {
+                let data = self.servers.remove(position);
+                data.task.abort();
+                data.lists_task.abort();
+                info!(?addr, ?interface, "removed server");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the allow and deny lists of a running server, without reopening its socket. The
+    /// server is identified by the address and interface in the config. Returns false when there
+    /// is no such server.
+    pub fn update_server(&mut self, config: ServerConfig) -> bool {
+        match self
+            .servers
+            .iter_mut()
+            .find(|data| data.config.same_listener(&config))
+        {
+            Some(data) => {
+                data.config = Arc::new(config);
+                // the task keeps the receiver alive for as long as it runs
+                data.config_sender.send(data.config.clone()).ok();
+                info!(addr = ?data.config.addr, interface = ?data.config.interface, "updated server");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Read the allow and deny list files of all servers again, also when they seem unchanged
+    pub fn reload_list_files(&self) {
+        for data in self.servers.iter() {
+            data.reload_lists.notify_one();
+        }
     }
 
-    pub async fn add_server(&mut self, config: ServerConfig) -> JoinHandle<()> {
-        self.add_server_internal(Arc::new(config))
+    pub fn server_configs(&self) -> impl Iterator<Item = &ServerConfig> + '_ {
+        self.servers.iter().map(|data| data.config.as_ref())
+    }
+
+    pub fn observe_servers(&self) -> impl Iterator<Item = ObservableServerState> + '_ {
+        let now = std::time::Instant::now();
+        self.servers.iter().map(move |data| ObservableServerState {
+            address: data.config.addr,
+            interface: data.config.interface.clone(),
+            statistics: data.stats.statistics(),
+            clients: data.stats.clients().observe(now),
+        })
     }
 
     #[cfg(test)]
@@ -156,6 +371,8 @@ impl<C: NtpClock> Peers<C> {
                 PeerData {
                     status: status.to_owned(),
                     config: Arc::new(raw_configs[i].clone()),
+                    addr: raw_configs[i].addr().as_str().parse().unwrap(),
+                    task: None,
                 },
             );
         }
@@ -178,16 +395,13 @@ impl<C: NtpClock> Peers<C> {
             PeerStatus::NoMeasurement => ObservablePeerState::Nothing,
             PeerStatus::Measurement(snapshot) => ObservablePeerState::Observable {
                 statistics: snapshot.statistics,
+                raw_offset: snapshot.raw_offset,
                 reachability: snapshot.reach,
                 uptime: snapshot.time.elapsed(),
                 poll_interval: snapshot.poll_interval.as_system_duration(),
                 peer_id: snapshot.peer_id,
-                address: match &*data.config {
-                    PeerConfig::Standard(StandardPeerConfig { addr, .. }) => {
-                        addr.as_str().to_string()
-                    }
-                    PeerConfig::Pool(PoolPeerConfig { addr, .. }) => addr.as_str().to_string(),
-                },
+                address: data.config.addr().as_str().to_string(),
+                ignored: snapshot.ignored,
             },
         })
     }
@@ -202,22 +416,37 @@ impl<C: NtpClock> Peers<C> {
     pub async fn update(&mut self, msg: MsgForSystem, current_reset_epoch: ResetEpoch) {
         match msg {
             MsgForSystem::MustDemobilize(index) => {
-                self.peers.remove(&index);
-            }
-            MsgForSystem::NewMeasurement(index, msg_reset_epoch, snapshot) => {
-                if current_reset_epoch == msg_reset_epoch {
-                    self.peers.get_mut(&index).unwrap().status = PeerStatus::Measurement(snapshot);
-                }
+                self.demobilize(index);
             }
-            MsgForSystem::UpdatedSnapshot(index, msg_reset_epoch, snapshot) => {
-                if current_reset_epoch == msg_reset_epoch {
-                    self.peers.get_mut(&index).unwrap().status = PeerStatus::Measurement(snapshot);
+            MsgForSystem::NewMeasurement(index, msg_reset_epoch, snapshot)
+            | MsgForSystem::UpdatedSnapshot(index, msg_reset_epoch, snapshot) => {
+                // messages of a peer that has since been moved to a new address are dropped
+                if let Some(data) = self.peers.get_mut(&index) {
+                    if current_reset_epoch == msg_reset_epoch {
+                        data.status = PeerStatus::Measurement(snapshot);
+                    }
                 }
             }
             MsgForSystem::NetworkIssue(index) => {
                 // Restart the peer reusing its configuration.
-                let config = self.peers.remove(&index).unwrap().config;
-                self.add_peer_internal(config).await;
+                if let Some(data) = self.peers.remove(&index) {
+                    self.add_peer_internal(data.config).await;
+                }
+            }
+            MsgForSystem::ResolveAddress(index) => {
+                self.resolve_address(index);
+            }
+            MsgForSystem::AddressChanged(index, addr) => {
+                // Restart the peer at the new address, reusing its configuration.
+                if let Some(data) = self.demobilize(index) {
+                    info!(
+                        address = data.config.addr().as_str(),
+                        old = ?data.addr,
+                        new = ?addr,
+                        "peer moved to a new address"
+                    );
+                    self.spawn_peer(data.config, addr);
+                }
             }
         }
     }
@@ -236,7 +465,7 @@ mod tests {
         PollInterval,
     };
 
-    use crate::config::{NormalizedAddress, StandardPeerConfig};
+    use crate::config::{NormalizedAddress, PeerOptions, StandardPeerConfig};
 
     use super::*;
 
@@ -281,6 +510,7 @@ mod tests {
                 .map(|i| {
                     PeerConfig::Standard(StandardPeerConfig {
                         addr: NormalizedAddress::new_unchecked(&format!("127.0.0.{i}:123")),
+                        options: PeerOptions::default(),
                     })
                 })
                 .collect::<Vec<_>>(),
@@ -384,4 +614,82 @@ mod tests {
         peers.reset_all();
         assert_eq!(peers.valid_snapshots().count(), 0);
     }
+
+    #[tokio::test]
+    async fn test_address_changed() {
+        let mut peers = Peers::from_statuslist(
+            &[PeerStatus::NoMeasurement],
+            &[PeerConfig::Standard(StandardPeerConfig {
+                addr: NormalizedAddress::new_unchecked("127.0.0.1:123"),
+                options: PeerOptions::default(),
+            })],
+            TestClock {},
+        );
+
+        let new_addr: SocketAddr = "127.0.0.2:123".parse().unwrap();
+        peers
+            .update(
+                MsgForSystem::AddressChanged(PeerIndex { index: 0 }, new_addr),
+                ResetEpoch::default(),
+            )
+            .await;
+
+        assert_eq!(peers.size(), 1);
+        assert!(!peers.peers.contains_key(&PeerIndex { index: 0 }));
+        let data = peers.peers.values().next().unwrap();
+        assert_eq!(data.addr, new_addr);
+        assert_eq!(data.config.addr().as_str(), "127.0.0.1:123");
+
+        // messages of the peer at the old address are ignored
+        peers
+            .update(
+                MsgForSystem::UpdatedSnapshot(
+                    PeerIndex { index: 0 },
+                    ResetEpoch::default(),
+                    peer_snapshot(
+                        PeerStatistics::default(),
+                        NtpInstant::now(),
+                        NtpDuration::from_seconds(0.1),
+                        NtpDuration::from_seconds(0.05),
+                    ),
+                ),
+                ResetEpoch::default(),
+            )
+            .await;
+        assert_eq!(peers.valid_snapshots().count(), 0);
+
+        for data in peers.peers.values_mut() {
+            if let Some(task) = data.task.take() {
+                task.abort();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_remove_peer() {
+        let mut peers = Peers::from_statuslist(
+            &[PeerStatus::NoMeasurement],
+            &[PeerConfig::Standard(StandardPeerConfig {
+                addr: NormalizedAddress::new_unchecked("127.0.0.1:123"),
+                options: PeerOptions::default(),
+            })],
+            TestClock {},
+        );
+
+        let config = PeerConfig::Standard(StandardPeerConfig {
+            addr: NormalizedAddress::new_unchecked("127.0.0.2:123"),
+            options: PeerOptions::default(),
+        });
+        peers.add_resolved_peer(config.clone(), "127.0.0.2:123".parse().unwrap());
+        assert_eq!(peers.size(), 2);
+        assert!(peers.peer_configs().any(|(c, _)| c == &config));
+
+        assert_eq!(peers.remove_peer(config.addr()), 1);
+        assert_eq!(peers.size(), 1);
+        assert!(!peers.peer_configs().any(|(c, _)| c == &config));
+
+        // removing an unknown peer does nothing
+        assert_eq!(peers.remove_peer(config.addr()), 0);
+        assert_eq!(peers.size(), 1);
+    }
 }
@@ -0,0 +1,283 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Instant,
+};
+
+use crate::config::{RateLimitConfig, ServerConfig};
+
+/// What to do with a request from a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitResult {
+    Allow,
+    /// The client exceeds its limit, reply with a RATE kiss-o'-death
+    Kod,
+    /// The client exceeds its limit, and so many kiss-o'-death packets were sent recently that
+    /// this one should not get one
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Take a token, after adding one for every `interval` seconds since the last call
+    fn take(&mut self, interval: f64, capacity: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = f64::min(self.tokens + elapsed / interval, capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Client {
+    prefix: IpAddr,
+    bucket: TokenBucket,
+}
+
+/// Per-client rate limiting with a token bucket for every client prefix.
+///
+/// To bound the memory used, clients are kept in a fixed number of slots. Every prefix can only
+/// be stored in two slots (picked by a keyed hash), and when both are taken by other clients the
+/// one that was seen least recently is forgotten. A forgotten client starts over with a full
+/// bucket, so heavy churn can only make the limits less strict.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    hasher: RandomState,
+    clients: Vec<Option<Client>>,
+    kod: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, now: Instant) -> Self {
+        // slots come in pairs
+        let slots = config.max_clients.max(2);
+        let slots = slots + slots % 2;
+
+        RateLimiter {
+            config,
+            hasher: RandomState::new(),
+            clients: vec![None; slots],
+            kod: TokenBucket::full(config.kod_rate as f64, now),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Register a request from `addr` and decide what to do with it
+    pub fn check(&mut self, addr: IpAddr, now: Instant) -> RateLimitResult {
+        if self.allow(addr, now) {
+            return RateLimitResult::Allow;
+        }
+
+        let kod_rate = self.config.kod_rate as f64;
+        if kod_rate > 0.0 && self.kod.take(1.0 / kod_rate, kod_rate, now) {
+            RateLimitResult::Kod
+        } else {
+            RateLimitResult::Ignore
+        }
+    }
+
+    // `BuildHasher::hash_one` needs a more recent compiler than we support
+    #[allow(clippy::manual_hash_one)]
+    fn allow(&mut self, addr: IpAddr, now: Instant) -> bool {
+        let prefix = self.prefix(addr);
+        let interval = self.config.interval.to_seconds();
+        let burst = self.config.burst as f64;
+
+        let mut hasher = self.hasher.build_hasher();
+        prefix.hash(&mut hasher);
+        let first = (hasher.finish() as usize % (self.clients.len() / 2)) * 2;
+
+        let index = match (&self.clients[first], &self.clients[first + 1]) {
+            (Some(client), _) if client.prefix == prefix => first,
+            (_, Some(client)) if client.prefix == prefix => first + 1,
+            (None, _) => first,
+            (_, None) => first + 1,
+            (Some(a), Some(b)) if a.bucket.last <= b.bucket.last => first,
+            _ => first + 1,
+        };
+
+        let client = match &mut self.clients[index] {
+            Some(client) if client.prefix == prefix => client,
+            slot => slot.insert(Client {
+                prefix,
+                bucket: TokenBucket::full(burst, now),
+            }),
+        };
+
+        client.bucket.take(interval, burst, now)
+    }
+
+    /// The prefix of `addr` that identifies a client
+    fn prefix(&self, addr: IpAddr) -> IpAddr {
+        match addr {
+            IpAddr::V4(addr) => IpAddr::V4(mask_v4(addr, self.config.ipv4_prefix)),
+            IpAddr::V6(addr) => match addr.octets() {
+                // ipv4 clients of a dual stack socket
+                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                    IpAddr::V4(mask_v4(Ipv4Addr::new(a, b, c, d), self.config.ipv4_prefix))
+                }
+                _ => IpAddr::V6(mask_v6(addr, self.config.ipv6_prefix)),
+            },
+        }
+    }
+}
+
+/// The rate limiters of a server, one for every rate limit class in use. All workers of a server
+/// share these, so a client gets the same limit whichever socket its requests arrive on.
+#[derive(Debug, Default)]
+pub struct RateLimiters {
+    limiters: Vec<RateLimiter>,
+}
+
+impl RateLimiters {
+    /// Register a request from `addr` with the limiter of class `config`, which is created on
+    /// first use. Limiters of classes that `server_config` no longer uses are forgotten then.
+    pub fn check(
+        &mut self,
+        server_config: &ServerConfig,
+        config: RateLimitConfig,
+        addr: IpAddr,
+        now: Instant,
+    ) -> RateLimitResult {
+        let index = match self
+            .limiters
+            .iter()
+            .position(|limiter| *limiter.config() == config)
+        {
+            Some(index) => index,
+            None => {
+                self.limiters.retain(|limiter| {
+                    server_config
+                        .rate_limits()
+                        .any(|class| class == *limiter.config())
+                });
+                self.limiters.push(RateLimiter::new(config, now));
+                self.limiters.len() - 1
+            }
+        };
+
+        self.limiters[index].check(addr, now)
+    }
+}
+
+fn mask_v4(addr: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    Ipv4Addr::from(u32::from(addr) & mask)
+}
+
+fn mask_v6(addr: Ipv6Addr, prefix: u8) -> Ipv6Addr {
+    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+    Ipv6Addr::from(u128::from(addr) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ntp_proto::NtpDuration;
+
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let start = Instant::now();
+        let config = RateLimitConfig {
+            interval: NtpDuration::from_seconds(2.0),
+            burst: 3,
+            kod_rate: 2,
+            ..RateLimitConfig::default()
+        };
+        let mut limiter = RateLimiter::new(config, start);
+
+        let client = "192.0.2.1".parse().unwrap();
+        let neighbour = "192.0.2.200".parse().unwrap();
+        let other = "198.51.100.1".parse().unwrap();
+
+        // the burst
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, start), RateLimitResult::Allow);
+        }
+        assert_eq!(limiter.check(client, start), RateLimitResult::Kod);
+        // the /24 shares the limit
+        assert_eq!(limiter.check(neighbour, start), RateLimitResult::Kod);
+        // only 2 kiss-o'-death packets per second
+        assert_eq!(limiter.check(client, start), RateLimitResult::Ignore);
+
+        assert_eq!(limiter.check(other, start), RateLimitResult::Allow);
+
+        // one request per interval is fine
+        for i in 1..10 {
+            let now = start + Duration::from_millis(2010 * i);
+            assert_eq!(limiter.check(client, now), RateLimitResult::Allow);
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_bounded() {
+        let start = Instant::now();
+        let config = RateLimitConfig {
+            burst: 1,
+            max_clients: 16,
+            ..RateLimitConfig::default()
+        };
+        let mut limiter = RateLimiter::new(config, start);
+
+        for i in 0..1000u32 {
+            let addr = IpAddr::V4(Ipv4Addr::from(i << 8));
+            assert_eq!(limiter.check(addr, start), RateLimitResult::Allow);
+        }
+        assert_eq!(limiter.clients.len(), 16);
+
+        // the most recent client is still known
+        let addr = IpAddr::V4(Ipv4Addr::from(999 << 8));
+        assert_eq!(limiter.check(addr, start), RateLimitResult::Kod);
+    }
+
+    #[test]
+    fn test_prefix() {
+        let limiter = RateLimiter::new(RateLimitConfig::default(), Instant::now());
+
+        let prefix = |addr: &str| limiter.prefix(addr.parse().unwrap()).to_string();
+        assert_eq!(prefix("192.0.2.123"), "192.0.2.0");
+        assert_eq!(prefix("::ffff:192.0.2.123"), "192.0.2.0");
+        assert_eq!(prefix("2001:db8:1:2:3:4:5:6"), "2001:db8:1:2::");
+
+        let config = RateLimitConfig {
+            ipv4_prefix: 32,
+            ipv6_prefix: 0,
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config, Instant::now());
+        assert_eq!(
+            limiter.prefix("192.0.2.123".parse().unwrap()).to_string(),
+            "192.0.2.123"
+        );
+        assert_eq!(
+            limiter.prefix("2001:db8::1".parse().unwrap()).to_string(),
+            "::"
+        );
+    }
+}
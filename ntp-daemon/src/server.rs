@@ -1,177 +1,515 @@
 use std::{
+    collections::HashMap,
     net::{IpAddr, SocketAddr},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use ntp_proto::{
-    NtpAssociationMode, NtpClock, NtpHeader, NtpTimestamp, ReferenceId, SystemSnapshot,
+    FrequencyTolerance, NtpAssociationMode, NtpClock, NtpHeader, NtpLeapIndicator, NtpTimestamp,
+    ReferenceId, SystemConfig, SystemSnapshot,
+};
+use ntp_udp::{InterfaceWatcher, UdpSocket, MAX_BATCH};
+use tokio::{
+    sync::{watch, RwLock},
+    task::{JoinHandle, JoinSet},
 };
-use ntp_udp::UdpSocket;
-use tokio::{sync::RwLock, task::JoinHandle};
 use tracing::{error, info, instrument, trace, warn};
 
-use crate::config::{FilterAction, ServerConfig};
+use crate::{
+    config::{AccessAction, FilterAction, RateLimitConfig, ServerConfig, UnsynchronizedAction},
+    ratelimit::{RateLimitResult, RateLimiters},
+    server_stats::{Outcome, ServerStats},
+};
+
+/// How often the addresses of the interface of a server are checked
+const INTERFACE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// A worker serving requests on one socket. A server can have several workers, each with a
+/// socket bound to the same address with `SO_REUSEPORT`.
 pub struct ServerTask<C: 'static + NtpClock + Send> {
-    config: Arc<ServerConfig>,
+    /// The address this worker serves on
+    addr: SocketAddr,
+    /// Updated when the filters of this server are changed at runtime
+    config: watch::Receiver<Arc<ServerConfig>>,
     network_wait_period: std::time::Duration,
-    system: Arc<RwLock<SystemSnapshot>>,
+    system: watch::Receiver<SystemSnapshot>,
+    /// Our copy of the system snapshot, so serving a request needs no lock
+    snapshot: SystemSnapshot,
+    system_config: Arc<RwLock<SystemConfig>>,
+    /// Taken from the system config whenever the snapshot changes
+    frequency_tolerance: FrequencyTolerance,
     clock: C,
+    /// Shared with the other workers of this server, on all of its addresses
+    rate_limiters: Arc<Mutex<RateLimiters>>,
+    /// Shared with the other workers of this server
+    stats: Arc<ServerStats>,
+}
+
+/// What to do with a client according to the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Serve, possibly within a rate limit
+    Serve(Option<RateLimitConfig>),
+    Ignore,
+    /// Reply with a kiss-o'-death with this code
+    Kiss(ReferenceId),
 }
 
 #[derive(Debug)]
 enum AcceptResult {
-    Accept(NtpHeader, SocketAddr, NtpTimestamp),
+    Accept(NtpHeader, SocketAddr, NtpTimestamp, Option<RateLimitConfig>),
     Ignore,
-    Deny(NtpHeader, SocketAddr),
+    Kiss(NtpHeader, SocketAddr, ReferenceId),
     NetworkGone,
 }
 
+/// The addresses of an interface as seen by [`InterfaceWatcher`], with the port of the server
+fn interface_addresses(addresses: &[SocketAddr], port: u16) -> Vec<SocketAddr> {
+    addresses
+        .iter()
+        .map(|addr| {
+            let mut addr = *addr;
+            addr.set_port(port);
+            addr
+        })
+        .collect()
+}
+
+/// Wait until the addresses of the interface change. Never completes without an interface.
+async fn interface_changed(watcher: &mut Option<InterfaceWatcher>) -> std::io::Result<()> {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Stop serving on the addresses that left the interface, and start serving on the new ones
+fn update_listeners(
+    listeners: &mut HashMap<SocketAddr, JoinSet<()>>,
+    watcher: &InterfaceWatcher,
+    port: u16,
+    spawn_workers: impl Fn(SocketAddr) -> JoinSet<()>,
+) {
+    let interface = watcher.name();
+    let addresses = interface_addresses(watcher.addresses(), port);
+    if addresses.is_empty() {
+        warn!(interface, "Interface has no addresses to serve on");
+    }
+
+    // dropping the workers of an address aborts them
+    listeners.retain(|addr, _| {
+        let keep = addresses.contains(addr);
+        if !keep {
+            info!(
+                ?addr,
+                interface, "address left the interface, stopped serving on it"
+            );
+        }
+        keep
+    });
+
+    for addr in addresses {
+        listeners.entry(addr).or_insert_with(|| {
+            info!(?addr, interface, "serving on new interface address");
+            spawn_workers(addr)
+        });
+    }
+}
+
+/// Open a server socket, retrying until that succeeds
+async fn open_socket(
+    addr: SocketAddr,
+    reuse_port: bool,
+    network_wait_period: std::time::Duration,
+) -> UdpSocket {
+    loop {
+        let socket = if reuse_port {
+            UdpSocket::server_reuse_port(addr).await
+        } else {
+            UdpSocket::server(addr).await
+        };
+        match socket {
+            Ok(socket) => return socket,
+            Err(error) => {
+                warn!(?error, "Could not open server socket");
+                tokio::time::sleep(network_wait_period).await;
+            }
+        }
+    }
+}
+
 impl<C: 'static + NtpClock + Send> ServerTask<C> {
+    /// Serve on the address of `config`, or on all addresses of its interface, with the
+    /// configured number of workers per address. The workers are restarted when that number
+    /// changes, and stop when the returned task is aborted.
     pub fn spawn(
-        config: Arc<ServerConfig>,
-        system: Arc<RwLock<SystemSnapshot>>,
+        mut config: watch::Receiver<Arc<ServerConfig>>,
+        stats: Arc<ServerStats>,
+        system: watch::Receiver<SystemSnapshot>,
+        system_config: Arc<RwLock<SystemConfig>>,
         clock: C,
         network_wait_period: std::time::Duration,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            let mut process = ServerTask {
-                config,
-                network_wait_period,
-                system,
-                clock,
+            // the address and interface of a server never change, that makes it another server
+            let (port, interface) = {
+                let config = config.borrow();
+                (config.addr.port(), config.interface.clone())
             };
+            let mut watcher = interface
+                .as_deref()
+                .map(|name| InterfaceWatcher::new(name, INTERFACE_POLL_INTERVAL));
+
+            // one set of limiters for all workers, so clients can't get more by switching ports
+            let rate_limiters = Arc::new(Mutex::new(RateLimiters::default()));
+            let worker_config = config.clone();
+            let worker_system_config = system_config.clone();
+            let spawn_workers = move |addr: SocketAddr, workers: usize, frequency_tolerance| {
+                let reuse_port = workers > 1;
+
+                // aborts the workers when dropped
+                let mut tasks = JoinSet::new();
+                for _ in 0..workers {
+                    let mut process = ServerTask {
+                        addr,
+                        config: worker_config.clone(),
+                        network_wait_period,
+                        system: system.clone(),
+                        snapshot: *system.borrow(),
+                        system_config: worker_system_config.clone(),
+                        frequency_tolerance,
+                        clock: clock.clone(),
+                        rate_limiters: rate_limiters.clone(),
+                        stats: stats.clone(),
+                    };
+                    tasks.spawn(async move {
+                        let socket = open_socket(addr, reuse_port, network_wait_period).await;
+                        process.serve(socket, reuse_port).await
+                    });
+                }
+                tasks
+            };
+
+            loop {
+                let workers = config.borrow().workers;
+                let frequency_tolerance = system_config.read().await.frequency_tolerance;
+
+                // the workers for every address we serve on
+                let mut listeners: HashMap<SocketAddr, JoinSet<()>> = HashMap::new();
+                let addresses = match &watcher {
+                    Some(watcher) => interface_addresses(watcher.addresses(), port),
+                    None => vec![config.borrow().addr],
+                };
+                for addr in addresses {
+                    listeners.insert(addr, spawn_workers(addr, workers, frequency_tolerance));
+                }
 
-            process.serve().await
+                loop {
+                    tokio::select! {
+                        result = config.changed() => {
+                            if result.is_err() {
+                                // the configuration can no longer change
+                                for tasks in listeners.values_mut() {
+                                    while tasks.join_next().await.is_some() {}
+                                }
+                                return;
+                            }
+                            if config.borrow().workers != workers {
+                                break;
+                            }
+                        }
+                        result = interface_changed(&mut watcher) => match (result, &watcher) {
+                            (Ok(()), Some(watcher)) => {
+                                let spawn = |addr| spawn_workers(addr, workers, frequency_tolerance);
+                                update_listeners(&mut listeners, watcher, port, spawn);
+                            }
+                            (Ok(()), None) => {}
+                            (Err(error), _) => {
+                                warn!(?error, ?interface, "Could not read the interface addresses");
+                            }
+                        },
+                    }
+                }
+
+                info!(
+                    workers = config.borrow().workers,
+                    "restarting server workers"
+                );
+                for tasks in listeners.values_mut() {
+                    tasks.shutdown().await;
+                }
+            }
         })
     }
 
-    fn filter(&self, addr: &IpAddr) -> Option<FilterAction> {
-        if self.config.denylist.is_in(addr) {
-            // First apply denylist
-            Some(self.config.denylist_action)
-        } else if !self.config.allowlist.is_in(addr) {
+    /// Take a new copy of the system snapshot when it changed
+    async fn refresh_system(&mut self) {
+        if let Ok(true) = self.system.has_changed() {
+            self.snapshot = *self.system.borrow_and_update();
+            self.frequency_tolerance = self.system_config.read().await.frequency_tolerance;
+        }
+    }
+
+    fn filter(&self, config: &ServerConfig, addr: &IpAddr) -> Access {
+        // The access rules take precedence
+        if let Some((action, rate_limit)) = config.access.lookup(addr) {
+            return match action {
+                AccessAction::Serve => Access::Serve(None),
+                AccessAction::RateLimit => {
+                    Access::Serve(Some(rate_limit.or(config.rate_limit).unwrap_or_default()))
+                }
+                AccessAction::Ignore => Access::Ignore,
+                AccessAction::Deny => Access::Kiss(ReferenceId::KISS_DENY),
+                AccessAction::Restrict => Access::Kiss(ReferenceId::KISS_RSTR),
+            };
+        }
+
+        let action = if config.denylist.is_in(addr) {
+            // Then apply denylist
+            Some(config.denylist_action)
+        } else if !config.allowlist.is_in(addr) {
             // Then allowlist
-            Some(self.config.allowlist_action)
+            Some(config.allowlist_action)
         } else {
             None
+        };
+
+        match action {
+            Some(FilterAction::Ignore) => Access::Ignore,
+            Some(FilterAction::Deny) => Access::Kiss(ReferenceId::KISS_DENY),
+            None => Access::Serve(config.rate_limit),
         }
     }
 
-    fn generate_deny(&self, input: NtpHeader) -> NtpHeader {
+    fn generate_kiss(&self, input: NtpHeader, reference_id: ReferenceId) -> NtpHeader {
         NtpHeader {
             mode: NtpAssociationMode::Server,
             stratum: 0,
-            reference_id: ReferenceId::KISS_DENY,
+            reference_id,
             origin_timestamp: input.transmit_timestamp,
             ..NtpHeader::new()
         }
     }
 
-    async fn generate_response(
-        &mut self,
+    /// The response to a request, or `None` when the request should not be answered because
+    /// the clock is not synchronized
+    fn generate_response(
+        &self,
+        config: &ServerConfig,
         input: NtpHeader,
         recv_timestamp: NtpTimestamp,
-    ) -> NtpHeader {
-        let system = self.system.read().await;
-        NtpHeader {
+    ) -> Option<NtpHeader> {
+        let frequency_tolerance = self.frequency_tolerance;
+        let system = &self.snapshot;
+        let (leap, stratum) = if system.is_synchronized() {
+            (system.leap_indicator, system.stratum)
+        } else {
+            match config.unsynchronized_action {
+                UnsynchronizedAction::Ignore => return None,
+                UnsynchronizedAction::Unsynchronized => (NtpLeapIndicator::Unknown, 16),
+                UnsynchronizedAction::Serve => (system.leap_indicator, system.stratum),
+            }
+        };
+
+        Some(NtpHeader {
+            leap,
             mode: NtpAssociationMode::Server,
-            stratum: system.stratum,
+            stratum,
             origin_timestamp: input.transmit_timestamp,
             receive_timestamp: recv_timestamp,
             reference_id: system.reference_id,
             poll: input.poll,
             precision: system.precision.log2(),
             root_delay: system.root_delay,
-            root_dispersion: system.root_dispersion,
+            root_dispersion: system.root_dispersion_at(recv_timestamp, frequency_tolerance),
+            reference_timestamp: system.reference_timestamp,
             // Timestamp must be last to make it as accurate as possible.
             transmit_timestamp: self.clock.now().expect("Failed to read time"),
-            ..NtpHeader::new()
-        }
+        })
     }
 
-    #[instrument(level = "debug", skip(self), fields(
-        addr = debug(self.config.addr),
+    #[instrument(level = "debug", skip(self, socket), fields(
+        addr = debug(self.addr),
     ))]
-    async fn serve(&mut self) {
-        let mut cur_socket = None;
+    async fn serve(&mut self, socket: UdpSocket, reuse_port: bool) {
+        let mut cur_socket = Some(socket);
+        let mut bufs = [[0_u8; 48]; MAX_BATCH];
+        let mut received = Vec::with_capacity(MAX_BATCH);
+        let mut responses = Vec::with_capacity(MAX_BATCH);
         loop {
             let socket = if let Some(ref socket) = cur_socket {
                 socket
             } else {
-                cur_socket = Some(loop {
-                    match UdpSocket::server(self.config.addr).await {
-                        Ok(socket) => break socket,
-                        Err(error) => {
-                            warn!(?error, "Could not open server socket");
-                            tokio::time::sleep(self.network_wait_period).await;
-                        }
-                    }
-                });
+                cur_socket =
+                    Some(open_socket(self.addr, reuse_port, self.network_wait_period).await);
                 cur_socket.as_ref().unwrap()
             };
 
-            let mut buf = [0_u8; 48];
-            let recv_res = socket.recv(&mut buf).await;
-            let accept_result = self.accept_packet(recv_res, &buf);
-            match accept_result {
-                AcceptResult::Accept(packet, peer_addr, recv_timestamp) => {
-                    let response = self.generate_response(packet, recv_timestamp).await;
+            let recv_res = socket.recv_batch(&mut bufs, &mut received).await;
+            self.refresh_system().await;
+            // a single borrow per batch, so all packets are handled with the same version of
+            // the config
+            let config = self.config.borrow().clone();
 
-                    if let Err(send_err) = socket.send_to(&response.serialize(), peer_addr).await {
-                        warn!(error=?send_err, "Could not send response packet");
+            if let Err(receive_error) = recv_res {
+                if let AcceptResult::NetworkGone =
+                    self.accept_packet(&config, Err(receive_error), &bufs[0])
+                {
+                    error!("Server connection gone");
+                    cur_socket = None;
+                }
+                continue;
+            }
+
+            self.stats.count_received(received.len());
+            {
+                let now = Instant::now();
+                let mut clients = self.stats.clients();
+                for (buf, message) in bufs.iter().zip(received.iter()) {
+                    if message.len > 0 {
+                        let mode = buf[0] & 0x07;
+                        let version = (buf[0] & 0x38) >> 3;
+                        clients.record(message.addr, mode, version, now);
                     }
                 }
-                AcceptResult::Deny(packet, peer_addr) => {
-                    let response = self.generate_deny(packet);
-                    if let Err(send_err) = socket.send_to(&response.serialize(), peer_addr).await {
-                        warn!(error=?send_err, "Could not send deny packet");
+            }
+
+            responses.clear();
+            {
+                // the limiters are shared with the other workers, lock them once per batch
+                let rate_limiters = self.rate_limiters.clone();
+                let mut rate_limiters = rate_limiters
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                for (buf, message) in bufs.iter().zip(received.iter()) {
+                    let accept_result = self.accept_packet(
+                        &config,
+                        Ok((message.len, message.addr, message.timestamp)),
+                        buf,
+                    );
+                    if let Some(response) = self.respond(&config, &mut rate_limiters, accept_result)
+                    {
+                        responses.push(response);
                     }
                 }
-                AcceptResult::NetworkGone => {
-                    error!("Server connection gone");
-                    cur_socket = None;
-                    continue;
+            }
+
+            let messages: Vec<_> = responses
+                .iter()
+                .map(|(packet, addr)| (&packet[..], *addr))
+                .collect();
+            if let Err(send_err) = socket.send_batch(&messages).await {
+                warn!(error=?send_err, "Could not send response packets");
+            }
+        }
+    }
+
+    /// The packet to send back for a received packet, if any
+    fn respond(
+        &mut self,
+        config: &ServerConfig,
+        rate_limiters: &mut RateLimiters,
+        accept_result: AcceptResult,
+    ) -> Option<([u8; 48], SocketAddr)> {
+        match accept_result {
+            AcceptResult::Accept(packet, peer_addr, recv_timestamp, rate_limit) => {
+                let result = match rate_limit {
+                    Some(rate_limit) => {
+                        rate_limiters.check(config, rate_limit, peer_addr.ip(), Instant::now())
+                    }
+                    None => RateLimitResult::Allow,
+                };
+
+                match result {
+                    RateLimitResult::Allow => {
+                        match self.generate_response(config, packet, recv_timestamp) {
+                            Some(response) => {
+                                self.stats.count(Outcome::Served);
+                                Some((response.serialize(), peer_addr))
+                            }
+                            None => {
+                                trace!("Not answering {} while unsynchronized", peer_addr);
+                                self.stats.count(Outcome::Ignored);
+                                None
+                            }
+                        }
+                    }
+                    RateLimitResult::Kod => {
+                        trace!("Rate limiting {}", peer_addr);
+                        self.stats.count(Outcome::RateLimited);
+                        let response = self.generate_kiss(packet, ReferenceId::KISS_RATE);
+                        Some((response.serialize(), peer_addr))
+                    }
+                    RateLimitResult::Ignore => {
+                        trace!("Rate limiting {} without kiss-o'-death", peer_addr);
+                        self.stats.count(Outcome::RateLimited);
+                        None
+                    }
                 }
-                AcceptResult::Ignore => {}
             }
+            AcceptResult::Kiss(packet, peer_addr, reference_id) => {
+                self.stats.count(Outcome::Denied);
+                let response = self.generate_kiss(packet, reference_id);
+                Some((response.serialize(), peer_addr))
+            }
+            AcceptResult::NetworkGone | AcceptResult::Ignore => None,
         }
     }
 
     fn accept_packet(
         &self,
+        config: &ServerConfig,
         result: Result<(usize, SocketAddr, Option<NtpTimestamp>), std::io::Error>,
         buf: &[u8; 48],
     ) -> AcceptResult {
+        // The kernel turns on receive timestamps with a delay when the first socket asks for
+        // them, so the first packets on a new socket can lack one. Read the clock for those.
+        let result = result.map(|(size, peer_addr, timestamp)| {
+            (size, peer_addr, timestamp.or_else(|| self.clock.now().ok()))
+        });
+
         match result {
             Ok((size, peer_addr, Some(recv_timestamp))) if size >= 48 => {
                 // Note: packets are allowed to be bigger when including extensions.
                 // we don't expect them, but the client may still send them. The
                 // extra bytes are guaranteed safe to ignore. `recv` truncates the messages.
                 // Messages of fewer than 48 bytes are skipped entirely
-                match self.filter(&peer_addr.ip()) {
-                    Some(FilterAction::Deny) => {
+                match self.filter(config, &peer_addr.ip()) {
+                    Access::Kiss(reference_id) => {
                         match self.accept_data(buf, peer_addr, recv_timestamp) {
                             // We should send deny messages only to reasonable requests
                             // otherwise two servers could end up in a loop of sending
                             // deny's to each other.
-                            AcceptResult::Accept(packet, addr, _) => {
-                                AcceptResult::Deny(packet, addr)
+                            AcceptResult::Accept(packet, addr, _, _) => {
+                                AcceptResult::Kiss(packet, addr, reference_id)
+                            }
+                            v => v,
+                        }
+                    }
+                    Access::Ignore => {
+                        self.stats.count(Outcome::Ignored);
+                        AcceptResult::Ignore
+                    }
+                    Access::Serve(rate_limit) => {
+                        match self.accept_data(buf, peer_addr, recv_timestamp) {
+                            AcceptResult::Accept(packet, addr, recv_timestamp, _) => {
+                                AcceptResult::Accept(packet, addr, recv_timestamp, rate_limit)
                             }
                             v => v,
                         }
                     }
-                    Some(FilterAction::Ignore) => AcceptResult::Ignore,
-                    None => self.accept_data(buf, peer_addr, recv_timestamp),
                 }
             }
             Ok((size, _, Some(_))) => {
                 info!(expected = 48, actual = size, "received packet is too small");
+                self.stats.count(Outcome::Malformed);
 
                 AcceptResult::Ignore
             }
             Ok((size, _, None)) => {
                 warn!(?size, "received a packet without a timestamp");
+                self.stats.count(Outcome::Ignored);
 
                 AcceptResult::Ignore
             }
@@ -204,7 +542,7 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
             Ok(packet) => match packet.mode {
                 NtpAssociationMode::Client => {
                     trace!("NTP client request accepted from {}", peer_addr);
-                    AcceptResult::Accept(packet, peer_addr, recv_timestamp)
+                    AcceptResult::Accept(packet, peer_addr, recv_timestamp, None)
                 }
                 _ => {
                     trace!(
@@ -212,11 +550,13 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                         packet.mode,
                         peer_addr
                     );
+                    self.stats.count(Outcome::Ignored);
                     AcceptResult::Ignore
                 }
             },
             Err(e) => {
                 info!("received invalid packet: {}", e);
+                self.stats.count(Outcome::VersionMismatch);
                 AcceptResult::Ignore
             }
         }
@@ -227,9 +567,13 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
 mod tests {
     use std::time::Duration;
 
-    use ntp_proto::{NtpDuration, NtpLeapIndicator, PollInterval};
+    use ntp_proto::{NtpDuration, NtpLeapIndicator, PollInterval, SyncState};
 
-    use crate::ipfilter::IpFilter;
+    use crate::{
+        config::{AccessRule, AccessRules},
+        ipfilter::IpFilter,
+        server_stats::ServerStatistics,
+    };
 
     use super::*;
 
@@ -275,15 +619,30 @@ mod tests {
     async fn test_server_filter_allow_ok() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9000".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::none(),
             denylist_action: FilterAction::Ignore,
+            denylist_file: None,
             allowlist: IpFilter::new(&["127.0.0.0/24".parse().unwrap()]),
             allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9001".parse().unwrap(),
@@ -312,15 +671,30 @@ mod tests {
     async fn test_server_filter_allow_deny() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9002".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::none(),
             denylist_action: FilterAction::Ignore,
+            denylist_file: None,
             allowlist: IpFilter::new(&["128.0.0.0/24".parse().unwrap()]),
             allowlist_action: FilterAction::Deny,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9003".parse().unwrap(),
@@ -350,15 +724,30 @@ mod tests {
     async fn test_server_filter_allow_ignore() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9004".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::none(),
             denylist_action: FilterAction::Ignore,
+            denylist_file: None,
             allowlist: IpFilter::new(&["128.0.0.0/24".parse().unwrap()]),
             allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9005".parse().unwrap(),
@@ -383,15 +772,30 @@ mod tests {
     async fn test_server_filter_deny_ok() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9006".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::new(&["192.168.0.0/16".parse().unwrap()]),
             denylist_action: FilterAction::Ignore,
+            denylist_file: None,
             allowlist: IpFilter::all(),
             allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9007".parse().unwrap(),
@@ -420,15 +824,30 @@ mod tests {
     async fn test_server_filter_deny_deny() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9008".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::new(&["127.0.0.0/24".parse().unwrap()]),
             denylist_action: FilterAction::Deny,
+            denylist_file: None,
             allowlist: IpFilter::all(),
             allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9009".parse().unwrap(),
@@ -458,15 +877,30 @@ mod tests {
     async fn test_server_filter_deny_ignore() {
         let config = Arc::new(ServerConfig {
             addr: "127.0.0.1:9010".parse().unwrap(),
+            interface: None,
             denylist: IpFilter::new(&["127.0.0.0/24".parse().unwrap()]),
             denylist_action: FilterAction::Ignore,
+            denylist_file: None,
             allowlist: IpFilter::all(),
             allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
         });
-        let system_snapshots = Arc::new(RwLock::new(SystemSnapshot::default()));
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
         let clock = TestClock {};
 
-        let server = ServerTask::spawn(config, system_snapshots, clock, Duration::from_secs(1));
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
 
         let mut socket = UdpSocket::client(
             "127.0.0.1:9011".parse().unwrap(),
@@ -486,4 +920,574 @@ mod tests {
 
         server.abort();
     }
+
+    #[tokio::test]
+    async fn test_server_filter_update() {
+        let config = Arc::new(ServerConfig {
+            addr: "127.0.0.1:9012".parse().unwrap(),
+            interface: None,
+            denylist: IpFilter::new(&["127.0.0.0/24".parse().unwrap()]),
+            denylist_action: FilterAction::Ignore,
+            denylist_file: None,
+            allowlist: IpFilter::all(),
+            allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
+        });
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9013".parse().unwrap(),
+            "127.0.0.1:9012".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+
+        socket.send(&packet.serialize()).await.unwrap();
+        let mut buf = [0; 48];
+        let res = tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        // lift the denylist on the running server
+        config_tx
+            .send(Arc::new(ServerConfig {
+                addr: "127.0.0.1:9012".parse().unwrap(),
+                interface: None,
+                denylist: IpFilter::none(),
+                denylist_action: FilterAction::Ignore,
+                denylist_file: None,
+                allowlist: IpFilter::all(),
+                allowlist_action: FilterAction::Ignore,
+                allowlist_file: None,
+                rate_limit: None,
+                access: AccessRules::default(),
+                unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+                workers: 1,
+            }))
+            .unwrap();
+
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = NtpHeader::deserialize(&buf).unwrap();
+        assert_ne!(packet.stratum, 0);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_rate_limit() {
+        let config = Arc::new(ServerConfig {
+            addr: "127.0.0.1:9016".parse().unwrap(),
+            interface: None,
+            denylist: IpFilter::none(),
+            denylist_action: FilterAction::Ignore,
+            denylist_file: None,
+            allowlist: IpFilter::all(),
+            allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: Some(crate::config::RateLimitConfig {
+                interval: NtpDuration::from_seconds(60.0),
+                burst: 2,
+                kod_rate: 1,
+                ..Default::default()
+            }),
+            access: AccessRules::default(),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
+        });
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9017".parse().unwrap(),
+            "127.0.0.1:9016".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+
+        let mut buf = [0; 48];
+        for _ in 0..2 {
+            socket.send(&packet.serialize()).await.unwrap();
+            tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let response = NtpHeader::deserialize(&buf).unwrap();
+            assert_ne!(response.stratum, 0);
+        }
+
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(response.stratum, 0);
+        assert_eq!(response.reference_id, ReferenceId::KISS_RATE);
+
+        // kiss-o'-death packets are rate limited too
+        socket.send(&packet.serialize()).await.unwrap();
+        let res = tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_workers_rate_limit() {
+        let config = Arc::new(ServerConfig {
+            unsynchronized_action: UnsynchronizedAction::Serve,
+            rate_limit: Some(crate::config::RateLimitConfig {
+                interval: NtpDuration::from_seconds(60.0),
+                burst: 2,
+                ..Default::default()
+            }),
+            workers: 4,
+            ..ServerConfig::from_addr("127.0.0.1:9031".parse().unwrap())
+        });
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+        let mut buf = [0; 48];
+
+        // a fresh port for every request may reach another worker, but not another limit
+        let mut strata = vec![];
+        for port in 9032..9036 {
+            let mut socket = UdpSocket::client(
+                format!("127.0.0.1:{port}").parse().unwrap(),
+                "127.0.0.1:9031".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+            socket.send(&packet.serialize()).await.unwrap();
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            strata.push(NtpHeader::deserialize(&buf).unwrap().stratum);
+        }
+        assert_eq!(strata[2..], [0, 0]);
+        assert!(strata[..2].iter().all(|stratum| *stratum != 0));
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_access_rules() {
+        let config = Arc::new(ServerConfig {
+            addr: "127.0.0.1:9018".parse().unwrap(),
+            interface: None,
+            denylist: IpFilter::all(),
+            denylist_action: FilterAction::Ignore,
+            denylist_file: None,
+            allowlist: IpFilter::all(),
+            allowlist_action: FilterAction::Ignore,
+            allowlist_file: None,
+            rate_limit: None,
+            access: AccessRules::new(vec![
+                AccessRule {
+                    subnet: "127.0.0.2/32".parse().unwrap(),
+                    action: AccessAction::Ignore,
+                    rate_limit: None,
+                },
+                AccessRule {
+                    subnet: "127.0.0.0/8".parse().unwrap(),
+                    action: AccessAction::Restrict,
+                    rate_limit: None,
+                },
+            ]),
+            unsynchronized_action: UnsynchronizedAction::Unsynchronized,
+            workers: 1,
+        });
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9019".parse().unwrap(),
+            "127.0.0.1:9018".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+
+        // the access rules take precedence over the denylist
+        socket.send(&packet.serialize()).await.unwrap();
+        let mut buf = [0; 48];
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(packet.stratum, 0);
+        assert_eq!(packet.reference_id, ReferenceId::KISS_RSTR);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_unsynchronized() {
+        let server_config = |unsynchronized_action| {
+            Arc::new(ServerConfig {
+                unsynchronized_action,
+                ..ServerConfig::from_addr("127.0.0.1:9020".parse().unwrap())
+            })
+        };
+        let (system_tx, system_snapshots) = watch::channel(SystemSnapshot {
+            stratum: 2,
+            leap_indicator: NtpLeapIndicator::NoWarning,
+            ..SystemSnapshot::default()
+        });
+        let clock = TestClock {};
+
+        let (config_tx, config) = watch::channel(server_config(UnsynchronizedAction::Ignore));
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock.clone(),
+            Duration::from_secs(1),
+        );
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9021".parse().unwrap(),
+            "127.0.0.1:9020".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+        let mut buf = [0; 48];
+
+        // the clock was never updated
+        socket.send(&packet.serialize()).await.unwrap();
+        let res = tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        config_tx
+            .send(server_config(UnsynchronizedAction::Unsynchronized))
+            .unwrap();
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(response.stratum, 16);
+        assert_eq!(response.leap, NtpLeapIndicator::Unknown);
+
+        config_tx
+            .send(server_config(UnsynchronizedAction::Serve))
+            .unwrap();
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(response.stratum, 2);
+
+        // a recent clock update
+        let reference_timestamp = clock.now().unwrap();
+        system_tx.send_modify(|system| {
+            system.reference_timestamp = reference_timestamp;
+            system.sync_state = SyncState::Synchronized;
+        });
+        config_tx
+            .send(server_config(UnsynchronizedAction::Ignore))
+            .unwrap();
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(response.stratum, 2);
+        assert_eq!(response.leap, NtpLeapIndicator::NoWarning);
+        assert_eq!(response.reference_timestamp, reference_timestamp);
+
+        // in holdover the root dispersion grows by 15 ppm of the time since the last update
+        system_tx.send_modify(|system| {
+            system.reference_timestamp = reference_timestamp - NtpDuration::from_seconds(1000.0);
+            system.root_dispersion = NtpDuration::from_seconds(0.01);
+            system.sync_state = SyncState::Holdover;
+        });
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = NtpHeader::deserialize(&buf).unwrap();
+        assert_eq!(response.stratum, 2);
+        let root_dispersion = response.root_dispersion.to_seconds();
+        assert!(
+            (0.0249..0.0251).contains(&root_dispersion),
+            "{root_dispersion}"
+        );
+
+        // the holdover expired
+        system_tx.send_modify(|system| system.sync_state = SyncState::Unsynchronized);
+        socket.send(&packet.serialize()).await.unwrap();
+        let res = tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_workers() {
+        let server_config = |workers| {
+            Arc::new(ServerConfig {
+                unsynchronized_action: UnsynchronizedAction::Serve,
+                workers,
+                ..ServerConfig::from_addr("127.0.0.1:9022".parse().unwrap())
+            })
+        };
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (config_tx, config) = watch::channel(server_config(2));
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+        let mut buf = [0; 48];
+
+        // the kernel spreads the clients over the sockets of the workers
+        for port in 9023..9027 {
+            let mut socket = UdpSocket::client(
+                format!("127.0.0.1:{port}").parse().unwrap(),
+                "127.0.0.1:9022".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+            socket.send(&packet.serialize()).await.unwrap();
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_ne!(NtpHeader::deserialize(&buf).unwrap().stratum, 0);
+        }
+
+        // the server keeps answering after the number of workers changes
+        config_tx.send(server_config(1)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9023".parse().unwrap(),
+            "127.0.0.1:9022".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        socket.send(&packet.serialize()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(NtpHeader::deserialize(&buf).unwrap().stratum, 0);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_interface() {
+        let config = Arc::new(ServerConfig {
+            interface: Some("lo".into()),
+            unsynchronized_action: UnsynchronizedAction::Serve,
+            ..ServerConfig::from_addr("0.0.0.0:9029".parse().unwrap())
+        });
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            Arc::new(ServerStats::default()),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        // give the server time to find the addresses of the interface
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        };
+        let mut buf = [0; 48];
+
+        // every address of the interface is served, with the port from the config
+        for (client, server) in [
+            ("127.0.0.1:9030", "127.0.0.1:9029"),
+            ("[::1]:9031", "[::1]:9029"),
+        ] {
+            let mut socket = UdpSocket::client(client.parse().unwrap(), server.parse().unwrap())
+                .await
+                .unwrap();
+            socket.send(&packet.serialize()).await.unwrap();
+            tokio::time::timeout(Duration::from_millis(100), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_ne!(NtpHeader::deserialize(&buf).unwrap().stratum, 0);
+        }
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_stats() {
+        let config = Arc::new(ServerConfig {
+            unsynchronized_action: UnsynchronizedAction::Serve,
+            ..ServerConfig::from_addr("127.0.0.1:9027".parse().unwrap())
+        });
+        let stats = Arc::new(ServerStats::default());
+        let (_, system_snapshots) = watch::channel(SystemSnapshot::default());
+        let clock = TestClock {};
+
+        let (_config_tx, config) = watch::channel(config);
+        let server = ServerTask::spawn(
+            config,
+            stats.clone(),
+            system_snapshots,
+            Arc::new(RwLock::new(SystemConfig::default())),
+            clock,
+            Duration::from_secs(1),
+        );
+
+        let mut socket = UdpSocket::client(
+            "127.0.0.1:9028".parse().unwrap(),
+            "127.0.0.1:9027".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        let packet = NtpHeader {
+            mode: NtpAssociationMode::Client,
+            ..NtpHeader::new()
+        }
+        .serialize();
+
+        let mut buf = [0; 48];
+        socket.send(&packet).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // too small
+        socket.send(&packet[..10]).await.unwrap();
+
+        // NTP version 3
+        let mut old_version = packet;
+        old_version[0] = (old_version[0] & !0x38) | (3 << 3);
+        socket.send(&old_version).await.unwrap();
+
+        // not a client request
+        let server_mode = NtpHeader {
+            mode: NtpAssociationMode::Server,
+            ..NtpHeader::new()
+        }
+        .serialize();
+        socket.send(&server_mode).await.unwrap();
+
+        let res = tokio::time::timeout(Duration::from_millis(10), socket.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        assert_eq!(
+            stats.statistics(),
+            ServerStatistics {
+                received: 4,
+                served: 1,
+                ignored: 1,
+                malformed: 1,
+                version_mismatch: 1,
+                ..Default::default()
+            }
+        );
+
+        let clients = stats.clients().observe(Instant::now());
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].address, "127.0.0.1:9028".parse().unwrap());
+        assert_eq!(clients[0].packets, 4);
+        assert_eq!(clients[0].mode, 4);
+        assert_eq!(clients[0].version, 4);
+
+        server.abort();
+    }
 }
@@ -0,0 +1,275 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of clients a server remembers in its list of recent clients
+pub const CLIENT_LIST_SIZE: usize = 1024;
+
+/// What a server did with a packet it received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Answered with the time
+    Served,
+    /// Answered with a DENY or RSTR kiss-o'-death
+    Denied,
+    /// Not answered, because of the access rules, the mode of the packet, or because the clock
+    /// is not synchronized
+    Ignored,
+    /// The client exceeded its rate limit
+    RateLimited,
+    /// The packet is too small to be an NTP packet
+    Malformed,
+    /// The packet has an NTP version we do not serve
+    VersionMismatch,
+}
+
+/// Number of packets a server received, and what it did with them. Every received packet has
+/// exactly one outcome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStatistics {
+    pub received: u64,
+    pub served: u64,
+    pub denied: u64,
+    pub ignored: u64,
+    pub rate_limited: u64,
+    pub malformed: u64,
+    pub version_mismatch: u64,
+}
+
+/// A client in the list of recent clients of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObservableClient {
+    /// The address of the client, with the port of its most recent packet
+    pub address: SocketAddr,
+    pub packets: u64,
+    /// Time since the most recent packet
+    pub last_seen: Duration,
+    /// Average time between the packets, absent when only one packet was seen
+    pub average_interval: Option<Duration>,
+    /// Mode of the most recent packet
+    pub mode: u8,
+    /// NTP version of the most recent packet
+    pub version: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClientEntry {
+    address: SocketAddr,
+    packets: u64,
+    first_seen: Instant,
+    last_seen: Instant,
+    mode: u8,
+    version: u8,
+    /// Position in the usage order, a higher value is more recent
+    sequence: u64,
+}
+
+/// The clients that sent a packet most recently. When the list is full, the client that was
+/// seen least recently is forgotten.
+#[derive(Debug)]
+pub struct ClientList {
+    capacity: usize,
+    next_sequence: u64,
+    clients: HashMap<IpAddr, ClientEntry>,
+    order: BTreeMap<u64, IpAddr>,
+}
+
+impl ClientList {
+    pub fn new(capacity: usize) -> Self {
+        ClientList {
+            capacity,
+            next_sequence: 0,
+            clients: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Register a packet with the given mode and version from `address`
+    pub fn record(&mut self, address: SocketAddr, mode: u8, version: u8, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        match self.clients.get_mut(&address.ip()) {
+            Some(entry) => {
+                self.order.remove(&entry.sequence);
+                entry.address = address;
+                entry.packets = entry.packets.saturating_add(1);
+                entry.last_seen = now;
+                entry.mode = mode;
+                entry.version = version;
+                entry.sequence = sequence;
+            }
+            None => {
+                if self.clients.len() >= self.capacity {
+                    // `BTreeMap::pop_first` needs a more recent compiler than we support
+                    let oldest = self.order.keys().next().copied();
+                    if let Some(ip) = oldest.and_then(|sequence| self.order.remove(&sequence)) {
+                        self.clients.remove(&ip);
+                    }
+                }
+
+                self.clients.insert(
+                    address.ip(),
+                    ClientEntry {
+                        address,
+                        packets: 1,
+                        first_seen: now,
+                        last_seen: now,
+                        mode,
+                        version,
+                        sequence,
+                    },
+                );
+            }
+        }
+
+        self.order.insert(sequence, address.ip());
+    }
+
+    /// The clients, the most recently seen first
+    pub fn observe(&self, now: Instant) -> Vec<ObservableClient> {
+        self.order
+            .values()
+            .rev()
+            .map(|ip| {
+                let entry = &self.clients[ip];
+                let average_interval = match entry.packets {
+                    0 | 1 => None,
+                    packets => Some(
+                        entry.last_seen.saturating_duration_since(entry.first_seen)
+                            / u32::try_from(packets - 1).unwrap_or(u32::MAX),
+                    ),
+                };
+
+                ObservableClient {
+                    address: entry.address,
+                    packets: entry.packets,
+                    last_seen: now.saturating_duration_since(entry.last_seen),
+                    average_interval,
+                    mode: entry.mode,
+                    version: entry.version,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The counters and recent clients of a server, shared by all of its workers
+#[derive(Debug)]
+pub struct ServerStats {
+    received: AtomicU64,
+    served: AtomicU64,
+    denied: AtomicU64,
+    ignored: AtomicU64,
+    rate_limited: AtomicU64,
+    malformed: AtomicU64,
+    version_mismatch: AtomicU64,
+    clients: Mutex<ClientList>,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        ServerStats {
+            received: AtomicU64::new(0),
+            served: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            ignored: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+            version_mismatch: AtomicU64::new(0),
+            clients: Mutex::new(ClientList::new(CLIENT_LIST_SIZE)),
+        }
+    }
+}
+
+impl ServerStats {
+    pub fn count_received(&self, packets: usize) {
+        self.received.fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Served => &self.served,
+            Outcome::Denied => &self.denied,
+            Outcome::Ignored => &self.ignored,
+            Outcome::RateLimited => &self.rate_limited,
+            Outcome::Malformed => &self.malformed,
+            Outcome::VersionMismatch => &self.version_mismatch,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The list of recent clients. Workers take this lock once for every batch of packets.
+    pub fn clients(&self) -> MutexGuard<'_, ClientList> {
+        // the list stays consistent even when a holder of the lock panicked
+        self.clients
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn statistics(&self) -> ServerStatistics {
+        ServerStatistics {
+            received: self.received.load(Ordering::Relaxed),
+            served: self.served.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            ignored: self.ignored.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            version_mismatch: self.version_mismatch.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_list() {
+        let start = Instant::now();
+        let mut list = ClientList::new(2);
+        let a: SocketAddr = "10.0.0.1:123".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:123".parse().unwrap();
+        let c: SocketAddr = "10.0.0.3:123".parse().unwrap();
+
+        list.record(a, 3, 4, start);
+        list.record(b, 3, 4, start + Duration::from_secs(1));
+        list.record(a, 3, 3, start + Duration::from_secs(16));
+
+        let clients = list.observe(start + Duration::from_secs(20));
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].address, a);
+        assert_eq!(clients[0].packets, 2);
+        assert_eq!(clients[0].version, 3);
+        assert_eq!(clients[0].last_seen, Duration::from_secs(4));
+        assert_eq!(clients[0].average_interval, Some(Duration::from_secs(16)));
+        assert_eq!(clients[1].address, b);
+        assert_eq!(clients[1].average_interval, None);
+
+        // the least recently seen client is forgotten
+        list.record(c, 3, 4, start + Duration::from_secs(21));
+        let clients = list.observe(start + Duration::from_secs(21));
+        let addresses: Vec<_> = clients.iter().map(|client| client.address).collect();
+        assert_eq!(addresses, vec![c, a]);
+
+        // a client is known by its ip address, the port can change
+        let a_other_port: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        list.record(a_other_port, 3, 4, start + Duration::from_secs(22));
+        let clients = list.observe(start + Duration::from_secs(22));
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].address, a_other_port);
+        assert_eq!(clients[0].packets, 3);
+    }
+}
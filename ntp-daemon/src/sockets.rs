@@ -27,6 +27,48 @@ where
     Ok(serde_json::from_slice(buffer).unwrap())
 }
 
+/// Read a value that is followed by the end of the stream. Unlike `read_json`, this also works
+/// for values that do not arrive in a single read, like the state of a busy server.
+pub async fn read_json_to_end<'a, T>(
+    stream: &mut UnixStream,
+    buffer: &'a mut Vec<u8>,
+) -> std::io::Result<T>
+where
+    T: serde::Deserialize<'a>,
+{
+    buffer.clear();
+
+    stream.read_to_end(buffer).await?;
+
+    serde_json::from_slice(buffer)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Like `read_json_to_end`, but fails with `InvalidData` when the stream holds more than `limit`
+/// bytes, so a client cannot make us buffer any amount of data
+pub async fn read_json_to_end_limited<'a, T>(
+    stream: &mut UnixStream,
+    buffer: &'a mut Vec<u8>,
+    limit: u64,
+) -> std::io::Result<T>
+where
+    T: serde::Deserialize<'a>,
+{
+    buffer.clear();
+
+    // one byte more than allowed tells us the limit was exceeded
+    (&mut *stream).take(limit + 1).read_to_end(buffer).await?;
+    if buffer.len() as u64 > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("more than {limit} bytes"),
+        ));
+    }
+
+    serde_json::from_slice(buffer)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
 pub fn create_unix_socket(path: &Path) -> std::io::Result<UnixListener> {
     use std::io::{Error, ErrorKind};
 
@@ -92,4 +134,63 @@ mod tests {
         // the logic will automatically grow the buffer to the required size
         assert!(!buf.is_empty());
     }
+
+    #[tokio::test]
+    async fn write_then_read_to_end_is_identity() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-7");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut writer = UnixStream::connect(&path).await.unwrap();
+
+        let (mut reader, _) = listener.accept().await.unwrap();
+
+        // larger than what a socket takes in one go
+        let object: Vec<usize> = (0..1_000_000).collect();
+
+        let written = tokio::spawn(async move {
+            write_json(&mut writer, &object).await.unwrap();
+            object
+        });
+
+        let mut buf = Vec::new();
+        let output = read_json_to_end::<Vec<usize>>(&mut reader, &mut buf)
+            .await
+            .unwrap();
+
+        assert_eq!(written.await.unwrap(), output);
+    }
+
+    #[tokio::test]
+    async fn read_to_end_limited() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join("ntp-test-stream-8");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let listener = UnixListener::bind(&path).unwrap();
+
+        for (object, limit, fits) in [(vec![1usize, 2], 5, true), (vec![10, 20, 30], 5, false)] {
+            let mut writer = UnixStream::connect(&path).await.unwrap();
+            let (mut reader, _) = listener.accept().await.unwrap();
+
+            write_json(&mut writer, &object).await.unwrap();
+            drop(writer);
+
+            let mut buf = Vec::new();
+            let output = read_json_to_end_limited::<Vec<usize>>(&mut reader, &mut buf, limit).await;
+            match output {
+                Ok(output) => {
+                    assert!(fits);
+                    assert_eq!(output, object);
+                }
+                Err(e) => {
+                    assert!(!fits);
+                    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+                }
+            }
+        }
+    }
 }
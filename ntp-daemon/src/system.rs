@@ -1,21 +1,25 @@
 use crate::{
     config::{PeerConfig, ServerConfig},
+    list_files::ListFiles,
     peer::{MsgForSystem, PeerChannels, ResetEpoch},
     peer_manager::Peers,
 };
 use ntp_os_clock::UnixNtpClock;
 use ntp_proto::{
-    ClockController, ClockUpdateResult, FilterAndCombine, NtpClock, NtpInstant, PeerSnapshot,
-    PollInterval, SystemConfig, SystemSnapshot,
+    measure_precision, ClockController, ClockUpdateResult, FilterAndCombine, NtpClock, NtpDuration,
+    NtpInstant, PeerSnapshot, PollInterval, SyncState, SystemConfig, SystemSnapshot,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::{
     sync::{mpsc, watch},
     task::JoinHandle,
 };
 
+/// How often to check whether the clock went into holdover, or the holdover expired
+const HOLDOVER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct DaemonChannels<C: NtpClock> {
     pub config: Arc<tokio::sync::RwLock<SystemConfig>>,
     pub peers: Arc<tokio::sync::RwLock<Peers<C>>>,
@@ -39,13 +43,18 @@ pub async fn spawn(
     let (msg_for_system_tx, msg_for_system_rx) = mpsc::channel::<MsgForSystem>(32);
 
     // System snapshot
-    let system_snapshot = SystemSnapshot::default();
+    let system_snapshot = SystemSnapshot {
+        poll_interval: config.poll_limits.min,
+        precision: system_precision(&UnixNtpClock::new()),
+        ..Default::default()
+    };
 
     // Clock controller
     let controller = ClockController::new(UnixNtpClock::new(), &system_snapshot);
 
     // Daemon channels
     let system = Arc::new(tokio::sync::RwLock::new(system_snapshot));
+    let (system_snapshot_sender, system_snapshot_updates) = watch::channel(system_snapshot);
     let config = Arc::new(tokio::sync::RwLock::new(config));
     let mut peers = Peers::new(
         PeerChannels {
@@ -53,6 +62,7 @@ pub async fn spawn(
             system_snapshots: system.clone(),
             reset: reset_rx.clone(),
             system_config: config.clone(),
+            system_snapshot_updates,
         },
         UnixNtpClock::new(),
     );
@@ -61,7 +71,10 @@ pub async fn spawn(
     }
 
     for server_config in server_configs.iter() {
-        peers.add_server(server_config.to_owned()).await;
+        let files = ListFiles::read(server_config).await;
+        if !peers.add_server(server_config.to_owned(), files) {
+            warn!(addr = ?server_config.addr, "ignoring duplicate server");
+        }
     }
 
     let peers = Arc::new(tokio::sync::RwLock::new(peers));
@@ -80,9 +93,11 @@ pub async fn spawn(
 
             msg_for_system_rx,
             reset_tx,
+            system_snapshot_sender,
 
             reset_epoch,
             controller,
+            clock: UnixNtpClock::new(),
         };
 
         system.run().await
@@ -98,16 +113,31 @@ struct System<C: NtpClock> {
 
     msg_for_system_rx: mpsc::Receiver<MsgForSystem>,
     reset_tx: watch::Sender<ResetEpoch>,
+    /// Copies of the global system snapshot for the server workers
+    system_snapshot_sender: watch::Sender<SystemSnapshot>,
 
     reset_epoch: ResetEpoch,
     controller: ClockController<C>,
+    clock: C,
 }
 
 impl<C: NtpClock> System<C> {
     async fn run(&mut self) -> std::io::Result<()> {
         let mut snapshots = Vec::with_capacity(self.peers_rwlock.read().await.size());
+        let mut holdover_check = tokio::time::interval(HOLDOVER_CHECK_INTERVAL);
+
+        loop {
+            let msg_for_system = tokio::select! {
+                msg = self.msg_for_system_rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = holdover_check.tick() => {
+                    self.check_holdover().await;
+                    continue;
+                }
+            };
 
-        while let Some(msg_for_system) = self.msg_for_system_rx.recv().await {
             let ntp_instant = NtpInstant::now();
             let system = *self.global_system_snapshot.read().await;
 
@@ -150,6 +180,11 @@ impl<C: NtpClock> System<C> {
             Some(clock_select) => clock_select,
             None => {
                 info!("filter and combine did not produce a result");
+                let mut global = self.global_system_snapshot.write().await;
+                if global.enter_holdover() {
+                    warn!("No usable peers, going into holdover");
+                    self.system_snapshot_sender.send_replace(*global);
+                }
                 return;
             }
         };
@@ -188,6 +223,38 @@ impl<C: NtpClock> System<C> {
             global.accumulated_steps_threshold = config.accumulated_threshold;
             global.root_delay = clock_select.system_root_delay;
             global.root_dispersion = clock_select.system_root_dispersion;
+            match self.clock.now() {
+                Ok(now) => global.reference_timestamp = now,
+                Err(error) => warn!(?error, "could not read the time of the clock update"),
+            }
+            if global.sync_state != SyncState::Synchronized {
+                info!(previous = ?global.sync_state, "Clock synchronized");
+                global.sync_state = SyncState::Synchronized;
+            }
+            self.system_snapshot_sender.send_replace(*global);
+        }
+    }
+
+    async fn check_holdover(&mut self) {
+        let now = match self.clock.now() {
+            Ok(now) => now,
+            Err(error) => {
+                warn!(?error, "could not read the clock to check the holdover");
+                return;
+            }
+        };
+
+        let max_holdover = self.config.read().await.max_holdover;
+        let mut global = self.global_system_snapshot.write().await;
+        if global.age_sync_state(now, max_holdover) {
+            self.system_snapshot_sender.send_replace(*global);
+            match global.sync_state {
+                SyncState::Holdover => warn!("No recent clock updates, going into holdover"),
+                SyncState::Unsynchronized => {
+                    warn!("Holdover expired, reporting the clock as unsynchronized")
+                }
+                SyncState::Synchronized => {}
+            }
         }
     }
 
@@ -198,6 +265,52 @@ impl<C: NtpClock> System<C> {
     }
 }
 
+/// How precise our time is: the precision with which the clock can be read, or the resolution of
+/// the timestamps the kernel puts on packets, whichever is worse. This is advertised as a power
+/// of two, so round it up to one.
+fn system_precision<C: NtpClock>(clock: &C) -> NtpDuration {
+    let default = SystemSnapshot::default().precision;
+    let read_precision = match measure_precision(clock) {
+        Ok(Some(precision)) => precision,
+        Ok(None) => {
+            warn!("the clock does not advance, using the default precision");
+            default
+        }
+        Err(error) => {
+            warn!(?error, "could not measure the precision of the clock");
+            default
+        }
+    };
+
+    let timestamp_resolution = match ntp_udp::timestamp_resolution() {
+        Ok(resolution) => resolution,
+        Err(error) => {
+            warn!(
+                ?error,
+                "could not determine the resolution of packet timestamps"
+            );
+            NtpDuration::ZERO
+        }
+    };
+
+    let precision = Ord::max(read_precision, timestamp_resolution);
+    let exponent = precision.log2();
+    let precision = if NtpDuration::from_exponent(exponent) < precision {
+        NtpDuration::from_exponent(exponent.saturating_add(1))
+    } else {
+        NtpDuration::from_exponent(exponent)
+    };
+
+    info!(
+        read_precision_ns = read_precision.to_seconds() * 1e9,
+        timestamp_resolution_ns = timestamp_resolution.to_seconds() * 1e9,
+        precision = precision.log2(),
+        "Measured the clock precision"
+    );
+
+    precision
+}
+
 fn requires_clock_recalculation(
     msg: MsgForSystem,
     current_reset_epoch: ResetEpoch,
@@ -214,6 +327,7 @@ fn requires_clock_recalculation(
                     config.frequency_tolerance,
                     config.distance_threshold,
                     system_poll,
+                    config.max_stratum,
                 )
                 .is_ok()
     } else {
@@ -226,7 +340,7 @@ mod tests {
     use ntp_proto::{peer_snapshot, NtpDuration, NtpLeapIndicator, NtpTimestamp, PeerStatistics};
 
     use crate::{
-        config::{NormalizedAddress, StandardPeerConfig},
+        config::{NormalizedAddress, PeerOptions, StandardPeerConfig},
         peer_manager::{PeerIndex, PeerStatus},
     };
 
@@ -262,6 +376,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_system_precision() {
+        let precision = system_precision(&UnixNtpClock::new());
+        assert!(precision > NtpDuration::ZERO);
+        assert!(precision < NtpDuration::from_seconds(0.01));
+        assert_eq!(NtpDuration::from_exponent(precision.log2()), precision);
+
+        // falls back to the default when the clock cannot be read
+        let precision = system_precision(&TestClock {});
+        assert_eq!(precision, SystemSnapshot::default().precision);
+    }
+
     #[test]
     fn test_requires_clock_recalculation() {
         let base = NtpInstant::now();
@@ -386,15 +512,19 @@ mod tests {
             &[
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("127.0.0.1:123"),
+                    options: PeerOptions::default(),
                 }),
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("127.0.0.2:123"),
+                    options: PeerOptions::default(),
                 }),
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("127.0.0.3:123"),
+                    options: PeerOptions::default(),
                 }),
                 PeerConfig::Standard(StandardPeerConfig {
                     addr: NormalizedAddress::new_unchecked("127.0.0.4:123"),
+                    options: PeerOptions::default(),
                 }),
             ],
             TestClock {},
@@ -410,9 +540,11 @@ mod tests {
 
                 msg_for_system_rx,
                 reset_tx,
+                system_snapshot_sender: watch::channel(SystemSnapshot::default()).0,
 
                 reset_epoch,
                 controller: ClockController::new(TestClock {}, &SystemSnapshot::default()),
+                clock: TestClock {},
             };
 
             system.run().await
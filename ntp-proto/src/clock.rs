@@ -7,6 +7,11 @@ use tracing::{debug, error, info, instrument, trace};
 /// Jitter averaging factor
 const JITTER_AVG: f64 = 4.;
 
+/// Number of clock ticks observed to measure the precision of a clock
+const PRECISION_SAMPLES: usize = 64;
+/// Number of reads after which we give up waiting for a clock to tick
+const PRECISION_MAX_READS: usize = 100_000;
+
 /// Interface for a clock settable by the ntp implementation.
 /// This needs to be a trait as a single system can have multiple clocks
 /// which need different implementation for steering and/or now.
@@ -27,6 +32,34 @@ pub trait NtpClock: Clone + Send + 'static {
     ) -> Result<(), Self::Error>;
 }
 
+/// Measure the precision of `clock`: the smallest step between two reads that return different
+/// times. This is the tick of a coarse clock, or the time it takes to read a fine grained one.
+/// Returns `None` when the clock does not advance.
+pub fn measure_precision<C: NtpClock>(clock: &C) -> Result<Option<NtpDuration>, C::Error> {
+    let mut precision: Option<NtpDuration> = None;
+
+    for _ in 0..PRECISION_SAMPLES {
+        let start = clock.now()?;
+        let mut step = None;
+        for _ in 0..PRECISION_MAX_READS {
+            let now = clock.now()?;
+            if now != start {
+                step = Some(now - start);
+                break;
+            }
+        }
+
+        match step {
+            // the clock went back, e.g. because it was stepped while measuring
+            Some(step) if step <= NtpDuration::ZERO => {}
+            Some(step) => precision = Some(precision.map_or(step, |p| Ord::min(p, step))),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(precision)
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum ClockState {
     StartupBlank,
@@ -74,7 +107,7 @@ impl<C: NtpClock> ClockController<C> {
             // Setting up the clock counts as an update for
             // the purposes of the math done here
             last_update_time: NtpInstant::now(),
-            preferred_poll_interval: PollInterval::MIN,
+            preferred_poll_interval: system.poll_interval,
             poll_interval_counter: 0,
             offset: NtpDuration::ZERO,
             jitter: system.precision,
@@ -140,7 +173,7 @@ impl<C: NtpClock> ClockController<C> {
                     }
 
                     self.set_freq(offset, last_peer_update);
-                    return self.do_step(offset, last_peer_update, system.precision);
+                    return self.do_step(config, offset, last_peer_update, system.precision);
                 }
                 ClockState::Spike => {
                     if NtpInstant::abs_diff(last_peer_update, self.last_update_time)
@@ -154,13 +187,13 @@ impl<C: NtpClock> ClockController<C> {
                     // Seems that the large difference reflects reality, since
                     // it persisted for a significant amount of time. So step
                     // the clock
-                    return self.do_step(offset, last_peer_update, system.precision);
+                    return self.do_step(config, offset, last_peer_update, system.precision);
                 }
                 ClockState::StartupBlank | ClockState::StartupFreq => {
                     // In fully non-synchronized states, doing the jump
                     // immediately is fine, as we expect the clock to
                     // be off significantly
-                    return self.do_step(offset, last_peer_update, system.precision);
+                    return self.do_step(config, offset, last_peer_update, system.precision);
                 }
             }
         } else {
@@ -172,7 +205,7 @@ impl<C: NtpClock> ClockController<C> {
                     // Using slew might result in us also accidentaly
                     // moving away from the freq=0 initialization done earlier,
                     // ruining the frequency measurement coming after.
-                    return self.do_step(offset, last_peer_update, system.precision);
+                    return self.do_step(config, offset, last_peer_update, system.precision);
                 }
                 ClockState::MeasureFreq => {
                     if NtpInstant::abs_diff(last_peer_update, self.last_update_time)
@@ -230,10 +263,14 @@ impl<C: NtpClock> ClockController<C> {
         // Note, our behaviour matches the code skeleton of rfc5905
         // fully, instead of the main text on page 50. This is needed
         // to improve responsiveness to upset events.
+        //
+        // Sub-second poll intervals have a non-positive exponent, which would
+        // stall or invert the hysteresis, so they are weighted as 2s intervals.
+        let poll_weight = self.preferred_poll_interval.as_log().max(1) as i32;
         if self.offset < self.jitter * Self::POLL_FACTOR {
-            self.poll_interval_counter += self.preferred_poll_interval.as_log() as i32;
+            self.poll_interval_counter += poll_weight;
         } else {
-            self.poll_interval_counter -= 2 * (self.preferred_poll_interval.as_log() as i32);
+            self.poll_interval_counter -= 2 * poll_weight;
         }
 
         trace!(
@@ -246,7 +283,7 @@ impl<C: NtpClock> ClockController<C> {
         // between different preferred interval lengths.
         if self.poll_interval_counter > Self::POLL_ADJUST {
             self.poll_interval_counter = 0;
-            self.preferred_poll_interval = self.preferred_poll_interval.inc();
+            self.preferred_poll_interval = self.preferred_poll_interval.inc(config.poll_limits);
             debug!(
                 poll_interval = debug(self.preferred_poll_interval),
                 "Increased system poll interval"
@@ -254,7 +291,7 @@ impl<C: NtpClock> ClockController<C> {
         }
         if self.poll_interval_counter < -Self::POLL_ADJUST {
             self.poll_interval_counter = 0;
-            self.preferred_poll_interval = self.preferred_poll_interval.dec();
+            self.preferred_poll_interval = self.preferred_poll_interval.dec(config.poll_limits);
             debug!(
                 poll_interval = debug(self.preferred_poll_interval),
                 "Decreased system poll interval"
@@ -320,13 +357,14 @@ impl<C: NtpClock> ClockController<C> {
 
     fn do_step(
         &mut self,
+        config: &SystemConfig,
         offset: NtpDuration,
         last_peer_update: NtpInstant,
         precision: NtpDuration,
     ) -> ClockUpdateResult {
         info!(offset = debug(offset), "Stepping clock");
         self.poll_interval_counter = 0;
-        self.preferred_poll_interval = PollInterval::MIN;
+        self.preferred_poll_interval = config.poll_limits.min;
         // It is reasonable to panic here, as there is very little we can
         // be expected to do if the clock is not amenable to change
         if let Err(e) = self.clock.step_clock(offset) {
@@ -371,6 +409,7 @@ impl<C: NtpClock> ClockController<C> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::PollIntervalLimits;
     use core::cell::RefCell;
     use std::time::Duration;
 
@@ -464,7 +503,9 @@ mod tests {
             *controller.clock.last_poll_interval.borrow()
         );
 
-        controller.preferred_poll_interval = controller.preferred_poll_interval.inc();
+        controller.preferred_poll_interval = controller
+            .preferred_poll_interval
+            .inc(PollIntervalLimits::default());
         let ref_interval = controller.preferred_poll_interval;
 
         assert_eq!(
@@ -676,7 +717,7 @@ mod tests {
             clock: TestClock::default(),
             state: ClockState::Sync,
             last_update_time: base,
-            preferred_poll_interval: PollInterval::MIN,
+            preferred_poll_interval: system.poll_interval,
             poll_interval_counter: 0,
             offset: NtpDuration::ZERO,
             jitter: system.precision,
@@ -1005,4 +1046,63 @@ mod tests {
             ClockUpdateResult::Step
         );
     }
+
+    #[derive(Debug, Clone, Default)]
+    struct TickingClock {
+        reads: RefCell<u32>,
+        /// Number of reads between ticks, `None` for a clock that never ticks
+        reads_per_tick: Option<u32>,
+    }
+
+    impl NtpClock for TickingClock {
+        type Error = std::io::Error;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            let mut reads = self.reads.borrow_mut();
+            *reads += 1;
+            let ticks = match self.reads_per_tick {
+                Some(reads_per_tick) => *reads / reads_per_tick,
+                None => 0,
+            };
+            // a millisecond tick
+            Ok(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                1_000 + ticks / 1000,
+                (ticks % 1000) * 1_000_000,
+            ))
+        }
+
+        fn set_freq(&self, _freq: f64) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn update_clock(
+            &self,
+            _offset: NtpDuration,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+            _poll_interval: PollInterval,
+            _leap_status: NtpLeapIndicator,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_measure_precision() {
+        let clock = TickingClock {
+            reads_per_tick: Some(7),
+            ..Default::default()
+        };
+        let precision = measure_precision(&clock).unwrap().unwrap();
+        assert!((precision.to_seconds() - 0.001).abs() < 1e-9);
+
+        let clock = TickingClock::default();
+        assert_eq!(measure_precision(&clock).unwrap(), None);
+
+        assert!(measure_precision(&TestClock::default()).is_err());
+    }
 }
@@ -30,7 +30,12 @@ impl FilterAndCombine {
         // so, it keeps that peer as the system peer rather selecting the now-best peer (something
         // it calls clock hopping). We'll have to see if that is something we should do too;
         // the spec text does not talk about keeping the existing system peer if it's in the candidate list
-        let system_peer_snapshot = *selection.survivors[0].peer;
+        //
+        // A prefer peer that survived takes precedence over the best peer
+        let system_peer_snapshot = match prefer_peer(&selection.survivors) {
+            Some(peer) => *peer,
+            None => *selection.survivors[0].peer,
+        };
 
         let combined = clock_combine(
             &selection.survivors,
@@ -131,10 +136,14 @@ fn clock_select<'a>(
             config.frequency_tolerance,
             config.distance_threshold,
             system_poll,
+            config.max_stratum,
         )
         .is_ok()
     });
 
+    // noselect peers are only measured, and never take part in selecting the time
+    let valid_associations = valid_associations.filter(|p| !p.noselect);
+
     let candidates = construct_candidate_list(config, valid_associations, local_clock_time);
 
     let mut survivors = construct_survivors(config, &candidates, local_clock_time);
@@ -241,9 +250,9 @@ fn filter_survivor<'a>(
     // Note: The standard is unclear on this, but this
     // is what gives sensible results in combination with
     // how interval selection works.
-    if candidate.edge < low
-        || candidate.edge > high
-        || candidate.endpoint_type != EndpointType::Middle
+    // Trusted peers are truechimers by definition
+    if candidate.endpoint_type != EndpointType::Middle
+        || ((candidate.edge < low || candidate.edge > high) && !candidate.peer.trust)
     {
         None
     } else {
@@ -256,6 +265,9 @@ fn filter_survivor<'a>(
 }
 
 /// Find the largest contiguous intersection of correctness intervals.
+///
+/// Trusted peers are never counted as falsetickers, their offset is accepted even when it
+/// lies outside of the intersection.
 #[instrument]
 fn find_interval(chime_list: &[CandidateTuple]) -> Option<(NtpDuration, NtpDuration)> {
     let n = chime_list.len() / 3;
@@ -283,7 +295,7 @@ fn find_interval(chime_list: &[CandidateTuple]) -> Option<(NtpDuration, NtpDurat
                 break;
             }
 
-            if let EndpointType::Middle = tuple.endpoint_type {
+            if tuple.endpoint_type == EndpointType::Middle && !tuple.peer.trust {
                 found += 1;
             }
         }
@@ -300,7 +312,7 @@ fn find_interval(chime_list: &[CandidateTuple]) -> Option<(NtpDuration, NtpDurat
                 break;
             }
 
-            if let EndpointType::Middle = tuple.endpoint_type {
+            if tuple.endpoint_type == EndpointType::Middle && !tuple.peer.trust {
                 found += 1;
             }
         }
@@ -423,12 +435,26 @@ pub struct ClockCombine {
 ///
 /// Assumption: the survivors are the output of the clustering algorithm,
 /// in particular they are in the order produced by the clustering algorithm.
+///
+/// Like the reference implementation, the offset of a surviving prefer peer is used
+/// directly instead of the weighted average.
 fn clock_combine<'a>(
     survivors: &'a [SurvivorTuple<'a>],
     system_selection_jitter: NtpDuration,
     local_clock_time: NtpInstant,
     frequency_tolerance: FrequencyTolerance,
 ) -> ClockCombine {
+    if let Some(peer) = prefer_peer(survivors) {
+        let system_jitter = NtpDuration::from_seconds(
+            (system_selection_jitter.to_seconds().powi(2) + peer.statistics.jitter.powi(2)).sqrt(),
+        );
+
+        return ClockCombine {
+            system_offset: peer.statistics.offset,
+            system_jitter,
+        };
+    }
+
     let mut y = 0.0; // normalization factor
     let mut z = 0.0; // weighed offset sum
 
@@ -458,6 +484,14 @@ fn clock_combine<'a>(
     }
 }
 
+/// The best surviving peer that is marked as prefer, if any
+fn prefer_peer<'a>(survivors: &[SurvivorTuple<'a>]) -> Option<&'a PeerSnapshot> {
+    survivors
+        .iter()
+        .map(|tuple| tuple.peer)
+        .find(|peer| peer.prefer)
+}
+
 #[cfg(feature = "fuzz")]
 pub fn fuzz_find_interval(spec: &[(i64, u64)]) {
     let instant = NtpInstant::now();
@@ -538,6 +572,11 @@ pub fn peer_snapshot(
         our_id: ReferenceId::from_int(1),
         reach,
         poll_interval: PollInterval::MIN,
+        prefer: false,
+        noselect: false,
+        trust: false,
+        ignored: Default::default(),
+        raw_offset: statistics.offset,
     }
 }
 
@@ -1442,4 +1481,75 @@ mod test {
         assert!(result.system_root_dispersion > NtpDuration::from_seconds(0.001));
         assert!(result.system_root_delay > baseline_result.system_root_delay);
     }
+
+    fn agreeing_peers(instant: NtpInstant) -> [PeerSnapshot; 3] {
+        let offsets = [0.0, 0.001, -0.001];
+        offsets.map(|offset| {
+            peer_snapshot(
+                PeerStatistics {
+                    offset: NtpDuration::from_seconds(offset),
+                    delay: NtpDuration::from_seconds(0.01),
+                    dispersion: NtpDuration::from_seconds(0.001),
+                    jitter: 0.001,
+                },
+                instant,
+                NtpDuration::from_seconds(0.001),
+                NtpDuration::from_seconds(0.001),
+            )
+        })
+    }
+
+    #[test]
+    fn test_noselect() {
+        let base = NtpInstant::now();
+        let mut config = SystemConfig::default();
+
+        let mut peers = agreeing_peers(base);
+        assert!(FilterAndCombine::run(&config, &peers, base, PollInterval::MIN).is_some());
+
+        // too few peers are left to agree on the time
+        peers[0].noselect = true;
+        assert!(FilterAndCombine::run(&config, &peers, base, PollInterval::MIN).is_none());
+
+        // the remaining peer alone determines the time
+        config.min_intersection_survivors = 1;
+        peers[2].noselect = true;
+        let result = FilterAndCombine::run(&config, &peers, base, PollInterval::MIN).unwrap();
+        assert_eq!(result.system_offset, peers[1].statistics.offset);
+    }
+
+    #[test]
+    fn test_prefer() {
+        let base = NtpInstant::now();
+        let config = SystemConfig::default();
+
+        let mut peers = agreeing_peers(base);
+        let result = FilterAndCombine::run(&config, &peers, base, PollInterval::MIN).unwrap();
+        assert_ne!(result.system_offset, peers[2].statistics.offset);
+
+        peers[2].prefer = true;
+        let result = FilterAndCombine::run(&config, &peers, base, PollInterval::MIN).unwrap();
+        assert_eq!(result.system_offset, peers[2].statistics.offset);
+        assert!(result.system_peer_snapshot.prefer);
+    }
+
+    #[test]
+    fn test_trust() {
+        let base = NtpInstant::now();
+        let config = SystemConfig::default();
+
+        let mut peers = agreeing_peers(base);
+        // far away from the other two peers
+        peers[2].statistics.offset = NtpDuration::from_seconds(1.0);
+
+        let candidates = construct_candidate_list(&config, &peers, base);
+        let survivors = construct_survivors(&config, &candidates, base);
+        assert_eq!(survivors.len(), 2);
+        assert!(survivors.iter().all(|s| !std::ptr::eq(s.peer, &peers[2])));
+
+        peers[2].trust = true;
+        let candidates = construct_candidate_list(&config, &peers, base);
+        let survivors = construct_survivors(&config, &candidates, base);
+        assert_eq!(survivors.len(), 3);
+    }
 }
@@ -2,21 +2,136 @@ use std::fmt;
 
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{time_types::FrequencyTolerance, NtpDuration};
+use crate::{
+    time_types::{parse_seconds, FrequencyTolerance},
+    NtpDuration, PollInterval, PollIntervalLimits,
+};
 
 fn deserialize_option_threshold<'de, D>(deserializer: D) -> Result<Option<NtpDuration>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let duration: NtpDuration = Deserialize::deserialize(deserializer)?;
-    Ok(if duration == NtpDuration::ZERO {
-        None
+    Threshold::deserialize(deserializer).map(|threshold| threshold.0)
+}
+
+/// Durations in the configuration are written as seconds. Converting to and from our internal
+/// representation is not exact, so round to microseconds to get back what was configured.
+pub fn config_seconds(duration: NtpDuration) -> f64 {
+    (duration.to_seconds() * 1e6).round() / 1e6
+}
+
+fn serialize_seconds<S>(duration: &NtpDuration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    config_seconds(*duration).serialize(serializer)
+}
+
+fn serialize_option_threshold<S>(
+    duration: &Option<NtpDuration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    Threshold(*duration).serialize(serializer)
+}
+
+/// The smallest threshold other than "unlimited". Thresholds are written back rounded to
+/// microseconds (see `config_seconds`), so a smaller one would come back as 0, meaning no limit.
+pub const MIN_THRESHOLD_SECONDS: f64 = 1e-6;
+
+/// A single step threshold: a duration, or "unlimited" (0 means the same for compatibility)
+struct Threshold(Option<NtpDuration>);
+
+impl Threshold {
+    const EXPECTED: &'static str = "a duration of at least 1us, 0 or \"unlimited\"";
+
+    fn from_seconds<E: de::Error>(seconds: f64) -> Result<Self, E> {
+        if seconds == 0.0 || seconds == f64::INFINITY {
+            Ok(Threshold(None))
+        } else if seconds.is_finite() && seconds >= MIN_THRESHOLD_SECONDS {
+            Ok(Threshold(Some(NtpDuration::from_seconds(seconds))))
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Float(seconds),
+                &Self::EXPECTED,
+            ))
+        }
+    }
+
+    fn from_str<E: de::Error>(value: &str) -> Result<Self, E> {
+        match value.trim() {
+            "unlimited" | "inf" | "infinity" => Ok(Threshold(None)),
+            value => Self::from_seconds(parse_seconds(value).map_err(de::Error::custom)?),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ThresholdVisitor;
+
+        impl<'de> Visitor<'de> for ThresholdVisitor {
+            type Value = Threshold;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(Threshold::EXPECTED)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Threshold, E> {
+                Threshold::from_seconds(v)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Threshold, E> {
+                Threshold::from_seconds(v as f64)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Threshold, E> {
+                Threshold::from_seconds(v as f64)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Threshold, E> {
+                Threshold::from_str(v)
+            }
+        }
+
+        deserializer.deserialize_any(ThresholdVisitor)
+    }
+}
+
+impl Serialize for Threshold {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Some(duration) => config_seconds(duration).serialize(serializer),
+            None => serializer.serialize_str("unlimited"),
+        }
+    }
+}
+
+fn deserialize_max_stratum<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let max_stratum: u8 = Deserialize::deserialize(deserializer)?;
+    if (1..=15).contains(&max_stratum) {
+        Ok(max_stratum)
     } else {
-        Some(duration)
-    })
+        Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(max_stratum as u64),
+            &"a stratum between 1 and 15",
+        ))
+    }
 }
 
 #[derive(Debug, Default, Copy, Clone)]
@@ -38,25 +153,18 @@ impl<'de> Deserialize<'de> for StepThreshold {
             type Value = StepThreshold;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("float or map")
+                formatter.write_str("a duration, \"unlimited\" or a map")
             }
 
             fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
             where
                 E: de::Error,
             {
-                let duration = NtpDuration::from_seconds(v);
-                if duration == NtpDuration::ZERO {
-                    Ok(StepThreshold {
-                        forward: None,
-                        backward: None,
-                    })
-                } else {
-                    Ok(StepThreshold {
-                        forward: Some(duration),
-                        backward: Some(duration),
-                    })
-                }
+                let Threshold(duration) = Threshold::from_seconds(v)?;
+                Ok(StepThreshold {
+                    forward: duration,
+                    backward: duration,
+                })
             }
 
             fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
@@ -73,6 +181,17 @@ impl<'de> Deserialize<'de> for StepThreshold {
                 self.visit_f64(v as f64)
             }
 
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let Threshold(duration) = Threshold::from_str(v)?;
+                Ok(StepThreshold {
+                    forward: duration,
+                    backward: duration,
+                })
+            }
+
             fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<StepThreshold, M::Error> {
                 let mut forward = None;
                 let mut backward = None;
@@ -83,28 +202,18 @@ impl<'de> Deserialize<'de> for StepThreshold {
                             if forward.is_some() {
                                 return Err(de::Error::duplicate_field("forward"));
                             }
-                            let raw: NtpDuration = map.next_value()?;
-
-                            if NtpDuration::ZERO == raw {
-                                forward = Some(None)
-                            } else {
-                                forward = Some(Some(raw))
-                            }
+                            let Threshold(raw) = map.next_value()?;
+                            forward = Some(raw);
                         }
                         "backward" => {
                             if backward.is_some() {
                                 return Err(de::Error::duplicate_field("backward"));
                             }
-                            let raw: NtpDuration = map.next_value()?;
-
-                            if NtpDuration::ZERO == raw {
-                                backward = Some(None)
-                            } else {
-                                backward = Some(Some(raw))
-                            }
+                            let Threshold(raw) = map.next_value()?;
+                            backward = Some(raw);
                         }
                         _ => {
-                            return Err(de::Error::unknown_field(key, &["addr", "mode"]));
+                            return Err(de::Error::unknown_field(key, &["forward", "backward"]));
                         }
                     }
                 }
@@ -120,7 +229,81 @@ impl<'de> Deserialize<'de> for StepThreshold {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+impl Serialize for StepThreshold {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("forward", &Threshold(self.forward))?;
+        map.serialize_entry("backward", &Threshold(self.backward))?;
+        map.end()
+    }
+}
+
+/// Options that can be set for every peer, regardless of its mode
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct PeerOptions {
+    /// Lower limit of the poll interval, the system limit is used when not given
+    pub min_poll: Option<PollInterval>,
+    /// Upper limit of the poll interval, the system limit is used when not given
+    pub max_poll: Option<PollInterval>,
+    /// Send a quick burst of polls at startup and after a reset
+    pub iburst: bool,
+    /// Send several requests on every poll, and only use the response with the lowest delay
+    pub burst: bool,
+    /// Favour this peer as the system peer when it survives clock selection
+    pub prefer: bool,
+    /// Only measure this peer, never use it to synchronize the clock
+    pub noselect: bool,
+    /// Never discard this peer as a falseticker
+    pub trust: bool,
+    /// Samples with a larger round-trip delay are discarded
+    pub max_delay: Option<NtpDuration>,
+    /// Samples with a round-trip delay larger than this multiple of the minimum delay
+    /// of the recent samples are discarded
+    pub max_delay_ratio: Option<f64>,
+    /// Fixed correction added to every offset measured for this peer
+    pub offset: NtpDuration,
+    /// Fraction of the round-trip delay spent on the path to the peer. When not given, the
+    /// path is assumed to be symmetric
+    pub asymmetry: Option<f64>,
+}
+
+impl PeerOptions {
+    /// The poll interval limits for this peer. When only one of the limits is configured for
+    /// the peer, it takes precedence over the system limit for the other side.
+    pub fn poll_limits(&self, system: PollIntervalLimits) -> PollIntervalLimits {
+        match (self.min_poll, self.max_poll) {
+            (Some(min), Some(max)) => PollIntervalLimits { min, max },
+            (Some(min), None) => PollIntervalLimits {
+                min,
+                max: system.max.max(min),
+            },
+            (None, Some(max)) => PollIntervalLimits {
+                min: system.min.min(max),
+                max,
+            },
+            (None, None) => system,
+        }
+    }
+
+    /// The correction to add to an offset measured with the given round-trip delay.
+    ///
+    /// The measured offset assumes that half of the delay is spent on the way out. When
+    /// a fraction `a` is spent on the way out instead, the real offset is lower by
+    /// `(a - 1/2) * delay`.
+    pub fn offset_correction(&self, delay: NtpDuration) -> NtpDuration {
+        let asymmetry = match self.asymmetry {
+            Some(asymmetry) => NtpDuration::from_seconds((asymmetry - 0.5) * delay.to_seconds()),
+            None => NtpDuration::ZERO,
+        };
+
+        self.offset - asymmetry
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "kebab-case")]
 pub struct SystemConfig {
     /// Minimum number of survivors needed to be able to discipline the system clock.
@@ -152,18 +335,27 @@ pub struct SystemConfig {
 
     /// A distance error occurs if the root distance exceeds the
     /// distance threshold plus an increment equal to one poll interval.
-    #[serde(default = "default_distance_threshold")]
+    #[serde(
+        default = "default_distance_threshold",
+        serialize_with = "serialize_seconds"
+    )]
     pub distance_threshold: NtpDuration,
 
     /// The amount of time to use to measure the system clocks frequency error
     /// on startup. Longer time periods give a more accurate initial estimate,
     /// but it will take longer for the clock to be fully synchronized
-    #[serde(default = "default_frequency_measurement_period")]
+    #[serde(
+        default = "default_frequency_measurement_period",
+        serialize_with = "serialize_seconds"
+    )]
     pub frequency_measurement_period: NtpDuration,
 
     /// The amount of time before a spike (a time difference greater than 0.125s)
     /// is considered real and not the result of a transient network condition
-    #[serde(default = "default_spike_threshold")]
+    #[serde(
+        default = "default_spike_threshold",
+        serialize_with = "serialize_seconds"
+    )]
     pub spike_threshold: NtpDuration,
 
     /// The maximum amount the system clock is allowed to change in a single go
@@ -184,8 +376,29 @@ pub struct SystemConfig {
 
     /// The maximum amount distributed amongst all steps except at startup the
     /// daemon is allowed to step the system clock.
-    #[serde(deserialize_with = "deserialize_option_threshold", default)]
+    #[serde(
+        deserialize_with = "deserialize_option_threshold",
+        serialize_with = "serialize_option_threshold",
+        default
+    )]
     pub accumulated_threshold: Option<NtpDuration>,
+
+    /// The range in which the system poll interval is allowed to move. This is
+    /// also the range used by peers that do not configure their own limits.
+    #[serde(default)]
+    pub poll_limits: PollIntervalLimits,
+
+    /// Highest stratum of a peer that we are willing to synchronize to
+    #[serde(
+        default = "default_max_stratum",
+        deserialize_with = "deserialize_max_stratum"
+    )]
+    pub max_stratum: u8,
+
+    /// How long after the last clock update we keep serving time when the peers can no longer
+    /// be used. After this, the system reports that it is unsynchronized.
+    #[serde(default = "default_max_holdover", serialize_with = "serialize_seconds")]
+    pub max_holdover: NtpDuration,
 }
 
 impl Default for SystemConfig {
@@ -201,10 +414,21 @@ impl Default for SystemConfig {
             panic_threshold: default_panic_threshold(),
             startup_panic_threshold: StepThreshold::default(),
             accumulated_threshold: None,
+            poll_limits: PollIntervalLimits::default(),
+            max_stratum: default_max_stratum(),
+            max_holdover: default_max_holdover(),
         }
     }
 }
 
+fn default_max_stratum() -> u8 {
+    15
+}
+
+fn default_max_holdover() -> NtpDuration {
+    NtpDuration::from_seconds(6.0 * 3600.0)
+}
+
 fn default_min_intersection_survivors() -> usize {
     3
 }
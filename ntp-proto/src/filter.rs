@@ -7,6 +7,7 @@
 //
 //      https://datatracker.ietf.org/doc/html/rfc5905#appendix-A.5.2
 
+use crate::config::PeerOptions;
 use crate::packet::NtpAssociationMode;
 use crate::peer::PeerStatistics;
 use crate::time_types::{FrequencyTolerance, NtpInstant};
@@ -31,6 +32,10 @@ impl FilterTuple {
         }
     }
 
+    pub(crate) fn delay(&self) -> NtpDuration {
+        self.delay
+    }
+
     fn is_dummy(self) -> bool {
         self.offset == NtpDuration::ZERO
             && self.delay == NtpDuration::MAX_DISPERSION
@@ -41,6 +46,8 @@ impl FilterTuple {
     ///
     /// A Broadcast association requires different logic.
     /// All other associations should use this function
+    ///
+    /// The offset includes the static offset and asymmetry correction of the peer's options.
     pub(crate) fn from_packet_default(
         packet: &NtpHeader,
         system_precision: NtpDuration,
@@ -48,6 +55,7 @@ impl FilterTuple {
         frequency_tolerance: FrequencyTolerance,
         origin_timestamp: NtpTimestamp,
         destination_timestamp: NtpTimestamp,
+        options: &PeerOptions,
     ) -> Self {
         // for reference
         //
@@ -83,6 +91,9 @@ impl FilterTuple {
         // delay is clamped to ensure it is always positive
         let delay = Ord::max(system_precision, delta1 - delta2);
 
+        // correct for known asymmetry of the path and other static errors
+        let offset = offset + options.offset_correction(delay);
+
         let dispersion = packet_precision + system_precision + (delta1 * frequency_tolerance);
 
         Self {
@@ -293,6 +304,7 @@ pub fn fuzz_tuple_from_packet_default(
         FrequencyTolerance::ppm(15),
         packet.origin_timestamp,
         NtpTimestamp::from_fixed_int(client.wrapping_add(client_interval as u64)),
+        &PeerOptions::default(),
     );
 
     assert!(result.delay >= NtpDuration::from_fixed_int(0));
@@ -482,6 +494,7 @@ mod test {
             FrequencyTolerance::ppm(15),
             packet.origin_timestamp,
             NtpTimestamp::from_fixed_int(3),
+            &PeerOptions::default(),
         );
         assert_eq!(result.offset, NtpDuration::from_fixed_int(0));
         assert_eq!(result.delay, NtpDuration::from_fixed_int(2));
@@ -499,6 +512,7 @@ mod test {
             FrequencyTolerance::ppm(15),
             packet.origin_timestamp,
             NtpTimestamp::from_fixed_int(3),
+            &PeerOptions::default(),
         );
         assert_eq!(result.offset, NtpDuration::from_fixed_int(1));
         assert_eq!(result.delay, NtpDuration::from_fixed_int(2));
@@ -516,12 +530,59 @@ mod test {
             FrequencyTolerance::ppm(15),
             packet.origin_timestamp,
             NtpTimestamp::from_fixed_int(3),
+            &PeerOptions::default(),
         );
         assert_eq!(result.offset, NtpDuration::from_fixed_int(1));
         assert_eq!(result.delay, NtpDuration::from_fixed_int(1));
         assert!(result.dispersion >= NtpDuration::from_fixed_int(0));
     }
 
+    #[test]
+    fn test_tuple_offset_correction() {
+        let instant = NtpInstant::now();
+
+        // a round trip of 2 seconds, with the server exactly in the middle
+        let mut packet = NtpHeader::new();
+        packet.origin_timestamp = NtpTimestamp::from_fixed_int(0);
+        packet.receive_timestamp = NtpTimestamp::from_fixed_int(1 << 32);
+        packet.transmit_timestamp = NtpTimestamp::from_fixed_int(1 << 32);
+
+        let tuple = |options: PeerOptions| {
+            FilterTuple::from_packet_default(
+                &packet,
+                NtpDuration::from_exponent(-32),
+                instant,
+                FrequencyTolerance::ppm(15),
+                packet.origin_timestamp,
+                NtpTimestamp::from_fixed_int(2 << 32),
+                &options,
+            )
+        };
+
+        let result = tuple(PeerOptions::default());
+        assert_eq!(result.offset, NtpDuration::ZERO);
+
+        // three quarters of the delay is spent on the way out, so the server is behind
+        let result = tuple(PeerOptions {
+            asymmetry: Some(0.75),
+            ..PeerOptions::default()
+        });
+        assert!((result.offset.to_seconds() + 0.5).abs() < 1e-6);
+        assert_eq!(result.delay, NtpDuration::from_seconds(2.0));
+
+        let options = PeerOptions {
+            asymmetry: Some(0.75),
+            offset: NtpDuration::from_seconds(0.1),
+            ..PeerOptions::default()
+        };
+        let result = tuple(options);
+        assert!((result.offset.to_seconds() + 0.4).abs() < 1e-6);
+        assert_eq!(
+            result.offset - options.offset_correction(result.delay),
+            NtpDuration::ZERO
+        );
+    }
+
     #[test]
     fn clock_filter_dispersion_update() {
         let base = NtpInstant::now();
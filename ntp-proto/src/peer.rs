@@ -1,8 +1,11 @@
+use std::collections::VecDeque;
+
 use crate::{
+    config::{PeerOptions, SystemConfig},
     filter::{FilterTuple, LastMeasurements},
     packet::{NtpAssociationMode, NtpLeapIndicator},
     time_types::{FrequencyTolerance, NtpInstant},
-    NtpDuration, NtpHeader, NtpTimestamp, PollInterval, ReferenceId,
+    NtpDuration, NtpHeader, NtpTimestamp, PollInterval, PollIntervalLimits, ReferenceId,
 };
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
@@ -10,6 +13,13 @@ use tracing::{debug, info, instrument, trace, warn};
 
 const MAX_STRATUM: u8 = 16;
 const POLL_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
+/// Number of requests sent on every poll when the peer is configured to burst
+const BURST_REQUESTS: u8 = 4;
+/// Number of recent round-trip delays used to determine the minimum delay of a peer
+const DELAY_HISTORY: usize = 8;
+/// Number of poll intervals without a clock update after which the system goes into holdover,
+/// like a peer becomes unreachable when its 8-bit reach register is empty
+const HOLDOVER_POLLS: u32 = 8;
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct PeerStatistics {
@@ -31,10 +41,22 @@ pub struct Peer {
     remote_min_poll_interval: PollInterval,
 
     // Last packet information
-    // We expect the next packet we receive to have this origin timestamp
-    // This is used as validation that the packet we get is the correct response to the one we sent
-    // (guards against e.g. replay and packet reordering)
-    next_expected_origin: Option<(NtpTimestamp, NtpInstant)>,
+    // We expect the packets we receive to have one of these origin timestamps
+    // This is used as validation that the packet we get is the correct response to one we sent
+    // (guards against e.g. replay and packet reordering). Outside of bursts, there is at most one.
+    expected_origins: Vec<(NtpTimestamp, NtpInstant)>,
+
+    // Options configured for this peer
+    options: PeerOptions,
+    // Number of requests sent in the current poll
+    burst_requests_sent: u8,
+    // Lowest delay response received in the current burst, not yet given to the filter
+    burst_candidate: Option<(FilterTuple, NtpHeader)>,
+
+    // Round-trip delays of the most recent samples, used for the delay ratio check
+    recent_delays: VecDeque<NtpDuration>,
+    // Number of received packets that were ignored, per reason
+    ignored: IgnoreStatistics,
 
     statistics: PeerStatistics,
     last_measurements: LastMeasurements,
@@ -92,6 +114,21 @@ impl Reach {
     }
 }
 
+/// Whether the system clock is steered by our peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncState {
+    /// The clock was never synchronized, or the holdover expired
+    Unsynchronized,
+    Synchronized,
+    /// The clock was synchronized, but none of the peers can be used anymore. The clock keeps
+    /// running on its last frequency, and its root dispersion grows until the holdover expires.
+    Holdover,
+}
+
+fn unsynchronized() -> SyncState {
+    SyncState::Unsynchronized
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct SystemSnapshot {
     /// Desired poll interval
@@ -112,6 +149,12 @@ pub struct SystemSnapshot {
     pub accumulated_steps: NtpDuration,
     /// Crossing this amount of stepping will cause a Panic
     pub accumulated_steps_threshold: Option<NtpDuration>,
+    /// Time of the last clock update, zero when the clock was never updated
+    #[serde(skip)]
+    pub reference_timestamp: NtpTimestamp,
+    /// Synchronized, in holdover or unsynchronized. Absent when talking to an older daemon
+    #[serde(default = "unsynchronized")]
+    pub sync_state: SyncState,
 }
 
 impl Default for SystemSnapshot {
@@ -126,10 +169,71 @@ impl Default for SystemSnapshot {
             leap_indicator: NtpLeapIndicator::Unknown,
             accumulated_steps: NtpDuration::ZERO,
             accumulated_steps_threshold: None,
+            reference_timestamp: NtpTimestamp::default(),
+            sync_state: SyncState::Unsynchronized,
         }
     }
 }
 
+impl SystemSnapshot {
+    /// Whether the clock is synchronized, which includes the holdover after the last update
+    pub fn is_synchronized(&self) -> bool {
+        self.sync_state != SyncState::Unsynchronized
+    }
+
+    /// The root dispersion at `now`. Without clock updates our time gets worse, so the
+    /// dispersion grows by the frequency tolerance since the last update (RFC 5905, section 11.2).
+    pub fn root_dispersion_at(
+        &self,
+        now: NtpTimestamp,
+        frequency_tolerance: FrequencyTolerance,
+    ) -> NtpDuration {
+        if self.reference_timestamp == NtpTimestamp::default() {
+            return self.root_dispersion;
+        }
+
+        let age = std::cmp::max(now - self.reference_timestamp, NtpDuration::ZERO);
+        self.root_dispersion + age * frequency_tolerance
+    }
+
+    /// Go into holdover because the peers no longer produce a time, returns whether the state
+    /// changed
+    pub fn enter_holdover(&mut self) -> bool {
+        if self.sync_state == SyncState::Synchronized {
+            self.sync_state = SyncState::Holdover;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Go into holdover when the clock was not updated for a while, and become unsynchronized
+    /// when the last update is more than `max_holdover` ago. Returns whether the state changed.
+    pub fn age_sync_state(&mut self, now: NtpTimestamp, max_holdover: NtpDuration) -> bool {
+        let age = now - self.reference_timestamp;
+        let state = match self.sync_state {
+            SyncState::Unsynchronized => SyncState::Unsynchronized,
+            _ if age > max_holdover => SyncState::Unsynchronized,
+            SyncState::Synchronized if age > self.poll_interval.as_duration() * HOLDOVER_POLLS => {
+                SyncState::Holdover
+            }
+            state => state,
+        };
+
+        if state == self.sync_state {
+            return false;
+        }
+
+        self.sync_state = state;
+        if state == SyncState::Unsynchronized {
+            self.leap_indicator = NtpLeapIndicator::Unknown;
+            self.stratum = MAX_STRATUM;
+        }
+
+        true
+    }
+}
+
 #[derive(Debug)]
 pub enum IgnoreReason {
     /// The association mode is not one that this peer supports
@@ -146,6 +250,45 @@ pub enum IgnoreReason {
     KissDemobilize,
     /// The best packet is older than the peer's current time
     TooOld,
+    /// The round-trip delay exceeds the configured maximum delay
+    ExcessiveDelay,
+    /// The round-trip delay is too large compared to the minimum delay of recent samples
+    ExcessiveDelayRatio,
+    /// The stratum of the server is higher than the configured maximum stratum
+    StratumLimit,
+}
+
+/// Number of packets received from a peer that were ignored, per reason
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IgnoreStatistics {
+    pub invalid_mode: u64,
+    pub invalid_version: u64,
+    pub invalid_stratum: u64,
+    pub invalid_packet_time: u64,
+    pub kiss_ignore: u64,
+    pub kiss_demobilize: u64,
+    pub too_old: u64,
+    pub excessive_delay: u64,
+    pub excessive_delay_ratio: u64,
+    pub stratum_limit: u64,
+}
+
+impl IgnoreStatistics {
+    fn count(&mut self, reason: &IgnoreReason) {
+        let counter = match reason {
+            IgnoreReason::InvalidMode => &mut self.invalid_mode,
+            IgnoreReason::InvalidVersion => &mut self.invalid_version,
+            IgnoreReason::InvalidStratum => &mut self.invalid_stratum,
+            IgnoreReason::InvalidPacketTime => &mut self.invalid_packet_time,
+            IgnoreReason::KissIgnore => &mut self.kiss_ignore,
+            IgnoreReason::KissDemobilize => &mut self.kiss_demobilize,
+            IgnoreReason::TooOld => &mut self.too_old,
+            IgnoreReason::ExcessiveDelay => &mut self.excessive_delay,
+            IgnoreReason::ExcessiveDelayRatio => &mut self.excessive_delay_ratio,
+            IgnoreReason::StratumLimit => &mut self.stratum_limit,
+        };
+        *counter = counter.saturating_add(1);
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -165,6 +308,19 @@ pub struct PeerSnapshot {
     pub leap_indicator: NtpLeapIndicator,
     pub root_delay: NtpDuration,
     pub root_dispersion: NtpDuration,
+
+    /// Favour this peer as the system peer
+    pub prefer: bool,
+    /// Never use this peer for synchronization
+    pub noselect: bool,
+    /// Never discard this peer as a falseticker
+    pub trust: bool,
+
+    /// Number of packets from this peer that were ignored
+    pub ignored: IgnoreStatistics,
+
+    /// The offset as measured, before the static offset and asymmetry correction
+    pub raw_offset: NtpDuration,
 }
 
 impl PeerSnapshot {
@@ -174,6 +330,7 @@ impl PeerSnapshot {
         frequency_tolerance: FrequencyTolerance,
         distance_threshold: NtpDuration,
         system_poll: PollInterval,
+        max_stratum: u8,
     ) -> Result<(), AcceptSynchronizationError> {
         use AcceptSynchronizationError::*;
 
@@ -190,6 +347,15 @@ impl PeerSnapshot {
             return Err(Stratum);
         }
 
+        // We don't want to synchronize to peers with a higher stratum than configured
+        if self.stratum > max_stratum {
+            debug!(
+                stratum = debug(self.stratum),
+                max_stratum, "Peer rejected due to the stratum limit"
+            );
+            return Err(StratumLimit);
+        }
+
         //  A distance error occurs if the root distance exceeds the
         //  distance threshold plus an increment equal to one poll interval.
         let distance = self.root_distance(local_clock_time, frequency_tolerance);
@@ -256,6 +422,12 @@ impl PeerSnapshot {
             root_delay: peer.last_packet.root_delay,
             root_dispersion: peer.last_packet.root_dispersion,
             poll_interval: peer.last_poll_interval,
+            prefer: peer.options.prefer,
+            noselect: peer.options.noselect,
+            trust: peer.options.trust,
+            ignored: peer.ignored,
+            raw_offset: peer.statistics.offset
+                - peer.options.offset_correction(peer.statistics.delay),
         }
     }
 }
@@ -267,6 +439,7 @@ pub enum AcceptSynchronizationError {
     Loop,
     Distance,
     Stratum,
+    StratumLimit,
 }
 
 #[derive(Debug)]
@@ -277,16 +450,29 @@ pub enum Update {
 
 impl Peer {
     #[instrument]
-    pub fn new(our_id: ReferenceId, peer_id: ReferenceId, local_clock_time: NtpInstant) -> Self {
+    pub fn new(
+        our_id: ReferenceId,
+        peer_id: ReferenceId,
+        local_clock_time: NtpInstant,
+        options: PeerOptions,
+        system_config: &SystemConfig,
+    ) -> Self {
         // we initialize with the current time so that we're in the correct epoch.
         let time = local_clock_time;
+        let poll_limits = options.poll_limits(system_config.poll_limits);
 
         Self {
-            last_poll_interval: PollInterval::MIN,
-            backoff_interval: PollInterval::MIN,
-            remote_min_poll_interval: PollInterval::MIN,
+            last_poll_interval: poll_limits.min,
+            backoff_interval: poll_limits.min,
+            remote_min_poll_interval: poll_limits.min,
+
+            expected_origins: Vec::new(),
+            options,
+            burst_requests_sent: 0,
+            burst_candidate: None,
 
-            next_expected_origin: None,
+            recent_delays: VecDeque::with_capacity(DELAY_HISTORY),
+            ignored: IgnoreStatistics::default(),
 
             statistics: Default::default(),
             last_measurements: LastMeasurements::new(time),
@@ -298,57 +484,142 @@ impl Peer {
         }
     }
 
-    pub fn current_poll_interval(&self, system: SystemSnapshot) -> PollInterval {
+    /// Range in which the poll interval for this peer must stay. The limits of the peer itself
+    /// take precedence over those of the system, which can change while the peer runs.
+    pub fn poll_limits(&self, system_config: &SystemConfig) -> PollIntervalLimits {
+        self.options.poll_limits(system_config.poll_limits)
+    }
+
+    /// The poll interval for this peer, which follows the system poll interval unless
+    /// backoff or the remote require us to poll less often, but never leaves the peer's limits
+    pub fn current_poll_interval(
+        &self,
+        system: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) -> PollInterval {
+        let poll_limits = self.poll_limits(system_config);
         system
             .poll_interval
             .max(self.backoff_interval)
             .max(self.remote_min_poll_interval)
+            .clamp(poll_limits.min, poll_limits.max)
     }
 
-    pub fn generate_poll_message(&mut self, system: SystemSnapshot) -> NtpHeader {
+    pub fn generate_poll_message(
+        &mut self,
+        system: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) -> NtpHeader {
         self.reach.poll();
 
+        let poll_interval = self.current_poll_interval(system, system_config);
+
+        // Ensure we don't spam the remote with polls if it is not reachable
+        self.backoff_interval = poll_interval.inc(self.poll_limits(system_config));
+
+        // Responses to the requests of earlier polls are no longer accepted
+        self.expected_origins.clear();
+        self.burst_requests_sent = 0;
+
+        self.generate_request(poll_interval)
+    }
+
+    pub fn reach(&self) -> Reach {
+        self.reach
+    }
+
+    /// Number of requests that still need to be sent as part of the burst of the current poll
+    pub fn burst_requests_remaining(&self) -> u8 {
+        if self.options.burst {
+            BURST_REQUESTS.saturating_sub(self.burst_requests_sent)
+        } else {
+            0
+        }
+    }
+
+    /// Generate the next request of the burst of the current poll. Unlike a poll, this does not
+    /// count towards reachability.
+    pub fn generate_burst_message(
+        &mut self,
+        system: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) -> NtpHeader {
+        let poll_interval = self.current_poll_interval(system, system_config);
+        self.generate_request(poll_interval)
+    }
+
+    fn generate_request(&mut self, poll_interval: PollInterval) -> NtpHeader {
         let mut packet = NtpHeader::new();
-        let poll_interval = self.current_poll_interval(system);
         packet.poll = poll_interval.as_log();
         packet.mode = NtpAssociationMode::Client;
 
-        // Ensure we don't spam the remote with polls if it is not reachable
-        self.backoff_interval = poll_interval.inc();
-
         // In order to increase the entropy of the transmit timestamp
         // it is just a randomly generated timestamp.
         // We then expect to get it back identically from the remote
         // in the origin field.
         let transmit_timestamp = thread_rng().gen();
         let validity = NtpInstant::now() + POLL_WINDOW;
-        self.next_expected_origin = Some((transmit_timestamp, validity));
+        self.expected_origins.push((transmit_timestamp, validity));
+        self.burst_requests_sent = self.burst_requests_sent.saturating_add(1);
         packet.transmit_timestamp = transmit_timestamp;
 
         packet
     }
 
-    #[instrument(skip(self, system, frequency_tolerance), fields(peer = debug(self.peer_id)))]
+    /// Give the best response of an incomplete burst to the filter. This should be called
+    /// before starting a new poll, so that a burst where not all responses arrived is still used.
+    pub fn finish_burst(
+        &mut self,
+        system: SystemSnapshot,
+        system_config: &SystemConfig,
+    ) -> Option<Update> {
+        let (filter_input, message) = self.burst_candidate.take()?;
+        let frequency_tolerance = system_config.frequency_tolerance;
+        Some(self.step_filter(system, filter_input, message, frequency_tolerance))
+    }
+
+    #[instrument(skip(self, system, system_config), fields(peer = debug(self.peer_id)))]
     pub fn handle_incoming(
         &mut self,
         system: SystemSnapshot,
         message: NtpHeader,
         local_clock_time: NtpInstant,
-        frequency_tolerance: FrequencyTolerance,
+        system_config: &SystemConfig,
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
     ) -> Result<Update, IgnoreReason> {
-        let next_expected_origin = match self.next_expected_origin {
-            Some((next_expected_origin, validity)) if validity >= NtpInstant::now() => {
-                next_expected_origin
-            }
-            _ => {
-                debug!("Received old/unexpected packet from peer");
-                return Err(IgnoreReason::InvalidPacketTime);
-            }
-        };
+        let result = self.validate_and_process(
+            system,
+            message,
+            local_clock_time,
+            system_config,
+            send_time,
+            recv_time,
+        );
+
+        if let Err(reason) = &result {
+            self.ignored.count(reason);
+        }
+
+        result
+    }
+
+    fn validate_and_process(
+        &mut self,
+        system: SystemSnapshot,
+        message: NtpHeader,
+        local_clock_time: NtpInstant,
+        system_config: &SystemConfig,
+        send_time: NtpTimestamp,
+        recv_time: NtpTimestamp,
+    ) -> Result<Update, IgnoreReason> {
+        let now = NtpInstant::now();
+        let expected = self
+            .expected_origins
+            .iter()
+            .position(|&(origin, validity)| origin == message.origin_timestamp && validity >= now);
 
-        if message.origin_timestamp != next_expected_origin {
+        if expected.is_none() {
             // Packets should be a response to a previous request from us,
             // if not just ignore. Note that this might also happen when
             // we reset between sending the request and receiving the response.
@@ -359,9 +630,19 @@ impl Peer {
             Err(IgnoreReason::InvalidPacketTime)
         } else if message.is_kiss_rate() {
             // KISS packets may not have correct timestamps at all, handle them anyway
-            self.remote_min_poll_interval =
-                Ord::max(self.remote_min_poll_interval.inc(), self.last_poll_interval);
+            self.remote_min_poll_interval = Ord::max(
+                self.remote_min_poll_interval
+                    .inc(self.poll_limits(system_config)),
+                self.last_poll_interval,
+            );
             warn!(?self.remote_min_poll_interval, "Peer requested rate limit");
+
+            // Sending the rest of a burst would only make matters worse
+            if let Some(index) = expected {
+                self.expected_origins.swap_remove(index);
+            }
+            self.burst_requests_sent = BURST_REQUESTS;
+
             Err(IgnoreReason::KissIgnore)
         } else if message.is_kiss_rstr() || message.is_kiss_deny() {
             warn!("Peer denied service");
@@ -383,14 +664,19 @@ impl Peer {
             warn!("Received packet with invalid mode");
             Err(IgnoreReason::InvalidMode)
         } else {
-            Ok(self.process_message(
+            // we received this packet, and don't want to accept future ones with this origin
+            if let Some(index) = expected {
+                self.expected_origins.swap_remove(index);
+            }
+
+            self.process_message(
                 system,
                 message,
                 local_clock_time,
-                frequency_tolerance,
+                system_config,
                 send_time,
                 recv_time,
-            ))
+            )
         }
     }
 
@@ -399,19 +685,17 @@ impl Peer {
         system: SystemSnapshot,
         message: NtpHeader,
         local_clock_time: NtpInstant,
-        frequency_tolerance: FrequencyTolerance,
+        system_config: &SystemConfig,
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
-    ) -> Update {
+    ) -> Result<Update, IgnoreReason> {
         trace!("Packet accepted for processing");
         // For reachability, mark that we have had a response
         self.reach.received_packet();
 
         // Got a response, so no need for unreachability backoff
-        self.backoff_interval = PollInterval::MIN;
-
-        // we received this packet, and don't want to accept future ones with this next_expected_origin
-        self.next_expected_origin = None;
+        self.backoff_interval = self.poll_limits(system_config).min;
+        let frequency_tolerance = system_config.frequency_tolerance;
 
         let filter_input = FilterTuple::from_packet_default(
             &message,
@@ -420,8 +704,79 @@ impl Peer {
             frequency_tolerance,
             send_time,
             recv_time,
+            &self.options,
         );
 
+        // The peer did respond, but the sample itself is not usable
+        let max_stratum = system_config.max_stratum;
+        if message.stratum > max_stratum && message.stratum < MAX_STRATUM {
+            debug!(
+                stratum = message.stratum,
+                max_stratum, "Received message from server above the stratum limit"
+            );
+            return Err(IgnoreReason::StratumLimit);
+        }
+        self.check_delay(filter_input.delay())?;
+
+        if !self.options.burst {
+            return Ok(self.step_filter(system, filter_input, message, frequency_tolerance));
+        }
+
+        // Within a burst, only the response with the lowest delay is given to the filter
+        let best = match self.burst_candidate.take() {
+            Some((candidate, packet)) if candidate.delay() <= filter_input.delay() => {
+                (candidate, packet)
+            }
+            _ => (filter_input, message),
+        };
+
+        if self.expected_origins.is_empty() && self.burst_requests_remaining() == 0 {
+            let (filter_input, message) = best;
+            Ok(self.step_filter(system, filter_input, message, frequency_tolerance))
+        } else {
+            self.burst_candidate = Some(best);
+            Ok(Update::BareUpdate(PeerSnapshot::from_peer(self)))
+        }
+    }
+
+    /// Check the round-trip delay of a sample against the configured limits
+    fn check_delay(&mut self, delay: NtpDuration) -> Result<(), IgnoreReason> {
+        // The minimum is taken before adding this sample, so a persistent change of the path
+        // is accepted again once it fills the history
+        let min_delay = self.recent_delays.iter().min().copied();
+        if self.recent_delays.len() == DELAY_HISTORY {
+            self.recent_delays.pop_front();
+        }
+        self.recent_delays.push_back(delay);
+
+        if let Some(max_delay) = self.options.max_delay {
+            if delay > max_delay {
+                debug!(?delay, ?max_delay, "Sample rejected due to excessive delay");
+                return Err(IgnoreReason::ExcessiveDelay);
+            }
+        }
+
+        if let (Some(ratio), Some(min_delay)) = (self.options.max_delay_ratio, min_delay) {
+            if delay.to_seconds() > ratio * min_delay.to_seconds() {
+                debug!(
+                    ?delay,
+                    ?min_delay,
+                    "Sample rejected due to excessive delay ratio"
+                );
+                return Err(IgnoreReason::ExcessiveDelayRatio);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step_filter(
+        &mut self,
+        system: SystemSnapshot,
+        filter_input: FilterTuple,
+        message: NtpHeader,
+        frequency_tolerance: FrequencyTolerance,
+    ) -> Update {
         self.last_packet = message;
 
         let updated = self.last_measurements.step(
@@ -473,7 +828,9 @@ impl Peer {
         self.last_packet = Default::default();
 
         // make sure in-flight messages are ignored
-        self.next_expected_origin = None;
+        self.expected_origins.clear();
+        self.burst_requests_sent = 0;
+        self.burst_candidate = None;
 
         info!(our_id = ?self.our_id, peer_id = ?self.peer_id, "Peer reset");
     }
@@ -485,7 +842,13 @@ impl Peer {
             backoff_interval: PollInterval::default(),
             remote_min_poll_interval: PollInterval::default(),
 
-            next_expected_origin: None,
+            expected_origins: Vec::new(),
+            options: PeerOptions::default(),
+            burst_requests_sent: 0,
+            burst_candidate: None,
+
+            recent_delays: VecDeque::with_capacity(DELAY_HISTORY),
+            ignored: IgnoreStatistics::default(),
 
             statistics: Default::default(),
             last_measurements: LastMeasurements::new(instant),
@@ -650,13 +1013,14 @@ mod test {
         let ft = FrequencyTolerance::ppm(15);
         let dt = NtpDuration::ONE;
         let system_poll = PollInterval::MIN;
+        let mut max_stratum = 15;
 
         let mut peer = Peer::test_peer(local_clock_time);
 
         macro_rules! accept {
             () => {{
                 let snapshot = PeerSnapshot::from_peer(&peer);
-                snapshot.accept_synchronization(local_clock_time, ft, dt, system_poll)
+                snapshot.accept_synchronization(local_clock_time, ft, dt, system_poll, max_stratum)
             }};
         }
 
@@ -671,6 +1035,13 @@ mod test {
 
         assert_eq!(accept!(), Ok(()));
 
+        peer.last_packet.stratum = 3;
+        max_stratum = 2;
+        assert_eq!(accept!(), Err(StratumLimit));
+        max_stratum = 3;
+        assert_eq!(accept!(), Ok(()));
+        peer.last_packet.stratum = 0;
+
         peer.last_packet.leap = NtpLeapIndicator::Unknown;
         assert_eq!(accept!(), Err(Stratum));
 
@@ -690,25 +1061,40 @@ mod test {
         let mut peer = Peer::test_peer(base);
         let mut system = SystemSnapshot::default();
 
-        assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
-        assert!(peer.current_poll_interval(system) >= system.poll_interval);
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default())
+                >= peer.remote_min_poll_interval
+        );
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default()) >= system.poll_interval
+        );
 
         system.poll_interval = PollInterval::MAX;
 
-        assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
-        assert!(peer.current_poll_interval(system) >= system.poll_interval);
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default())
+                >= peer.remote_min_poll_interval
+        );
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default()) >= system.poll_interval
+        );
 
         system.poll_interval = PollInterval::MIN;
         peer.remote_min_poll_interval = PollInterval::MAX;
 
-        assert!(peer.current_poll_interval(system) >= peer.remote_min_poll_interval);
-        assert!(peer.current_poll_interval(system) >= system.poll_interval);
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default())
+                >= peer.remote_min_poll_interval
+        );
+        assert!(
+            peer.current_poll_interval(system, &SystemConfig::default()) >= system.poll_interval
+        );
 
         peer.remote_min_poll_interval = PollInterval::MIN;
 
-        let prev = peer.current_poll_interval(system);
-        let packet = peer.generate_poll_message(system);
-        assert!(peer.current_poll_interval(system) > prev);
+        let prev = peer.current_poll_interval(system, &SystemConfig::default());
+        let packet = peer.generate_poll_message(system, &SystemConfig::default());
+        assert!(peer.current_poll_interval(system, &SystemConfig::default()) > prev);
         let mut response = NtpHeader::new();
         response.mode = NtpAssociationMode::Server;
         response.stratum = 1;
@@ -718,16 +1104,19 @@ mod test {
                 system,
                 response,
                 base,
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::default(),
                 NtpTimestamp::default()
             )
             .is_ok());
-        assert_eq!(peer.current_poll_interval(system), prev);
+        assert_eq!(
+            peer.current_poll_interval(system, &SystemConfig::default()),
+            prev
+        );
 
-        let prev = peer.current_poll_interval(system);
-        let packet = peer.generate_poll_message(system);
-        assert!(peer.current_poll_interval(system) > prev);
+        let prev = peer.current_poll_interval(system, &SystemConfig::default());
+        let packet = peer.generate_poll_message(system, &SystemConfig::default());
+        assert!(peer.current_poll_interval(system, &SystemConfig::default()) > prev);
         let mut response = NtpHeader::new();
         response.mode = NtpAssociationMode::Server;
         response.stratum = 0;
@@ -738,22 +1127,87 @@ mod test {
                 system,
                 response,
                 base,
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::default(),
                 NtpTimestamp::default()
             )
             .is_err());
-        assert!(peer.current_poll_interval(system) > prev);
+        assert!(peer.current_poll_interval(system, &SystemConfig::default()) > prev);
         assert!(peer.remote_min_poll_interval > prev);
     }
 
+    #[test]
+    fn test_poll_interval_limits() {
+        let base = NtpInstant::now();
+        let limits = PollIntervalLimits {
+            min: PollInterval::from_log(0).unwrap(),
+            max: PollInterval::from_log(1).unwrap(),
+        };
+        let config = SystemConfig {
+            poll_limits: limits,
+            ..SystemConfig::default()
+        };
+        let mut peer = Peer::new(
+            ReferenceId::from_int(0),
+            ReferenceId::from_int(0),
+            base,
+            PeerOptions::default(),
+            &config,
+        );
+        let mut system = SystemSnapshot::default();
+
+        // the system poll interval is above the peer's maximum
+        assert_eq!(peer.current_poll_interval(system, &config), limits.max);
+
+        // backoff stays within the limits
+        for _ in 0..10 {
+            peer.generate_poll_message(system, &config);
+            assert_eq!(peer.current_poll_interval(system, &config), limits.max);
+        }
+
+        let limits = PollIntervalLimits {
+            min: PollInterval::from_log(6).unwrap(),
+            max: PollInterval::from_log(10).unwrap(),
+        };
+        let config = SystemConfig {
+            poll_limits: limits,
+            ..SystemConfig::default()
+        };
+        let mut peer = Peer::new(
+            ReferenceId::from_int(0),
+            ReferenceId::from_int(0),
+            base,
+            PeerOptions::default(),
+            &config,
+        );
+
+        // the system poll interval is below the peer's minimum
+        system.poll_interval = PollInterval::MIN;
+        assert_eq!(peer.current_poll_interval(system, &config), limits.min);
+        let packet = peer.generate_poll_message(system, &config);
+        assert_eq!(packet.poll, 6);
+
+        // a running peer follows changes of the system limits
+        let limits = PollIntervalLimits {
+            min: PollInterval::from_log(8).unwrap(),
+            max: PollInterval::from_log(10).unwrap(),
+        };
+        let config = SystemConfig {
+            poll_limits: limits,
+            ..SystemConfig::default()
+        };
+        assert_eq!(peer.current_poll_interval(system, &config), limits.min);
+        let packet = peer.generate_poll_message(system, &config);
+        assert_eq!(packet.poll, 8);
+    }
+
     #[test]
     fn test_handle_incoming() {
         let base = NtpInstant::now();
         let mut peer = Peer::test_peer(base);
 
         let system = SystemSnapshot::default();
-        let outgoing = peer.generate_poll_message(system);
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
         let mut packet = NtpHeader::new();
         let system = SystemSnapshot::default();
         packet.stratum = 1;
@@ -767,7 +1221,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(400)
             )
@@ -778,20 +1232,145 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(500)
             )
             .is_err());
     }
 
+    #[test]
+    fn test_burst() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer(base);
+        peer.options.burst = true;
+
+        let system = SystemSnapshot::default();
+        let mut outgoing = vec![peer.generate_poll_message(system, &SystemConfig::default())];
+        while peer.burst_requests_remaining() > 0 {
+            outgoing.push(peer.generate_burst_message(system, &SystemConfig::default()));
+        }
+        assert_eq!(outgoing.len(), BURST_REQUESTS as usize);
+
+        let mut packets = vec![];
+        for (i, request) in outgoing.iter().enumerate() {
+            let mut packet = NtpHeader::new();
+            packet.stratum = 1;
+            packet.mode = NtpAssociationMode::Server;
+            packet.origin_timestamp = request.transmit_timestamp;
+            packet.receive_timestamp = NtpTimestamp::from_fixed_int(100);
+            packet.transmit_timestamp = NtpTimestamp::from_fixed_int(200);
+            // every next response has a millisecond more delay, well above the precision
+            let recv = NtpTimestamp::from_fixed_int(400 + (i as u64) * (1 << 22));
+            packets.push((packet, recv));
+        }
+
+        // Responses are accepted in any order, only the last one completes the burst
+        packets.reverse();
+        let (last_packet, last_recv) = packets.pop().unwrap();
+        for (packet, recv) in packets {
+            let update = peer
+                .handle_incoming(
+                    system,
+                    packet,
+                    base + Duration::from_secs(1),
+                    &SystemConfig::default(),
+                    NtpTimestamp::from_fixed_int(0),
+                    recv,
+                )
+                .unwrap();
+            assert!(matches!(update, Update::BareUpdate(_)));
+        }
+        let update = peer
+            .handle_incoming(
+                system,
+                last_packet,
+                base + Duration::from_secs(1),
+                &SystemConfig::default(),
+                NtpTimestamp::from_fixed_int(0),
+                last_recv,
+            )
+            .unwrap();
+        assert!(matches!(update, Update::NewMeasurement(_)));
+        // the first request had the lowest delay
+        assert_eq!(peer.last_packet, last_packet);
+
+        // A rate limit cancels the rest of the burst
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
+        assert_eq!(peer.burst_requests_remaining(), BURST_REQUESTS - 1);
+        let mut packet = NtpHeader::new();
+        packet.reference_id = ReferenceId::KISS_RATE;
+        packet.origin_timestamp = outgoing.transmit_timestamp;
+        packet.mode = NtpAssociationMode::Server;
+        assert!(matches!(
+            peer.handle_incoming(
+                system,
+                packet,
+                base + Duration::from_secs(1),
+                &SystemConfig::default(),
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(400)
+            ),
+            Err(IgnoreReason::KissIgnore)
+        ));
+        assert_eq!(peer.burst_requests_remaining(), 0);
+    }
+
+    #[test]
+    fn test_delay_limits() {
+        let base = NtpInstant::now();
+        let mut peer = Peer::test_peer(base);
+        let system = SystemSnapshot::default();
+
+        // one unit is about a millisecond, well above the precision
+        let respond = |peer: &mut Peer, delay: u64| {
+            let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
+            let mut packet = NtpHeader::new();
+            packet.stratum = 1;
+            packet.mode = NtpAssociationMode::Server;
+            packet.origin_timestamp = outgoing.transmit_timestamp;
+            packet.receive_timestamp = NtpTimestamp::from_fixed_int(100);
+            packet.transmit_timestamp = NtpTimestamp::from_fixed_int(200);
+            peer.handle_incoming(
+                system,
+                packet,
+                base + Duration::from_secs(1),
+                &SystemConfig::default(),
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(100 + delay * (1 << 22)),
+            )
+        };
+
+        peer.options.max_delay = Some(NtpDuration::from_seconds(0.0025));
+        assert!(respond(&mut peer, 1).is_ok());
+        assert!(matches!(
+            respond(&mut peer, 3),
+            Err(IgnoreReason::ExcessiveDelay)
+        ));
+        assert_eq!(peer.ignored.excessive_delay, 1);
+        // the peer did respond, so it stays reachable
+        assert!(peer.reach.is_reachable());
+
+        peer.options.max_delay = None;
+        peer.options.max_delay_ratio = Some(2.0);
+        assert!(matches!(
+            respond(&mut peer, 3),
+            Err(IgnoreReason::ExcessiveDelayRatio)
+        ));
+        assert!(respond(&mut peer, 2).is_ok());
+        assert_eq!(
+            PeerSnapshot::from_peer(&peer).ignored.excessive_delay_ratio,
+            1
+        );
+    }
+
     #[test]
     fn test_stratum_checks() {
         let base = NtpInstant::now();
         let mut peer = Peer::test_peer(base);
 
         let system = SystemSnapshot::default();
-        let outgoing = peer.generate_poll_message(system);
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
         let mut packet = NtpHeader::new();
         let system = SystemSnapshot::default();
         packet.stratum = MAX_STRATUM + 1;
@@ -804,7 +1383,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(500)
             )
@@ -816,11 +1395,32 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(500)
             )
             .is_err());
+
+        // the stratum limit of the system applies as it is when the response arrives
+        for max_stratum in [2, 3] {
+            let config = SystemConfig {
+                max_stratum,
+                ..SystemConfig::default()
+            };
+            let outgoing = peer.generate_poll_message(system, &config);
+            packet.stratum = 3;
+            packet.origin_timestamp = outgoing.transmit_timestamp;
+            let result = peer.handle_incoming(
+                system,
+                packet,
+                base + Duration::from_secs(1),
+                &config,
+                NtpTimestamp::from_fixed_int(0),
+                NtpTimestamp::from_fixed_int(500),
+            );
+            let limited = matches!(result, Err(IgnoreReason::StratumLimit));
+            assert_eq!(limited, max_stratum < 3);
+        }
     }
 
     #[test]
@@ -837,7 +1437,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             ),
@@ -846,7 +1446,7 @@ mod test {
 
         let mut packet = NtpHeader::new();
         let system = SystemSnapshot::default();
-        let outgoing = peer.generate_poll_message(system);
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
         packet.reference_id = ReferenceId::KISS_RSTR;
         packet.origin_timestamp = outgoing.transmit_timestamp;
         packet.mode = NtpAssociationMode::Server;
@@ -855,7 +1455,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             ),
@@ -871,7 +1471,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             ),
@@ -880,7 +1480,7 @@ mod test {
 
         let mut packet = NtpHeader::new();
         let system = SystemSnapshot::default();
-        let outgoing = peer.generate_poll_message(system);
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
         packet.reference_id = ReferenceId::KISS_DENY;
         packet.origin_timestamp = outgoing.transmit_timestamp;
         packet.mode = NtpAssociationMode::Server;
@@ -889,7 +1489,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             ),
@@ -907,7 +1507,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             )
@@ -919,7 +1519,7 @@ mod test {
         let old_remote_interval = peer.remote_min_poll_interval;
         let mut packet = NtpHeader::new();
         let system = SystemSnapshot::default();
-        let outgoing = peer.generate_poll_message(system);
+        let outgoing = peer.generate_poll_message(system, &SystemConfig::default());
         packet.reference_id = ReferenceId::KISS_RATE;
         packet.origin_timestamp = outgoing.transmit_timestamp;
         packet.mode = NtpAssociationMode::Server;
@@ -928,7 +1528,7 @@ mod test {
                 system,
                 packet,
                 base + Duration::from_secs(1),
-                FrequencyTolerance::ppm(15),
+                &SystemConfig::default(),
                 NtpTimestamp::from_fixed_int(0),
                 NtpTimestamp::from_fixed_int(100)
             )
@@ -936,4 +1536,47 @@ mod test {
         assert!(peer.remote_min_poll_interval > old_poll_interval);
         assert!(peer.remote_min_poll_interval >= old_remote_interval);
     }
+
+    #[test]
+    fn test_holdover() {
+        let reference_timestamp = NtpTimestamp::from_seconds_nanos_since_ntp_era(1_000_000, 0);
+        let mut system = SystemSnapshot {
+            poll_interval: PollInterval::from_log(4).unwrap(),
+            stratum: 2,
+            leap_indicator: NtpLeapIndicator::NoWarning,
+            root_dispersion: NtpDuration::from_seconds(0.01),
+            reference_timestamp,
+            sync_state: SyncState::Synchronized,
+            ..SystemSnapshot::default()
+        };
+        let max_holdover = NtpDuration::from_seconds(3600.0);
+        let at = |seconds| reference_timestamp + NtpDuration::from_seconds(seconds);
+
+        let dispersion = system.root_dispersion_at(at(1000.0), FrequencyTolerance::ppm(15));
+        assert!((dispersion.to_seconds() - 0.025).abs() < 1e-6);
+        // the clock went back a little after the update
+        let dispersion = system.root_dispersion_at(at(-1.0), FrequencyTolerance::ppm(15));
+        assert_eq!(dispersion, system.root_dispersion);
+
+        // no holdover within 8 poll intervals
+        assert!(!system.age_sync_state(at(100.0), max_holdover));
+        assert_eq!(system.sync_state, SyncState::Synchronized);
+
+        assert!(system.age_sync_state(at(200.0), max_holdover));
+        assert_eq!(system.sync_state, SyncState::Holdover);
+        assert!(system.is_synchronized());
+        assert!(!system.enter_holdover());
+
+        assert!(system.age_sync_state(at(3601.0), max_holdover));
+        assert_eq!(system.sync_state, SyncState::Unsynchronized);
+        assert_eq!(system.leap_indicator, NtpLeapIndicator::Unknown);
+        assert_eq!(system.stratum, 16);
+        assert!(!system.is_synchronized());
+        assert!(!system.age_sync_state(at(3700.0), max_holdover));
+
+        // failing clock selection goes straight into holdover
+        system.sync_state = SyncState::Synchronized;
+        assert!(system.enter_holdover());
+        assert_eq!(system.sync_state, SyncState::Holdover);
+    }
 }
@@ -336,13 +336,73 @@ impl Serialize for NtpDuration {
     }
 }
 
+/// Parse a number of seconds with an optional unit, like "1.5", "500ms" or "30m"
+pub(crate) fn parse_seconds(input: &str) -> Result<f64, String> {
+    let input = input.trim();
+    // the unit is the trailing run of letters, so an exponent like in "1e-3s" stays a number
+    let unit_start = input.trim_end_matches(|c: char| c.is_alphabetic()).len();
+    let (number, unit) = input.split_at(unit_start);
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration {input:?}"))?;
+
+    let scale = match unit {
+        "" | "s" => 1.0,
+        "ns" => 1e-9,
+        "us" | "µs" => 1e-6,
+        "ms" => 1e-3,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => {
+            return Err(format!(
+            "unknown unit {unit:?} in duration {input:?}, expected one of ns, us, ms, s, m, h or d"
+        ))
+        }
+    };
+
+    Ok(number * scale)
+}
+
+// Durations are configured either as a number of seconds, or as a string with a unit
 impl<'de> Deserialize<'de> for NtpDuration {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let seconds: f64 = Deserialize::deserialize(deserializer)?;
-        Ok(NtpDuration::from_seconds(seconds))
+        struct NtpDurationVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for NtpDurationVisitor {
+            type Value = NtpDuration;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a number of seconds or a duration like \"500ms\"")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<NtpDuration, E> {
+                if v.is_finite() {
+                    Ok(NtpDuration::from_seconds(v))
+                } else {
+                    Err(E::invalid_value(serde::de::Unexpected::Float(v), &self))
+                }
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<NtpDuration, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<NtpDuration, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<NtpDuration, E> {
+                self.visit_f64(parse_seconds(v).map_err(E::custom)?)
+            }
+        }
+
+        deserializer.deserialize_any(NtpDurationVisitor)
     }
 }
 
@@ -494,7 +554,8 @@ ntp_duration_scalar_div!(u32);
 //
 // - a value of 4 means 2^4 = 16 seconds
 // - a value of 17 is 2^17 = ~36h
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+// - a value of -2 is 2^-2 = 250ms
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct PollInterval(i8);
 
 impl std::fmt::Debug for PollInterval {
@@ -508,17 +569,33 @@ impl PollInterval {
     // with the exception that we have lowered the MAX value, which is needed because
     // we don't support bursting, and hence using a larger poll interval would not
     // yield usable results from a peer (gets rejected because of too high dispersion)
+    //
+    // These are the default limits, see `PollIntervalLimits` for configuring them.
     pub const MIN: Self = Self(4);
     pub const MAX: Self = Self(13);
 
+    /// Shortest poll interval that can be configured (1/64th of a second)
+    pub const SHORTEST: Self = Self(-6);
+    /// Longest poll interval that can be configured (~36 hours)
+    pub const LONGEST: Self = Self(17);
+
+    /// Construct a poll interval from its log2 representation, if it is in the supported range
+    pub fn from_log(log: i8) -> Option<Self> {
+        if (Self::SHORTEST.0..=Self::LONGEST.0).contains(&log) {
+            Some(Self(log))
+        } else {
+            None
+        }
+    }
+
     #[must_use]
-    pub fn inc(self) -> Self {
-        Self(self.0 + 1).min(Self::MAX)
+    pub fn inc(self, limits: PollIntervalLimits) -> Self {
+        Self(self.0 + 1).min(limits.max)
     }
 
     #[must_use]
-    pub fn dec(self) -> Self {
-        Self(self.0 - 1).max(Self::MIN)
+    pub fn dec(self, limits: PollIntervalLimits) -> Self {
+        Self(self.0 - 1).max(limits.min)
     }
 
     pub const fn as_log(self) -> i8 {
@@ -532,7 +609,11 @@ impl PollInterval {
     }
 
     pub const fn as_system_duration(self) -> Duration {
-        Duration::from_secs(1 << self.0)
+        if self.0 >= 0 {
+            Duration::from_secs(1 << self.0)
+        } else {
+            Duration::from_nanos(1_000_000_000 >> -self.0)
+        }
     }
 }
 
@@ -542,19 +623,155 @@ impl Default for PollInterval {
     }
 }
 
+impl<'de> Deserialize<'de> for PollInterval {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let log: i8 = Deserialize::deserialize(deserializer)?;
+        PollInterval::from_log(log).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "poll interval must be between {} and {}",
+                Self::SHORTEST.0,
+                Self::LONGEST.0
+            ))
+        })
+    }
+}
+
+/// The range in which a poll interval is allowed to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PollIntervalLimits {
+    pub min: PollInterval,
+    pub max: PollInterval,
+}
+
+impl Default for PollIntervalLimits {
+    fn default() -> Self {
+        Self {
+            min: PollInterval::MIN,
+            max: PollInterval::MAX,
+        }
+    }
+}
+
+// We have a custom deserializer for PollIntervalLimits because we
+// want to check that the limits are in the right order
+impl<'de> Deserialize<'de> for PollIntervalLimits {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct PollIntervalLimitsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PollIntervalLimitsVisitor {
+            type Value = PollIntervalLimits;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("map")
+            }
+
+            fn visit_map<M: serde::de::MapAccess<'de>>(
+                self,
+                mut map: M,
+            ) -> Result<PollIntervalLimits, M::Error> {
+                use serde::de::Error;
+
+                let mut min = None;
+                let mut max = None;
+
+                while let Some(key) = map.next_key::<&str>()? {
+                    match key {
+                        "min" => {
+                            if min.is_some() {
+                                return Err(M::Error::duplicate_field("min"));
+                            }
+                            min = Some(map.next_value()?);
+                        }
+                        "max" => {
+                            if max.is_some() {
+                                return Err(M::Error::duplicate_field("max"));
+                            }
+                            max = Some(map.next_value()?);
+                        }
+                        _ => {
+                            return Err(M::Error::unknown_field(key, &["min", "max"]));
+                        }
+                    }
+                }
+
+                let default = PollIntervalLimits::default();
+                let limits = PollIntervalLimits {
+                    min: min.unwrap_or(default.min),
+                    max: max.unwrap_or(default.max),
+                };
+
+                if limits.min > limits.max {
+                    return Err(M::Error::custom(
+                        "minimum poll interval is larger than the maximum",
+                    ));
+                }
+
+                Ok(limits)
+            }
+        }
+
+        deserializer.deserialize_map(PollIntervalLimitsVisitor)
+    }
+}
+
 /// Frequency tolerance PHI (unit: seconds per second)
 #[derive(Debug, Clone, Copy)]
 pub struct FrequencyTolerance {
     ppm: u32,
 }
 
+// Either a plain number of ppm, or a string with the unit, like "15ppm"
 impl<'de> Deserialize<'de> for FrequencyTolerance {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let val: u32 = Deserialize::deserialize(deserializer)?;
-        Ok(FrequencyTolerance { ppm: val })
+        struct FrequencyToleranceVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FrequencyToleranceVisitor {
+            type Value = FrequencyTolerance;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a whole number of ppm, like 15 or \"15ppm\"")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<FrequencyTolerance, E> {
+                let ppm = u32::try_from(v)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(v), &self))?;
+                Ok(FrequencyTolerance { ppm })
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<FrequencyTolerance, E> {
+                let v = u64::try_from(v)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))?;
+                self.visit_u64(v)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<FrequencyTolerance, E> {
+                let number = v.trim().strip_suffix("ppm").unwrap_or(v).trim();
+                let ppm = number
+                    .parse()
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self))?;
+                Ok(FrequencyTolerance { ppm })
+            }
+        }
+
+        deserializer.deserialize_any(FrequencyToleranceVisitor)
+    }
+}
+
+impl Serialize for FrequencyTolerance {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.ppm.serialize(serializer)
     }
 }
 
@@ -562,6 +779,10 @@ impl FrequencyTolerance {
     pub const fn ppm(ppm: u32) -> Self {
         Self { ppm }
     }
+
+    pub const fn to_ppm(self) -> u32 {
+        self.ppm
+    }
 }
 
 impl Mul<FrequencyTolerance> for NtpDuration {
@@ -751,20 +972,51 @@ mod tests {
     #[test]
     fn poll_interval_clamps() {
         let mut interval = PollInterval::default();
+        let limits = PollIntervalLimits::default();
         for _ in 0..100 {
-            interval = interval.inc();
+            interval = interval.inc(limits);
             assert!(interval <= PollInterval::MAX);
         }
         for _ in 0..100 {
-            interval = interval.dec();
+            interval = interval.dec(limits);
             assert!(interval >= PollInterval::MIN);
         }
         for _ in 0..100 {
-            interval = interval.inc();
+            interval = interval.inc(limits);
             assert!(interval <= PollInterval::MAX);
         }
     }
 
+    #[test]
+    fn poll_interval_custom_limits() {
+        let limits = PollIntervalLimits {
+            min: PollInterval(-2),
+            max: PollInterval(1),
+        };
+
+        let mut interval = PollInterval::default();
+        for _ in 0..100 {
+            interval = interval.dec(limits);
+            assert!(interval >= limits.min);
+        }
+        assert_eq!(interval, limits.min);
+        assert_eq!(interval.as_system_duration(), Duration::from_millis(250));
+
+        for _ in 0..100 {
+            interval = interval.inc(limits);
+            assert!(interval <= limits.max);
+        }
+        assert_eq!(interval, limits.max);
+    }
+
+    #[test]
+    fn poll_interval_from_log() {
+        assert_eq!(PollInterval::from_log(6), Some(PollInterval(6)));
+        assert_eq!(PollInterval::from_log(-6), Some(PollInterval::SHORTEST));
+        assert_eq!(PollInterval::from_log(-7), None);
+        assert_eq!(PollInterval::from_log(18), None);
+    }
+
     #[test]
     fn poll_interval_to_duration() {
         assert_eq!(
@@ -782,7 +1034,7 @@ mod tests {
                 interval.as_duration().as_seconds_nanos().0,
                 interval.as_system_duration().as_secs() as i32
             );
-            interval = interval.inc();
+            interval = interval.inc(PollIntervalLimits::default());
         }
 
         for _ in 0..100 {
@@ -790,8 +1042,12 @@ mod tests {
                 interval.as_duration().as_seconds_nanos().0,
                 interval.as_system_duration().as_secs() as i32
             );
-            interval = interval.dec();
+            interval = interval.dec(PollIntervalLimits::default());
         }
+
+        let interval = PollInterval(-3);
+        assert_eq!(interval.as_duration(), NtpDuration::from_fixed_int(1 << 29));
+        assert_eq!(interval.as_system_duration(), Duration::from_millis(125));
     }
 
     #[test]
@@ -801,4 +1057,42 @@ mod tests {
             NtpDuration::from_seconds(1.0) * FrequencyTolerance::ppm(1_000_000),
         );
     }
+
+    #[test]
+    fn duration_with_unit() {
+        use serde::de::value::{Error, F64Deserializer, StrDeserializer};
+
+        let parse = |v: &str| NtpDuration::deserialize(StrDeserializer::<Error>::new(v));
+
+        assert_eq!(parse("2").unwrap(), NtpDuration::from_seconds(2.0));
+        assert_eq!(parse("1.5s").unwrap(), NtpDuration::from_seconds(1.5));
+        assert_eq!(parse("500ms").unwrap(), NtpDuration::from_seconds(0.5));
+        assert_eq!(parse("250 us").unwrap(), NtpDuration::from_seconds(250e-6));
+        assert_eq!(parse("30m").unwrap(), NtpDuration::from_seconds(1800.0));
+        assert_eq!(parse("2h").unwrap(), NtpDuration::from_seconds(7200.0));
+        assert_eq!(parse("1e-3s").unwrap(), NtpDuration::from_seconds(1e-3));
+        assert_eq!(parse("-1ms").unwrap(), NtpDuration::from_seconds(-1e-3));
+        assert!(parse("5 parsecs").is_err());
+        assert!(parse("ms").is_err());
+        assert!(parse("inf").is_err());
+
+        let number = |v: f64| NtpDuration::deserialize(F64Deserializer::<Error>::new(v));
+        assert_eq!(number(900.0).unwrap(), NtpDuration::from_seconds(900.0));
+        assert!(number(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn frequency_tolerance_with_unit() {
+        use serde::de::value::{Error, I64Deserializer, StrDeserializer};
+
+        let parse = |v: &str| FrequencyTolerance::deserialize(StrDeserializer::<Error>::new(v));
+        assert_eq!(parse("15ppm").unwrap().to_ppm(), 15);
+        assert_eq!(parse("20 ppm").unwrap().to_ppm(), 20);
+        assert!(parse("1.5ppm").is_err());
+        assert!(parse("15ppb").is_err());
+
+        let number = |v: i64| FrequencyTolerance::deserialize(I64Deserializer::<Error>::new(v));
+        assert_eq!(number(15).unwrap().to_ppm(), 15);
+        assert!(number(-1).is_err());
+    }
 }
@@ -0,0 +1,160 @@
+//! Receiving and sending many messages with a single system call, for busy servers
+
+use std::{io, net::SocketAddr, os::unix::prelude::AsRawFd};
+
+use ntp_proto::NtpTimestamp;
+use tracing::warn;
+
+use crate::{
+    cerr, control_message_space, control_messages, interface_name::sockaddr_storage_to_socket_addr,
+    socket::read_ntp_timestamp, zeroed_sockaddr_storage, ControlMessage,
+};
+
+/// Largest number of messages that is received or sent with a single system call
+pub const MAX_BATCH: usize = 32;
+
+const CONTROL_SIZE: usize = control_message_space::<[libc::timespec; 3]>();
+
+/// A message received by `UdpSocket::recv_batch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// Number of bytes received, at most the size of the buffer
+    pub len: usize,
+    pub addr: SocketAddr,
+    /// The kernel receive timestamp
+    pub timestamp: Option<NtpTimestamp>,
+}
+
+fn zeroed_mmsghdr() -> libc::mmsghdr {
+    // Safety: a zeroed mmsghdr is valid, it contains null pointers and zero lengths
+    unsafe { std::mem::MaybeUninit::zeroed().assume_init() }
+}
+
+/// Receive up to `MAX_BATCH` messages into `bufs`, one message per buffer (truncated to the size
+/// of the buffer). Returns an error `WouldBlock` when no message is available.
+pub(crate) fn receive_messages<B: AsMut<[u8]>>(
+    socket: &std::net::UdpSocket,
+    bufs: &mut [B],
+    meta: &mut Vec<RecvMeta>,
+) -> io::Result<usize> {
+    let count = bufs.len().min(MAX_BATCH);
+
+    let mut iovecs = [libc::iovec {
+        iov_base: std::ptr::null_mut(),
+        iov_len: 0,
+    }; MAX_BATCH];
+    let mut addrs = [zeroed_sockaddr_storage(); MAX_BATCH];
+    let mut control = [[0u8; CONTROL_SIZE]; MAX_BATCH];
+    let mut headers = [zeroed_mmsghdr(); MAX_BATCH];
+
+    for i in 0..count {
+        let buf = bufs[i].as_mut();
+        iovecs[i] = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast::<libc::c_void>(),
+            iov_len: buf.len(),
+        };
+
+        let header = &mut headers[i].msg_hdr;
+        header.msg_name = (&mut addrs[i] as *mut libc::sockaddr_storage).cast::<libc::c_void>();
+        header.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as u32;
+        header.msg_iov = &mut iovecs[i];
+        header.msg_iovlen = 1;
+        header.msg_control = control[i].as_mut_ptr().cast::<libc::c_void>();
+        header.msg_controllen = CONTROL_SIZE as _;
+    }
+
+    let received = loop {
+        // Safety: the headers point to buffers that live until the end of this function, and
+        // `count` is at most the number of headers
+        let result = cerr(unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                count as _,
+                0,
+                std::ptr::null_mut(),
+            )
+        });
+
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            other => break other? as usize,
+        }
+    };
+
+    meta.clear();
+    for (header, addr) in headers.iter().zip(addrs.iter()).take(received) {
+        let addr = sockaddr_storage_to_socket_addr(addr)
+            .unwrap_or_else(|| unreachable!("We never constructed a non-ip socket"));
+
+        if header.msg_hdr.msg_flags & libc::MSG_CTRUNC > 0 {
+            warn!("truncated control messages");
+        }
+
+        let mut timestamp = None;
+        for msg in control_messages(&header.msg_hdr) {
+            match msg {
+                ControlMessage::Timestamping(timespec) => {
+                    timestamp = Some(read_ntp_timestamp(timespec));
+                }
+                ControlMessage::ReceiveError(_) | ControlMessage::Other(_) => {
+                    warn!("unexpected control message");
+                }
+            }
+        }
+
+        meta.push(RecvMeta {
+            len: header.msg_len as usize,
+            addr,
+            timestamp,
+        });
+    }
+
+    Ok(received)
+}
+
+/// Send up to `MAX_BATCH` messages, returns how many were sent
+pub(crate) fn send_messages(
+    socket: &std::net::UdpSocket,
+    messages: &[(&[u8], SocketAddr)],
+) -> io::Result<usize> {
+    let count = messages.len().min(MAX_BATCH);
+
+    let addrs: Vec<socket2::SockAddr> = messages[..count]
+        .iter()
+        .map(|(_, addr)| socket2::SockAddr::from(*addr))
+        .collect();
+    let mut iovecs = [libc::iovec {
+        iov_base: std::ptr::null_mut(),
+        iov_len: 0,
+    }; MAX_BATCH];
+    let mut headers = [zeroed_mmsghdr(); MAX_BATCH];
+
+    for i in 0..count {
+        let data = messages[i].0;
+        // the kernel does not write to the data of a message it sends
+        iovecs[i] = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+
+        let header = &mut headers[i].msg_hdr;
+        header.msg_name = addrs[i].as_ptr() as *mut libc::c_void;
+        header.msg_namelen = addrs[i].len();
+        header.msg_iov = &mut iovecs[i];
+        header.msg_iovlen = 1;
+    }
+
+    loop {
+        // Safety: the headers point to data that lives until the end of this function, and
+        // `count` is at most the number of headers
+        let result = cerr(unsafe {
+            libc::sendmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), count as _, 0)
+        });
+
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            other => return other.map(|sent| sent as usize),
+        }
+    }
+}
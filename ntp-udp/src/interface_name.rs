@@ -32,6 +32,20 @@ pub fn interface_name(local_addr: SocketAddr) -> std::io::Result<Option<[u8; 16]
     }
 }
 
+/// The addresses of the interface with the given name, sorted and with port 0. Empty when there
+/// is no such interface.
+pub fn interface_addresses(name: &str) -> std::io::Result<Vec<SocketAddr>> {
+    let mut addresses: Vec<_> = getifaddrs()?
+        .filter(|interface| interface.interface_name == name)
+        .filter_map(|interface| interface.address)
+        .collect();
+
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    Ok(addresses)
+}
+
 /// Describes a single address for an interface as returned by `getifaddrs`.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 struct InterfaceAddress {
@@ -87,7 +101,9 @@ pub unsafe fn sockaddr_to_socket_addr(sockaddr: *const libc::sockaddr) -> Option
     // of the underlying value regardless of platform.
     match (*sockaddr).sa_family as libc::c_int {
         libc::AF_INET => {
-            let inaddr: libc::sockaddr_in = *(sockaddr as *const libc::sockaddr_in);
+            // a `sockaddr` is less strictly aligned than a `sockaddr_in`
+            let inaddr: libc::sockaddr_in =
+                std::ptr::read_unaligned(sockaddr as *const libc::sockaddr_in);
 
             let socketaddr = std::net::SocketAddrV4::new(
                 std::net::Ipv4Addr::from(inaddr.sin_addr.s_addr.to_ne_bytes()),
@@ -97,7 +113,8 @@ pub unsafe fn sockaddr_to_socket_addr(sockaddr: *const libc::sockaddr) -> Option
             Some(std::net::SocketAddr::V4(socketaddr))
         }
         libc::AF_INET6 => {
-            let inaddr: libc::sockaddr_in6 = *(sockaddr as *const libc::sockaddr_in6);
+            let inaddr: libc::sockaddr_in6 =
+                std::ptr::read_unaligned(sockaddr as *const libc::sockaddr_in6);
 
             let sin_addr = inaddr.sin6_addr.s6_addr;
             let segment_bytes: [u8; 16] =
@@ -181,6 +198,14 @@ mod tests {
         assert!(name.is_some());
     }
 
+    #[test]
+    fn find_interface_addresses() {
+        let addresses = interface_addresses("lo").unwrap();
+        assert!(addresses.contains(&"127.0.0.1:0".parse().unwrap()));
+
+        assert!(interface_addresses("ntp-test-none").unwrap().is_empty());
+    }
+
     #[test]
     fn decode_socket_addr_v4() {
         let sockaddr = libc::sockaddr {
@@ -0,0 +1,81 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::interface_name::interface_addresses;
+
+/// Follows the addresses of a network interface, by checking them with `getifaddrs` at a
+/// fixed interval
+#[derive(Debug)]
+pub struct InterfaceWatcher {
+    name: String,
+    interval: Interval,
+    addresses: Vec<SocketAddr>,
+    checked: bool,
+}
+
+impl InterfaceWatcher {
+    /// Watch the interface with the given name. The addresses are first read on the first call
+    /// to [`InterfaceWatcher::changed`], which returns after that first check, also when the
+    /// interface has no addresses.
+    pub fn new(name: &str, poll_interval: Duration) -> Self {
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        InterfaceWatcher {
+            name: name.to_owned(),
+            interval,
+            addresses: vec![],
+            checked: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The addresses of the interface at the most recent check, sorted and with port 0. Link
+    /// local IPv6 addresses carry the scope id of the interface.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Wait until the addresses of the interface are different from [`InterfaceWatcher::addresses`].
+    /// When they can not be read, the error is returned and the addresses are left as they are.
+    pub async fn changed(&mut self) -> std::io::Result<()> {
+        loop {
+            self.interval.tick().await;
+
+            let addresses = interface_addresses(&self.name)?;
+            if addresses != self.addresses || !self.checked {
+                self.addresses = addresses;
+                self.checked = true;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn watch_loopback() {
+        let mut watcher = InterfaceWatcher::new("lo", Duration::from_millis(10));
+        assert!(watcher.addresses().is_empty());
+
+        watcher.changed().await.unwrap();
+        assert!(watcher
+            .addresses()
+            .contains(&"127.0.0.1:0".parse().unwrap()));
+
+        let mut missing = InterfaceWatcher::new("ntp-test-none", Duration::from_millis(10));
+        missing.changed().await.unwrap();
+        assert!(missing.addresses().is_empty());
+
+        // the addresses of the loopback interface do not change
+        let result = tokio::time::timeout(Duration::from_millis(50), watcher.changed()).await;
+        assert!(result.is_err());
+    }
+}
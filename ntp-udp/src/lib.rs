@@ -1,6 +1,10 @@
+mod batch;
 mod interface_name;
+mod interface_watcher;
 mod socket;
 
+pub use batch::{RecvMeta, MAX_BATCH};
+pub use interface_watcher::InterfaceWatcher;
 pub use socket::UdpSocket;
 use std::os::unix::prelude::{AsRawFd, RawFd};
 
@@ -54,6 +58,21 @@ pub(crate) fn cerr(t: libc::c_int) -> std::io::Result<libc::c_int> {
     }
 }
 
+/// Resolution of the software timestamps that the kernel puts on received packets. These are
+/// read from the realtime clock, so they are as fine grained as that clock.
+pub fn timestamp_resolution() -> std::io::Result<ntp_proto::NtpDuration> {
+    let mut res = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // Safety: `res` is a valid timespec to write the resolution into
+    cerr(unsafe { libc::clock_getres(libc::CLOCK_REALTIME, &mut res) })?;
+
+    let resolution = std::time::Duration::new(res.tv_sec as u64, res.tv_nsec as u32);
+    Ok(ntp_proto::NtpDuration::from_system_duration(resolution))
+}
+
 /// Receive a message on a socket (retry if interrupted)
 fn receive_message(
     socket: &std::net::UdpSocket,
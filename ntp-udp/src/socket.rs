@@ -12,9 +12,11 @@ use tokio::io::unix::AsyncFd;
 use tracing::{debug, instrument, trace, warn};
 
 use crate::{
+    batch::{receive_messages, send_messages, RecvMeta},
     control_message_space, control_messages, exceptional_condition_fd,
-    interface_name::sockaddr_storage_to_socket_addr, receive_message, set_timestamping_options,
-    zeroed_sockaddr_storage, ControlMessage, TimestampingConfig,
+    interface_name::sockaddr_storage_to_socket_addr,
+    receive_message, set_timestamping_options, zeroed_sockaddr_storage, ControlMessage,
+    TimestampingConfig,
 };
 
 enum Timestamping {
@@ -90,8 +92,32 @@ impl UdpSocket {
             "server socket bound"
         );
 
-        let socket = socket.into_std()?;
+        Self::server_from_std(socket.into_std()?)
+    }
 
+    /// A server socket with `SO_REUSEPORT`, so that several sockets can listen on the same
+    /// address. The kernel spreads the incoming packets over these sockets by their source.
+    #[instrument(level = "debug")]
+    pub async fn server_reuse_port(listen_addr: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(listen_addr),
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&listen_addr.into())?;
+
+        let socket: std::net::UdpSocket = socket.into();
+        debug!(
+            local_addr = debug(socket.local_addr().unwrap()),
+            "server socket bound"
+        );
+
+        Self::server_from_std(socket)
+    }
+
+    fn server_from_std(socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
         // our supported kernel versions always have receive timestamping. Send timestamping for a
         // server connection is not relevant, so we don't even bother with checking if it is supported
         let timestamping = TimestampingConfig {
@@ -214,6 +240,70 @@ impl UdpSocket {
         }
     }
 
+    /// Receive up to `MAX_BATCH` messages, one per buffer in `bufs`. Waits until at least one
+    /// message is available. The sender and receive timestamp of every message are put in `meta`.
+    #[instrument(level = "trace", skip(self, bufs, meta), fields(
+        local_addr = debug(self.as_ref().local_addr().unwrap()),
+        bufs = bufs.len(),
+    ))]
+    pub async fn recv_batch<B: AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+        meta: &mut Vec<RecvMeta>,
+    ) -> io::Result<usize> {
+        loop {
+            trace!("waiting for socket to become readable");
+            let mut guard = self.io.readable().await?;
+            let result = match guard.try_io(|inner| receive_messages(inner.get_ref(), bufs, meta)) {
+                Err(_would_block) => {
+                    trace!("blocked after becoming readable, retrying");
+                    continue;
+                }
+                Ok(result) => result,
+            };
+            match &result {
+                Ok(count) => trace!(count, "received messages"),
+                Err(e) => debug!(error = debug(e), "error receiving data"),
+            }
+            return result;
+        }
+    }
+
+    /// Send all `messages`, using as few system calls as possible. A message that cannot be sent
+    /// (e.g. because its destination is unreachable) is logged and skipped, so that it does not
+    /// hold up the others.
+    #[instrument(level = "trace", skip(self, messages), fields(
+        local_addr = debug(self.as_ref().local_addr().unwrap()),
+        messages = messages.len(),
+    ))]
+    pub async fn send_batch(&self, messages: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+        let mut sent = 0;
+        while sent < messages.len() {
+            let mut guard = self.io.writable().await?;
+            match guard.try_io(|inner| send_messages(inner.get_ref(), &messages[sent..])) {
+                Ok(Ok(count)) => {
+                    trace!(count, "sent messages");
+                    sent += count;
+                }
+                Ok(Err(e)) => {
+                    // the system call stops at the first message it cannot send
+                    warn!(
+                        error = debug(&e),
+                        addr = debug(messages[sent].1),
+                        "error sending data, skipping message"
+                    );
+                    sent += 1;
+                }
+                Err(_would_block) => {
+                    trace!("blocked after becoming writable, retrying");
+                    continue;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     #[instrument(level = "trace", skip(self, buf), fields(
         local_addr = debug(self.as_ref().local_addr().unwrap()),
         peer_addr = debug(self.as_ref().peer_addr().ok()),
@@ -387,7 +477,7 @@ fn fetch_send_timestamp_help(
     Ok(send_ts)
 }
 
-fn read_ntp_timestamp(timespec: libc::timespec) -> NtpTimestamp {
+pub(crate) fn read_ntp_timestamp(timespec: libc::timespec) -> NtpTimestamp {
     // Unix uses an epoch located at 1/1/1970-00:00h (UTC) and NTP uses 1/1/1900-00:00h.
     // This leads to an offset equivalent to 70 years in seconds
     // there are 17 leap years between the two dates so the offset is
@@ -408,6 +498,59 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_batch() {
+        let server = UdpSocket::server_reuse_port("127.0.0.1:10004".parse().unwrap())
+            .await
+            .unwrap();
+        // a second socket can listen on the same address
+        let other = UdpSocket::server_reuse_port("127.0.0.1:10004".parse().unwrap())
+            .await
+            .unwrap();
+        drop(other);
+        let mut client = UdpSocket::client(
+            "127.0.0.1:10005".parse().unwrap(),
+            "127.0.0.1:10004".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+        for i in 0..3 {
+            client.send(&[i; 48]).await.unwrap();
+        }
+
+        let mut bufs = [[0u8; 48]; 4];
+        let mut meta = vec![];
+        let mut received = 0;
+        while received < 3 {
+            let count = server
+                .recv_batch(&mut bufs[received..], &mut meta)
+                .await
+                .unwrap();
+            for message in &meta {
+                assert_eq!(message.len, 48);
+                assert_eq!(message.addr, "127.0.0.1:10005".parse().unwrap());
+                // no check on the timestamp: the kernel turns on receive timestamps with a
+                // delay, so the first packets after startup may lack one
+            }
+            received += count;
+        }
+        assert_eq!(bufs[..3], [[0; 48], [1; 48], [2; 48]]);
+
+        let addr = "127.0.0.1:10005".parse().unwrap();
+        let mut replies: Vec<_> = bufs[..3].iter().map(|buf| (&buf[..], addr)).collect();
+        // an ipv4 socket cannot send to an ipv6 address, the other replies must still be sent
+        replies.insert(1, (&bufs[3][..], "[::1]:10005".parse().unwrap()));
+        server.send_batch(&replies).await.unwrap();
+
+        let mut buf = [0; 48];
+        for i in 0..3 {
+            let (size, _, _) = client.recv(&mut buf).await.unwrap();
+            assert_eq!(size, 48);
+            assert_eq!(buf, [i; 48]);
+        }
+    }
+
     #[tokio::test]
     async fn test_client_basic_ipv4() {
         let mut a = UdpSocket::client(
@@ -0,0 +1,106 @@
+// sends client requests to a server as fast as it answers them, to measure its throughput
+
+use clap::Parser;
+use ntp_proto::{NtpAssociationMode, NtpHeader};
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+
+#[derive(Parser)]
+#[command(about = "Generate NTP client requests over loopback")]
+struct Args {
+    /// Address of the server under test
+    #[arg(default_value = "127.0.0.1:123")]
+    server: SocketAddr,
+
+    /// Number of clients, each with their own socket and at most one outstanding request
+    #[arg(short, long, default_value_t = 64)]
+    clients: usize,
+
+    /// How long to generate requests, in seconds
+    #[arg(short, long, default_value_t = 10)]
+    duration: u64,
+
+    /// How long a client waits for a response before sending the next request, in milliseconds
+    #[arg(short, long, default_value_t = 100)]
+    timeout: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    responses: AtomicU64,
+}
+
+async fn client(
+    server: SocketAddr,
+    timeout: Duration,
+    until: Instant,
+    counters: Arc<Counters>,
+) -> std::io::Result<()> {
+    let sock = UdpSocket::bind("127.0.0.1:0").await?;
+    sock.connect(server).await?;
+
+    let packet = NtpHeader {
+        mode: NtpAssociationMode::Client,
+        ..NtpHeader::new()
+    }
+    .serialize();
+
+    let mut buf = [0; 48];
+    while Instant::now() < until {
+        sock.send(&packet).await?;
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+
+        if let Ok(result) = tokio::time::timeout(timeout, sock.recv(&mut buf)).await {
+            result?;
+            counters.responses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let counters = Arc::new(Counters::default());
+    let duration = Duration::from_secs(args.duration);
+    let start = Instant::now();
+
+    let tasks: Vec<_> = (0..args.clients)
+        .map(|_| {
+            tokio::spawn(client(
+                args.server,
+                Duration::from_millis(args.timeout),
+                start + duration,
+                counters.clone(),
+            ))
+        })
+        .collect();
+
+    for task in tasks {
+        task.await??;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let requests = counters.requests.load(Ordering::Relaxed);
+    let responses = counters.responses.load(Ordering::Relaxed);
+    println!("{} requests ({:.0}/s)", requests, requests as f64 / elapsed);
+    println!(
+        "{} responses ({:.0}/s)",
+        responses,
+        responses as f64 / elapsed
+    );
+    println!("{} requests unanswered", requests - responses);
+
+    Ok(())
+}